@@ -0,0 +1,1584 @@
+//! Analog to Digital Converters (ADC)
+//!
+//! The F3 has four 12-bit ADCs grouped in pairs (ADC1 / ADC2 and ADC3 /
+//! ADC4) that share a common control register. The ADCs are clocked
+//! synchronously from the AHB clock (`ahb::FREQUENCY`).
+//!
+//! NOTE the analog inputs must be configured in analog mode (`MODER = 0b11`)
+//! by the application; see the datasheet for the channel to pin mapping.
+//!
+//! # Internal channels
+//!
+//! - Temperature sensor: ADC1, channel 16 (`VTS`)
+//! - VBAT / 2: ADC1, channel 17 (`VBAT`)
+//! - VREFINT: all ADCs, channel 18 (`VREFINT`)
+//!
+//! Enable them with `Adc::set_internal` and sample them with a sample time
+//! of at least 2.2 us (`SampleTime::Cycles19_5` at 8 MHz).
+//!
+//! # Sequences
+//!
+//! A regular sequence of up to 16 channels can be streamed into memory
+//! using the DMA. The sequence can be converted back to back or on every
+//! event of a timer; use a timer TRGO to sample at an exact rate:
+//!
+//! ``` ignore
+//! // 10 KHz sample rate
+//! Timer(&tim6).init(10_000.hz().invert(), rcc).unwrap();
+//! Timer(&tim6).set_trigger_output(TriggerOutput::Update);
+//!
+//! let channels = Dma1Channels::take().unwrap();
+//!
+//! adc.set_sequence(&[Channel(1), Channel(2)]).unwrap();
+//! let trigger = Some((Trigger::Tim6Trgo, Edge::Rising));
+//! adc.read_sequence_circ(dma1, &channels.ch1, rcc, BUFFER.borrow(), trigger)
+//!     .unwrap();
+//!
+//! Timer(&tim6).resume();
+//! ```
+//!
+//! | ADC  | DMA channel    |
+//! |------|----------------|
+//! | ADC1 | DMA1 channel 1 |
+//! | ADC2 | DMA2 channel 1 |
+//! | ADC3 | DMA2 channel 5 |
+//! | ADC4 | DMA2 channel 2 |
+//!
+//! # Dual mode
+//!
+//! ADC1 / ADC2 and ADC3 / ADC4 can work together as master and slave (see
+//! `DualAdc`), for example to sample two signals at the same time or to
+//! double the sample rate of a single signal.
+
+use core::marker::Unsize;
+use core::ops::Deref;
+use core::ptr;
+
+use cortex_m::asm;
+use hal;
+use nb;
+use static_ref::Ref;
+use stm32f30x::{Adc1, Adc12, Adc2, Adc3, Adc34, Adc4, Dma1, Dma2, Rcc,
+                adc1, adc1_2};
+
+use dma::{self, Buffer, CircBuffer, DMA, Direction, Dma1Channel1,
+          Dma2Channel1, Dma2Channel2, Dma2Channel5, DmaChannel, Priority,
+          Size};
+
+// CR bits
+const ADEN: u32 = 1 << 0;
+const ADDIS: u32 = 1 << 1;
+const ADSTART: u32 = 1 << 2;
+const JADSTART: u32 = 1 << 3;
+const ADSTP: u32 = 1 << 4;
+const JADSTP: u32 = 1 << 5;
+const ADCALDIF: u32 = 1 << 30;
+const ADCAL: u32 = 1 << 31;
+
+// ISR bits
+const ADRDY: u32 = 1 << 0;
+const EOC: u32 = 1 << 2;
+const EOS: u32 = 1 << 3;
+const OVR: u32 = 1 << 4;
+const JEOC: u32 = 1 << 5;
+const JEOS: u32 = 1 << 6;
+const AWD1: u32 = 1 << 7;
+
+// CFGR bits
+const DMAEN: u32 = 1 << 0;
+const DMACFG: u32 = 1 << 1;
+const RES: u32 = 0b11 << 3;
+const ALIGN: u32 = 1 << 5;
+const EXTSEL: u32 = 0b1111 << 6;
+const EXTEN: u32 = 0b11 << 10;
+const CONT: u32 = 1 << 13;
+const AWD1SGL: u32 = 1 << 22;
+const AWD1EN: u32 = 1 << 23;
+const JAWD1EN: u32 = 1 << 24;
+const AWD1CH: u32 = 0b11111 << 26;
+
+// CCR bits
+const DUAL: u32 = 0b11111 << 0;
+const DELAY: u32 = 0b1111 << 8;
+const CCR_DMACFG: u32 = 1 << 13;
+const MDMA: u32 = 0b11 << 14;
+const CKMODE: u32 = 0b11 << 16;
+const VREFEN: u32 = 1 << 22;
+const TSEN: u32 = 1 << 23;
+const VBATEN: u32 = 1 << 24;
+
+/// Temperature sensor raw data acquired at 30 C, VDDA = 3.3 V
+const TS_CAL1: *const u16 = 0x1fff_f7b8 as *const u16;
+/// Internal reference voltage raw data acquired at 30 C, VDDA = 3.3 V
+const VREFINT_CAL: *const u16 = 0x1fff_f7ba as *const u16;
+/// Temperature sensor raw data acquired at 110 C, VDDA = 3.3 V
+const TS_CAL2: *const u16 = 0x1fff_f7c2 as *const u16;
+
+/// VDDA at which the factory calibration values were acquired, in mV
+const VDDA_CAL: u32 = 3_300;
+
+/// ADC error
+#[derive(Debug)]
+pub enum Error {
+    /// A conversion result was overwritten before it was read
+    Overrun,
+    /// The trigger is not available on this ADC
+    Trigger,
+    /// The sequence is empty or too long, or the master and slave sequences
+    /// don't have the same length
+    Sequence,
+    /// DMA error
+    Dma(dma::Error),
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// ADC input channel (1 - 18)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Channel(pub u8);
+
+/// Temperature sensor channel (ADC1 only)
+pub const VTS: Channel = Channel(16);
+
+/// VBAT / 2 channel (ADC1 only)
+pub const VBAT: Channel = Channel(17);
+
+/// Internal reference voltage channel
+pub const VREFINT: Channel = Channel(18);
+
+/// Maximum length of a regular sequence
+pub const SEQUENCE_MAX: usize = 16;
+
+/// Maximum length of an injected sequence
+pub const INJECTED_MAX: usize = 4;
+
+/// Internal channel
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Internal {
+    /// Temperature sensor
+    Temperature,
+    /// VBAT / 2
+    Vbat,
+    /// Internal reference voltage
+    Vrefint,
+}
+
+/// Sampling time, in ADC clock cycles
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SampleTime {
+    /// 1.5 cycles
+    Cycles1_5,
+    /// 2.5 cycles
+    Cycles2_5,
+    /// 4.5 cycles
+    Cycles4_5,
+    /// 7.5 cycles
+    Cycles7_5,
+    /// 19.5 cycles
+    Cycles19_5,
+    /// 61.5 cycles
+    Cycles61_5,
+    /// 181.5 cycles
+    Cycles181_5,
+    /// 601.5 cycles
+    Cycles601_5,
+}
+
+impl SampleTime {
+    fn bits(&self) -> u32 {
+        match *self {
+            SampleTime::Cycles1_5 => 0b000,
+            SampleTime::Cycles2_5 => 0b001,
+            SampleTime::Cycles4_5 => 0b010,
+            SampleTime::Cycles7_5 => 0b011,
+            SampleTime::Cycles19_5 => 0b100,
+            SampleTime::Cycles61_5 => 0b101,
+            SampleTime::Cycles181_5 => 0b110,
+            SampleTime::Cycles601_5 => 0b111,
+        }
+    }
+}
+
+/// Conversion resolution
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Resolution {
+    /// 12-bit
+    Twelve,
+    /// 10-bit
+    Ten,
+    /// 8-bit
+    Eight,
+    /// 6-bit
+    Six,
+}
+
+impl Resolution {
+    /// Returns the largest value a conversion can produce
+    pub fn max_sample(&self) -> u16 {
+        match *self {
+            Resolution::Twelve => (1 << 12) - 1,
+            Resolution::Ten => (1 << 10) - 1,
+            Resolution::Eight => (1 << 8) - 1,
+            Resolution::Six => (1 << 6) - 1,
+        }
+    }
+}
+
+/// Active edge of an external trigger
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Edge {
+    /// Rising edge
+    Rising,
+    /// Falling edge
+    Falling,
+    /// Both edges
+    Both,
+}
+
+impl Edge {
+    fn exten(&self) -> u32 {
+        match *self {
+            Edge::Rising => 0b01,
+            Edge::Falling => 0b10,
+            Edge::Both => 0b11,
+        }
+    }
+}
+
+/// External trigger of the regular sequence
+///
+/// NOTE not all the triggers are available on all the ADCs; see
+/// `ADC::extsel`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Trigger {
+    /// TIM1 capture / compare 1 (ADC1 / ADC2)
+    Tim1Cc1,
+    /// TIM1 capture / compare 2 (ADC1 / ADC2)
+    Tim1Cc2,
+    /// TIM1 capture / compare 3
+    Tim1Cc3,
+    /// TIM1 TRGO
+    Tim1Trgo,
+    /// TIM1 TRGO2
+    Tim1Trgo2,
+    /// TIM2 capture / compare 1 (ADC3 / ADC4)
+    Tim2Cc1,
+    /// TIM2 capture / compare 2 (ADC1 / ADC2)
+    Tim2Cc2,
+    /// TIM2 capture / compare 3 (ADC3 / ADC4)
+    Tim2Cc3,
+    /// TIM2 TRGO
+    Tim2Trgo,
+    /// TIM3 capture / compare 1 (ADC3 / ADC4)
+    Tim3Cc1,
+    /// TIM3 capture / compare 4 (ADC1 / ADC2)
+    Tim3Cc4,
+    /// TIM3 TRGO
+    Tim3Trgo,
+    /// TIM4 capture / compare 1 (ADC3 / ADC4)
+    Tim4Cc1,
+    /// TIM4 capture / compare 4 (ADC1 / ADC2)
+    Tim4Cc4,
+    /// TIM4 TRGO
+    Tim4Trgo,
+    /// TIM6 TRGO (ADC1 / ADC2)
+    Tim6Trgo,
+    /// TIM7 TRGO (ADC3 / ADC4)
+    Tim7Trgo,
+    /// TIM8 capture / compare 1 (ADC3 / ADC4)
+    Tim8Cc1,
+    /// TIM8 TRGO
+    Tim8Trgo,
+    /// TIM8 TRGO2
+    Tim8Trgo2,
+    /// TIM15 TRGO
+    Tim15Trgo,
+    /// EXTI line 2 (ADC3 / ADC4)
+    Exti2,
+    /// EXTI line 11 (ADC1 / ADC2)
+    Exti11,
+}
+
+/// External trigger of the injected sequence
+///
+/// NOTE not all the triggers are available on all the ADCs; see
+/// `ADC::jextsel`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InjectedTrigger {
+    /// TIM1 capture / compare 3 (ADC3 / ADC4)
+    Tim1Cc3,
+    /// TIM1 capture / compare 4
+    Tim1Cc4,
+    /// TIM1 TRGO
+    Tim1Trgo,
+    /// TIM1 TRGO2
+    Tim1Trgo2,
+    /// TIM2 capture / compare 1 (ADC1 / ADC2)
+    Tim2Cc1,
+    /// TIM2 TRGO
+    Tim2Trgo,
+    /// TIM3 capture / compare 1 (ADC1 / ADC2)
+    Tim3Cc1,
+    /// TIM3 capture / compare 3 (ADC1 / ADC2)
+    Tim3Cc3,
+    /// TIM3 capture / compare 4 (ADC1 / ADC2)
+    Tim3Cc4,
+    /// TIM3 TRGO
+    Tim3Trgo,
+    /// TIM4 capture / compare 3 (ADC3 / ADC4)
+    Tim4Cc3,
+    /// TIM4 capture / compare 4 (ADC3 / ADC4)
+    Tim4Cc4,
+    /// TIM4 TRGO
+    Tim4Trgo,
+    /// TIM6 TRGO (ADC1 / ADC2)
+    Tim6Trgo,
+    /// TIM7 TRGO (ADC3 / ADC4)
+    Tim7Trgo,
+    /// TIM8 capture / compare 2 (ADC3 / ADC4)
+    Tim8Cc2,
+    /// TIM8 capture / compare 4
+    Tim8Cc4,
+    /// TIM8 TRGO
+    Tim8Trgo,
+    /// TIM8 TRGO2
+    Tim8Trgo2,
+    /// TIM15 TRGO
+    Tim15Trgo,
+    /// EXTI line 15 (ADC1 / ADC2)
+    Exti15,
+}
+
+/// Rank of a conversion in the injected sequence
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InjectedRank {
+    /// First conversion (`JDR1`)
+    _1,
+    /// Second conversion (`JDR2`)
+    _2,
+    /// Third conversion (`JDR3`)
+    _3,
+    /// Fourth conversion (`JDR4`)
+    _4,
+}
+
+/// ADC event
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// End of a regular conversion
+    EndOfConversion,
+    /// End of a regular sequence
+    EndOfSequence,
+    /// End of an injected sequence
+    EndOfInjectedSequence,
+    /// Regular data overrun
+    Overrun,
+    /// Analog watchdog 1 threshold crossed
+    Watchdog,
+}
+
+impl Event {
+    fn bit(&self) -> u32 {
+        match *self {
+            Event::EndOfConversion => EOC,
+            Event::EndOfSequence => EOS,
+            Event::EndOfInjectedSequence => JEOS,
+            Event::Overrun => OVR,
+            Event::Watchdog => AWD1,
+        }
+    }
+}
+
+macro_rules! inputs {
+    ($($In:ident => $n:expr,)+) => {
+        $(
+            /// ADC input channel, for use with `hal::adc::OneShot`
+            #[derive(Clone, Copy, Debug)]
+            pub struct $In;
+
+            impl<A> hal::adc::Channel<A> for $In
+            where
+                A: ADC,
+            {
+                type ID = u8;
+
+                fn channel() -> u8 {
+                    $n
+                }
+            }
+        )+
+    }
+}
+
+inputs! {
+    In1 => 1,
+    In2 => 2,
+    In3 => 3,
+    In4 => 4,
+    In5 => 5,
+    In6 => 6,
+    In7 => 7,
+    In8 => 8,
+    In9 => 9,
+    In10 => 10,
+    In11 => 11,
+    In12 => 12,
+    In13 => 13,
+    In14 => 14,
+    In15 => 15,
+    In16 => 16,
+    In17 => 17,
+    In18 => 18,
+}
+
+/// IMPLEMENTATION DETAIL
+pub unsafe trait ADC: Deref<Target = adc1::RegisterBlock> {
+    /// Common registers shared with the other ADC of the pair
+    type Common: Deref<Target = adc1_2::RegisterBlock>;
+    /// DMA controller that serves the requests of this ADC
+    type DMA: DMA;
+    /// DMA channel that serves the requests of this ADC
+    type RxChannel: DmaChannel<Dma = Self::DMA>;
+
+    /// Powers up the ADC pair this ADC belongs to
+    fn enable(rcc: &Rcc);
+    /// Maps `trigger` to its EXTSEL value, if available on this ADC
+    fn extsel(trigger: Trigger) -> Option<u32>;
+    /// Maps `trigger` to its JEXTSEL value, if available on this ADC
+    fn jextsel(trigger: InjectedTrigger) -> Option<u32>;
+}
+
+/// EXTSEL values of ADC1 and ADC2
+fn adc12_extsel(trigger: Trigger) -> Option<u32> {
+    Some(match trigger {
+        Trigger::Tim1Cc1 => 0,
+        Trigger::Tim1Cc2 => 1,
+        Trigger::Tim1Cc3 => 2,
+        Trigger::Tim2Cc2 => 3,
+        Trigger::Tim3Trgo => 4,
+        Trigger::Tim4Cc4 => 5,
+        Trigger::Exti11 => 6,
+        Trigger::Tim8Trgo => 7,
+        Trigger::Tim8Trgo2 => 8,
+        Trigger::Tim1Trgo => 9,
+        Trigger::Tim1Trgo2 => 10,
+        Trigger::Tim2Trgo => 11,
+        Trigger::Tim4Trgo => 12,
+        Trigger::Tim6Trgo => 13,
+        Trigger::Tim15Trgo => 14,
+        Trigger::Tim3Cc4 => 15,
+        _ => return None,
+    })
+}
+
+/// EXTSEL values of ADC3 and ADC4
+fn adc34_extsel(trigger: Trigger) -> Option<u32> {
+    Some(match trigger {
+        Trigger::Tim3Cc1 => 0,
+        Trigger::Tim2Cc3 => 1,
+        Trigger::Tim1Cc3 => 2,
+        Trigger::Tim8Cc1 => 3,
+        Trigger::Tim8Trgo => 4,
+        Trigger::Exti2 => 5,
+        Trigger::Tim4Cc1 => 6,
+        Trigger::Tim2Trgo => 7,
+        Trigger::Tim8Trgo2 => 8,
+        Trigger::Tim1Trgo => 9,
+        Trigger::Tim1Trgo2 => 10,
+        Trigger::Tim3Trgo => 11,
+        Trigger::Tim4Trgo => 12,
+        Trigger::Tim7Trgo => 13,
+        Trigger::Tim15Trgo => 14,
+        Trigger::Tim2Cc1 => 15,
+        _ => return None,
+    })
+}
+
+/// JEXTSEL values of ADC1 and ADC2
+fn adc12_jextsel(trigger: InjectedTrigger) -> Option<u32> {
+    Some(match trigger {
+        InjectedTrigger::Tim1Trgo => 0,
+        InjectedTrigger::Tim1Cc4 => 1,
+        InjectedTrigger::Tim2Trgo => 2,
+        InjectedTrigger::Tim2Cc1 => 3,
+        InjectedTrigger::Tim3Cc4 => 4,
+        InjectedTrigger::Tim4Trgo => 5,
+        InjectedTrigger::Exti15 => 6,
+        InjectedTrigger::Tim8Cc4 => 7,
+        InjectedTrigger::Tim1Trgo2 => 8,
+        InjectedTrigger::Tim8Trgo => 9,
+        InjectedTrigger::Tim8Trgo2 => 10,
+        InjectedTrigger::Tim3Cc3 => 11,
+        InjectedTrigger::Tim3Trgo => 12,
+        InjectedTrigger::Tim3Cc1 => 13,
+        InjectedTrigger::Tim6Trgo => 14,
+        InjectedTrigger::Tim15Trgo => 15,
+        _ => return None,
+    })
+}
+
+/// JEXTSEL values of ADC3 and ADC4
+fn adc34_jextsel(trigger: InjectedTrigger) -> Option<u32> {
+    Some(match trigger {
+        InjectedTrigger::Tim1Trgo => 0,
+        InjectedTrigger::Tim1Cc4 => 1,
+        InjectedTrigger::Tim4Cc3 => 2,
+        InjectedTrigger::Tim8Cc2 => 3,
+        InjectedTrigger::Tim8Cc4 => 4,
+        InjectedTrigger::Tim4Cc4 => 6,
+        InjectedTrigger::Tim4Trgo => 7,
+        InjectedTrigger::Tim1Trgo2 => 8,
+        InjectedTrigger::Tim8Trgo => 9,
+        InjectedTrigger::Tim8Trgo2 => 10,
+        InjectedTrigger::Tim1Cc3 => 11,
+        InjectedTrigger::Tim3Trgo => 12,
+        InjectedTrigger::Tim2Trgo => 13,
+        InjectedTrigger::Tim7Trgo => 14,
+        InjectedTrigger::Tim15Trgo => 15,
+        _ => return None,
+    })
+}
+
+/// Computes the CONT and EXTEN / EXTSEL bits of CFGR for `trigger`
+fn cfgr_trigger<A>(
+    trigger: Option<(Trigger, Edge)>,
+) -> Result<(u32, u32), Error>
+where
+    A: ADC,
+{
+    // without a trigger the sequence is converted back to back
+    match trigger {
+        Some((trigger, edge)) => {
+            let extsel = A::extsel(trigger).ok_or(Error::Trigger)?;
+
+            Ok((0, edge.exten() << 10 | extsel << 6))
+        }
+        None => Ok((CONT, 0)),
+    }
+}
+
+unsafe impl ADC for Adc1 {
+    type Common = Adc12;
+    type DMA = Dma1;
+    type RxChannel = Dma1Channel1;
+
+    fn enable(rcc: &Rcc) {
+        rcc.ahbenr.modify(|_, w| w.adc12en().enabled());
+    }
+
+    fn extsel(trigger: Trigger) -> Option<u32> {
+        adc12_extsel(trigger)
+    }
+
+    fn jextsel(trigger: InjectedTrigger) -> Option<u32> {
+        adc12_jextsel(trigger)
+    }
+}
+
+unsafe impl ADC for Adc2 {
+    type Common = Adc12;
+    type DMA = Dma2;
+    type RxChannel = Dma2Channel1;
+
+    fn enable(rcc: &Rcc) {
+        rcc.ahbenr.modify(|_, w| w.adc12en().enabled());
+    }
+
+    fn extsel(trigger: Trigger) -> Option<u32> {
+        adc12_extsel(trigger)
+    }
+
+    fn jextsel(trigger: InjectedTrigger) -> Option<u32> {
+        adc12_jextsel(trigger)
+    }
+}
+
+unsafe impl ADC for Adc3 {
+    type Common = Adc34;
+    type DMA = Dma2;
+    type RxChannel = Dma2Channel5;
+
+    fn enable(rcc: &Rcc) {
+        rcc.ahbenr.modify(|_, w| w.adc34en().enabled());
+    }
+
+    fn extsel(trigger: Trigger) -> Option<u32> {
+        adc34_extsel(trigger)
+    }
+
+    fn jextsel(trigger: InjectedTrigger) -> Option<u32> {
+        adc34_jextsel(trigger)
+    }
+}
+
+unsafe impl ADC for Adc4 {
+    type Common = Adc34;
+    type DMA = Dma2;
+    type RxChannel = Dma2Channel2;
+
+    fn enable(rcc: &Rcc) {
+        rcc.ahbenr.modify(|_, w| w.adc34en().enabled());
+    }
+
+    fn extsel(trigger: Trigger) -> Option<u32> {
+        adc34_extsel(trigger)
+    }
+
+    fn jextsel(trigger: InjectedTrigger) -> Option<u32> {
+        adc34_jextsel(trigger)
+    }
+}
+
+/// IMPLEMENTATION DETAIL
+pub unsafe trait MASTER: ADC {
+    /// The slave ADC of the pair
+    type Slave: ADC<Common = Self::Common>;
+}
+
+unsafe impl MASTER for Adc1 {
+    type Slave = Adc2;
+}
+
+unsafe impl MASTER for Adc3 {
+    type Slave = Adc4;
+}
+
+/// ADC interface
+pub struct Adc<'a, A>(pub &'a A)
+where
+    A: 'a;
+
+impl<'a, A> Clone for Adc<'a, A> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, A> Copy for Adc<'a, A> {}
+
+impl<'a, A> Adc<'a, A>
+where
+    A: ADC,
+{
+    /// Initializes the ADC
+    ///
+    /// This starts the voltage regulator and calibrates the ADC for single
+    /// ended and differential conversions. After initialization the ADC is
+    /// enabled and configured for single, 12-bit, right aligned conversions.
+    pub fn init(&self, common: &A::Common, rcc: &Rcc) {
+        let adc = self.0;
+
+        A::enable(rcc);
+
+        // CKMODE: synchronous clock, HCLK / 1
+        // NOTE this is only valid if the AHB prescaler is 1
+        common.ccr.modify(|r, w| unsafe {
+            w.bits(r.bits() & !CKMODE | 0b01 << 16)
+        });
+
+        // ADVREGEN: intermediate state (00) -> enabled (01)
+        adc.cr.write(|w| unsafe { w.bits(0) });
+        adc.cr.write(|w| unsafe { w.bits(0b01 << 28) });
+
+        // the regulator needs 10 us to start up
+        for _ in 0..::ahb::FREQUENCY / 100_000 {
+            asm::nop();
+        }
+
+        self._calibrate();
+
+        // single conversion mode, 12-bit, right aligned, overwrite the data
+        // register on overrun
+        adc.cfgr.write(|w| unsafe { w.bits(1 << 12) });
+
+        self._enable();
+    }
+
+    /// Re-runs the self calibration
+    ///
+    /// NOTE the ADC is disabled during the calibration
+    pub fn calibrate(&self) {
+        self._disable();
+        self._calibrate();
+        self._enable();
+    }
+
+    /// Selects single ended (`false`) or differential (`true`) mode for
+    /// `channel`
+    ///
+    /// In differential mode `channel` is the positive input and
+    /// `channel + 1` the negative one.
+    ///
+    /// NOTE the ADC is briefly disabled
+    pub fn set_differential(&self, channel: Channel, differential: bool) {
+        let bit = 1 << channel.0;
+
+        self._disable();
+        self.0.difsel.modify(|r, w| unsafe {
+            w.bits(if differential {
+                r.bits() | bit
+            } else {
+                r.bits() & !bit
+            })
+        });
+        self._enable();
+    }
+
+    /// Sets the sampling time of `channel`
+    pub fn set_sample_time(&self, channel: Channel, time: SampleTime) {
+        let adc = self.0;
+        let ch = u32::from(channel.0);
+
+        if ch < 10 {
+            let offset = 3 * ch;
+            adc.smpr1.modify(|r, w| unsafe {
+                w.bits(r.bits() & !(0b111 << offset) | time.bits() << offset)
+            });
+        } else {
+            let offset = 3 * (ch - 10);
+            adc.smpr2.modify(|r, w| unsafe {
+                w.bits(r.bits() & !(0b111 << offset) | time.bits() << offset)
+            });
+        }
+    }
+
+    /// Sets the resolution of the conversions
+    pub fn set_resolution(&self, resolution: Resolution) {
+        let res = match resolution {
+            Resolution::Twelve => 0b00,
+            Resolution::Ten => 0b01,
+            Resolution::Eight => 0b10,
+            Resolution::Six => 0b11,
+        };
+
+        self.0.cfgr.modify(|r, w| unsafe {
+            w.bits(r.bits() & !RES | res << 3)
+        });
+    }
+
+    /// Returns the resolution of the conversions
+    pub fn resolution(&self) -> Resolution {
+        match (self.0.cfgr.read().bits() & RES) >> 3 {
+            0b00 => Resolution::Twelve,
+            0b01 => Resolution::Ten,
+            0b10 => Resolution::Eight,
+            _ => Resolution::Six,
+        }
+    }
+
+    /// Aligns the conversion results to the left (`true`) or to the right
+    /// (`false`) of the 16-bit data register
+    pub fn set_left_aligned(&self, left: bool) {
+        self.0.cfgr.modify(|r, w| unsafe {
+            w.bits(if left {
+                r.bits() | ALIGN
+            } else {
+                r.bits() & !ALIGN
+            })
+        });
+    }
+
+    /// Enables or disables an internal channel
+    pub fn set_internal(
+        &self,
+        internal: Internal,
+        enabled: bool,
+        common: &A::Common,
+    ) {
+        let bit = match internal {
+            Internal::Temperature => TSEN,
+            Internal::Vbat => VBATEN,
+            Internal::Vrefint => VREFEN,
+        };
+
+        common.ccr.modify(|r, w| unsafe {
+            w.bits(if enabled {
+                r.bits() | bit
+            } else {
+                r.bits() & !bit
+            })
+        });
+    }
+
+    /// Converts `channel` and waits for the result
+    pub fn convert(&self, channel: Channel) -> u16 {
+        self.start(channel);
+
+        while self.0.isr.read().bits() & EOC == 0 {}
+
+        self.0.dr.read().bits() as u16
+    }
+
+    /// Starts a single conversion of `channel`
+    ///
+    /// Use `read` to retrieve the result
+    pub fn start(&self, channel: Channel) {
+        self._start(channel, false);
+    }
+
+    /// Starts converting `channel` continuously
+    ///
+    /// Use `read` to retrieve the results and `stop` to stop the conversions
+    pub fn start_continuous(&self, channel: Channel) {
+        self._start(channel, true);
+    }
+
+    /// Stops the ongoing conversions
+    pub fn stop(&self) {
+        let adc = self.0;
+
+        if adc.cr.read().bits() & ADSTART != 0 {
+            adc.cr.modify(|r, w| unsafe { w.bits(r.bits() | ADSTP) });
+            while adc.cr.read().bits() & ADSTP != 0 {}
+        }
+    }
+
+    /// Sets the regular sequence
+    ///
+    /// The channels are converted in order; a channel can appear more than
+    /// once.
+    ///
+    /// Returns `Error::Sequence` if `channels` is empty or longer than
+    /// `SEQUENCE_MAX`
+    pub fn set_sequence(&self, channels: &[Channel]) -> Result<(), Error> {
+        if channels.is_empty() || channels.len() > SEQUENCE_MAX {
+            return Err(Error::Sequence);
+        }
+
+        let adc = self.0;
+
+        self.stop();
+
+        let mut sqr = [0u32; 4];
+        // L: sequence length - 1
+        sqr[0] = channels.len() as u32 - 1;
+
+        for (rank, channel) in channels.iter().enumerate() {
+            // SQ1 - SQ4 start at bit 6 of SQR1; each of SQR2 - SQR4 starts
+            // with a new rank at bit 0
+            let (i, offset) = match rank {
+                0...3 => (0, 6 * (rank + 1)),
+                4...8 => (1, 6 * (rank - 4)),
+                9...13 => (2, 6 * (rank - 9)),
+                _ => (3, 6 * (rank - 14)),
+            };
+
+            sqr[i] |= u32::from(channel.0) << offset;
+        }
+
+        adc.sqr1.write(|w| unsafe { w.bits(sqr[0]) });
+        adc.sqr2.write(|w| unsafe { w.bits(sqr[1]) });
+        adc.sqr3.write(|w| unsafe { w.bits(sqr[2]) });
+        adc.sqr4.write(|w| unsafe { w.bits(sqr[3]) });
+
+        Ok(())
+    }
+
+    /// Sets the injected sequence and its trigger
+    ///
+    /// Injected conversions interrupt the regular sequence. `trigger` is the
+    /// hardware trigger and its active edge; `None` selects the software
+    /// trigger (`start_injected`).
+    ///
+    /// Returns `Error::Sequence` if `channels` is empty or longer than
+    /// `INJECTED_MAX`, and `Error::Trigger` if `trigger` is not available on
+    /// this ADC.
+    pub fn set_injected(
+        &self,
+        channels: &[Channel],
+        trigger: Option<(InjectedTrigger, Edge)>,
+    ) -> Result<(), Error> {
+        if channels.is_empty() || channels.len() > INJECTED_MAX {
+            return Err(Error::Sequence);
+        }
+
+        let adc = self.0;
+
+        // JL: sequence length - 1
+        let mut jsqr = channels.len() as u32 - 1;
+
+        if let Some((trigger, edge)) = trigger {
+            let jextsel = A::jextsel(trigger).ok_or(Error::Trigger)?;
+
+            jsqr |= edge.exten() << 6 | jextsel << 2;
+        }
+
+        for (rank, channel) in channels.iter().enumerate() {
+            jsqr |= u32::from(channel.0) << (8 + 6 * rank);
+        }
+
+        self.stop_injected();
+
+        adc.jsqr.write(|w| unsafe { w.bits(jsqr) });
+
+        Ok(())
+    }
+
+    /// Starts the injected sequence
+    ///
+    /// With a hardware trigger the sequence is converted on every trigger
+    /// event; otherwise it's converted right away.
+    pub fn start_injected(&self) {
+        let adc = self.0;
+
+        adc.isr.write(|w| unsafe { w.bits(JEOC | JEOS) });
+        adc.cr.modify(|r, w| unsafe { w.bits(r.bits() | JADSTART) });
+    }
+
+    /// Stops the injected conversions
+    pub fn stop_injected(&self) {
+        let adc = self.0;
+
+        if adc.cr.read().bits() & JADSTART != 0 {
+            adc.cr.modify(|r, w| unsafe { w.bits(r.bits() | JADSTP) });
+            while adc.cr.read().bits() & JADSTP != 0 {}
+        }
+    }
+
+    /// Reads the result of the `rank` conversion of the injected sequence
+    ///
+    /// Returns `WouldBlock` until the whole sequence has been converted
+    pub fn read_injected(&self, rank: InjectedRank) -> nb::Result<u16, !> {
+        let adc = self.0;
+
+        if adc.isr.read().bits() & JEOS == 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        Ok(match rank {
+            InjectedRank::_1 => adc.jdr1.read().bits(),
+            InjectedRank::_2 => adc.jdr2.read().bits(),
+            InjectedRank::_3 => adc.jdr3.read().bits(),
+            InjectedRank::_4 => adc.jdr4.read().bits(),
+        } as u16)
+    }
+
+    /// Enables the analog watchdog 1
+    ///
+    /// The watchdog flags conversions outside the `low` - `high` range
+    /// (inclusive). It guards `channel` or, if `None`, all the channels;
+    /// both regular and injected conversions are guarded.
+    ///
+    /// NOTE the thresholds are compared against 12-bit right aligned values
+    pub fn enable_watchdog(
+        &self,
+        channel: Option<Channel>,
+        low: u16,
+        high: u16,
+    ) {
+        let adc = self.0;
+
+        self.stop();
+        self.stop_injected();
+
+        adc.tr1.write(|w| unsafe {
+            w.bits(u32::from(high & 0xfff) << 16 | u32::from(low & 0xfff))
+        });
+
+        let sgl = match channel {
+            Some(channel) => AWD1SGL | u32::from(channel.0) << 26,
+            None => 0,
+        };
+
+        adc.cfgr.modify(|r, w| unsafe {
+            w.bits(
+                r.bits() & !(AWD1SGL | AWD1CH) | sgl | AWD1EN | JAWD1EN,
+            )
+        });
+    }
+
+    /// Disables the analog watchdog 1
+    pub fn disable_watchdog(&self) {
+        let adc = self.0;
+
+        self.stop();
+        self.stop_injected();
+
+        adc.cfgr.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(AWD1EN | JAWD1EN))
+        });
+    }
+
+    /// Starts listening for `event`
+    pub fn listen(&self, event: Event) {
+        let bit = event.bit();
+
+        self.0.ier.modify(|r, w| unsafe { w.bits(r.bits() | bit) });
+    }
+
+    /// Stops listening for `event`
+    pub fn unlisten(&self, event: Event) {
+        let bit = event.bit();
+
+        self.0.ier.modify(|r, w| unsafe { w.bits(r.bits() & !bit) });
+    }
+
+    /// Checks whether `event` has occurred
+    pub fn is_pending(&self, event: Event) -> bool {
+        self.0.isr.read().bits() & event.bit() != 0
+    }
+
+    /// Clears the `event` flag
+    pub fn clear(&self, event: Event) {
+        // NOTE the flags are cleared by writing 1
+        self.0.isr.write(|w| unsafe { w.bits(event.bit()) });
+    }
+
+    /// Reads the result of the last conversion
+    pub fn read(&self) -> nb::Result<u16, Error> {
+        let adc = self.0;
+        let isr = adc.isr.read().bits();
+
+        if isr & OVR != 0 {
+            adc.isr.write(|w| unsafe { w.bits(OVR) });
+            Err(nb::Error::Other(Error::Overrun))
+        } else if isr & EOC != 0 {
+            // NOTE reading DR clears the EOC flag
+            Ok(adc.dr.read().bits() as u16)
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    fn _start(&self, channel: Channel, continuous: bool) {
+        let adc = self.0;
+
+        // NOTE CFGR can only be written while no conversion is ongoing
+        self.stop();
+
+        // software trigger, no DMA
+        let cont = if continuous { CONT } else { 0 };
+        adc.cfgr.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(CONT | EXTEN | DMAEN) | cont)
+        });
+
+        // L = 0: one conversion; SQ1 = channel
+        adc.sqr1.write(|w| unsafe { w.bits(u32::from(channel.0) << 6) });
+
+        // clear stale flags
+        adc.isr.write(|w| unsafe { w.bits(EOC | OVR) });
+
+        adc.cr.modify(|r, w| unsafe { w.bits(r.bits() | ADSTART) });
+    }
+
+    /// Configures the trigger (`cfgr_trigger`) and the DMA request
+    /// generation of the regular sequence and starts it
+    fn _start_sequence(&self, (cont, ext): (u32, u32), circular: bool) {
+        let adc = self.0;
+
+        // DMACFG: circular mode; otherwise the DMA requests stop after the
+        // last transfer
+        let dmacfg = if circular { DMACFG } else { 0 };
+
+        adc.cfgr.modify(|r, w| unsafe {
+            w.bits(
+                r.bits() & !(CONT | EXTEN | EXTSEL | DMACFG) | cont | ext |
+                    dmacfg | DMAEN,
+            )
+        });
+
+        // clear stale flags
+        adc.isr.write(|w| unsafe { w.bits(EOC | EOS | OVR) });
+
+        adc.cr.modify(|r, w| unsafe { w.bits(r.bits() | ADSTART) });
+    }
+
+    fn _calibrate(&self) {
+        let adc = self.0;
+
+        for &dif in &[0, ADCALDIF] {
+            adc.cr.modify(|r, w| unsafe {
+                w.bits(r.bits() & !ADCALDIF | dif | ADCAL)
+            });
+            while adc.cr.read().bits() & ADCAL != 0 {}
+        }
+
+        adc.cr.modify(|r, w| unsafe { w.bits(r.bits() & !ADCALDIF) });
+    }
+
+    fn _enable(&self) {
+        let adc = self.0;
+
+        adc.cr.modify(|r, w| unsafe { w.bits(r.bits() | ADEN) });
+        while adc.isr.read().bits() & ADRDY == 0 {}
+
+        // ADRDY is cleared by writing 1
+        adc.isr.write(|w| unsafe { w.bits(ADRDY) });
+    }
+
+    fn _disable(&self) {
+        let adc = self.0;
+
+        self.stop();
+
+        if adc.cr.read().bits() & ADEN != 0 {
+            adc.cr.modify(|r, w| unsafe { w.bits(r.bits() | ADDIS) });
+            while adc.cr.read().bits() & ADEN != 0 {}
+        }
+    }
+}
+
+impl<'a, A> Adc<'a, A>
+where
+    A: ADC,
+{
+    /// Converts the regular sequence (see `set_sequence`) repeatedly until
+    /// `buffer` is full
+    ///
+    /// Without a `trigger` the conversions run back to back; otherwise the
+    /// whole sequence is converted on every trigger event. The samples of
+    /// the sequence are stored in order, one after the other. This will
+    /// mutably lock `buffer`, which can be `release`d once it has been
+    /// filled.
+    pub fn read_sequence<B>(
+        &self,
+        dma: &A::DMA,
+        _channel: &A::RxChannel,
+        rcc: &Rcc,
+        buffer: Ref<Buffer<B, A::RxChannel>>,
+        trigger: Option<(Trigger, Edge)>,
+    ) -> Result<(), Error>
+    where
+        B: Unsize<[u16]>,
+    {
+        if A::RxChannel::is_enabled(dma) {
+            return Err(Error::Dma(dma::Error::InUse));
+        }
+
+        let trigger = cfgr_trigger::<A>(trigger)?;
+        let len = dma::transfer_len(buffer.len::<u16>()).map_err(Error::Dma)?;
+
+        let buffer: &mut [u16] = buffer.lock_mut().map_err(Error::Dma)?;
+
+        self.stop();
+
+        self.configure_dma(dma, rcc, buffer.as_ptr() as u32, len, false);
+        self._start_sequence(trigger, false);
+
+        Ok(())
+    }
+
+    /// Streams the regular sequence (see `set_sequence`) into `buffer`
+    ///
+    /// The halves of `buffer` can be accessed using `CircBuffer::read` as
+    /// they get filled. The stream runs until `stop` and `CircBuffer::stop`
+    /// are called.
+    pub fn read_sequence_circ<B>(
+        &self,
+        dma: &A::DMA,
+        _channel: &A::RxChannel,
+        rcc: &Rcc,
+        buffer: Ref<CircBuffer<B, A::RxChannel>>,
+        trigger: Option<(Trigger, Edge)>,
+    ) -> Result<(), Error>
+    where
+        B: Unsize<[u16]>,
+    {
+        if A::RxChannel::is_enabled(dma) {
+            return Err(Error::Dma(dma::Error::InUse));
+        }
+
+        let trigger = cfgr_trigger::<A>(trigger)?;
+        let len = dma::transfer_len(buffer.len::<u16>()).map_err(Error::Dma)?;
+
+        let buffer: &[B; 2] = buffer.lock().map_err(Error::Dma)?;
+
+        self.stop();
+
+        self.configure_dma(dma, rcc, buffer.as_ptr() as u32, len, true);
+        self._start_sequence(trigger, true);
+
+        Ok(())
+    }
+
+    fn configure_dma(
+        &self,
+        dma: &A::DMA,
+        rcc: &Rcc,
+        address: u32,
+        len: u16,
+        circular: bool,
+    ) {
+        let adc = self.0;
+
+        A::DMA::enable(rcc);
+
+        // 16-bit results; the half and full transfer interrupts drive
+        // `CircBuffer::read`
+        A::RxChannel::configure(
+            dma,
+            &dma::Config {
+                priority: Priority::VeryHigh,
+                circular: circular,
+                transfer_complete_interrupt: circular,
+                half_transfer_interrupt: circular,
+                ..dma::Config::peripheral(
+                    Direction::PeripheralToMemory,
+                    Size::HalfWord,
+                )
+            },
+        );
+        A::RxChannel::start(dma, &adc.dr as *const _ as u32, address, len);
+    }
+}
+
+/// Dual ADC mode
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DualMode {
+    /// The regular sequences of both ADCs are converted at the same time
+    RegularSimultaneous,
+    /// The injected sequences of both ADCs are converted at the same time
+    InjectedSimultaneous,
+    /// Both the regular and the injected sequences are converted at the same
+    /// time
+    Simultaneous,
+    /// The master and the slave take turns converting the same channel; the
+    /// slave starts `delay` (1 - 16) ADC clock cycles after the master
+    Interleaved {
+        /// Delay between the master and the slave conversions
+        delay: u8,
+    },
+    /// The injected trigger events alternate between the master and the
+    /// slave
+    AlternateTrigger,
+}
+
+/// Two ADCs working as master and slave
+///
+/// The master (ADC1 or ADC3) controls the conversions of the slave (ADC2 or
+/// ADC4). Both ADCs must be initialized with `Adc::init` beforehand.
+pub struct DualAdc<'a, M>(pub &'a M, pub &'a M::Slave, pub &'a M::Common)
+where
+    M: MASTER + 'a,
+    M::Slave: 'a,
+    M::Common: 'a;
+
+impl<'a, M> Clone for DualAdc<'a, M>
+where
+    M: MASTER,
+{
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, M> Copy for DualAdc<'a, M>
+where
+    M: MASTER,
+{
+}
+
+impl<'a, M> DualAdc<'a, M>
+where
+    M: MASTER,
+{
+    /// Switches the ADC pair to dual `mode`
+    ///
+    /// # Panics
+    ///
+    /// If the `Interleaved` delay is out of range
+    pub fn init(&self, mode: DualMode) {
+        let common = self.2;
+
+        let (dual, delay) = match mode {
+            DualMode::Simultaneous => (0b00001, 0),
+            DualMode::InjectedSimultaneous => (0b00101, 0),
+            DualMode::RegularSimultaneous => (0b00110, 0),
+            DualMode::Interleaved { delay } => {
+                assert!(delay >= 1 && delay <= 16);
+
+                (0b00111, u32::from(delay - 1))
+            }
+            DualMode::AlternateTrigger => (0b01001, 0),
+        };
+
+        // NOTE DUAL can only be changed while no conversion is ongoing
+        self.stop();
+
+        common.ccr.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(DUAL | DELAY) | dual | delay << 8)
+        });
+    }
+
+    /// Switches the ADC pair back to independent mode
+    pub fn release(&self) {
+        let common = self.2;
+
+        self.stop();
+
+        common.ccr.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(DUAL | DELAY | MDMA | CCR_DMACFG))
+        });
+    }
+
+    /// Sets the regular sequences of the master and the slave
+    ///
+    /// Returns `Error::Sequence` if the sequences don't have the same length
+    /// (required by the simultaneous modes) or if they are empty or too
+    /// long; neither sequence is changed then.
+    pub fn set_sequences(
+        &self,
+        master: &[Channel],
+        slave: &[Channel],
+    ) -> Result<(), Error> {
+        if master.len() != slave.len() || master.is_empty() ||
+            master.len() > SEQUENCE_MAX
+        {
+            return Err(Error::Sequence);
+        }
+
+        Adc(self.0).set_sequence(master)?;
+        Adc(self.1).set_sequence(slave)
+    }
+
+    /// Starts converting the regular sequences
+    ///
+    /// Without a `trigger` the conversions run back to back; otherwise they
+    /// run on every trigger event. Use `read` to retrieve the results
+    pub fn start(
+        &self,
+        trigger: Option<(Trigger, Edge)>,
+    ) -> Result<(), Error> {
+        let trigger = cfgr_trigger::<M>(trigger)?;
+
+        self.stop();
+
+        self.2.ccr.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(MDMA | CCR_DMACFG))
+        });
+
+        self._start(trigger);
+
+        Ok(())
+    }
+
+    /// Stops the regular conversions of both ADCs
+    pub fn stop(&self) {
+        Adc(self.0).stop();
+        Adc(self.1).stop();
+    }
+
+    /// Reads the results of the last master and slave conversions, as a
+    /// `(master, slave)` pair
+    pub fn read(&self) -> nb::Result<(u16, u16), Error> {
+        let (master, slave) = (self.0, self.1);
+        let (misr, sisr) = (master.isr.read().bits(), slave.isr.read().bits());
+
+        if misr & OVR != 0 || sisr & OVR != 0 {
+            master.isr.write(|w| unsafe { w.bits(OVR) });
+            slave.isr.write(|w| unsafe { w.bits(OVR) });
+
+            Err(nb::Error::Other(Error::Overrun))
+        } else if misr & EOC != 0 && sisr & EOC != 0 {
+            // NOTE reading the data registers clears the EOC flags
+            let m = master.dr.read().bits() as u16;
+            let s = slave.dr.read().bits() as u16;
+
+            Ok((m, s))
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    /// Configures the slave like the master, with the trigger bits computed
+    /// by `cfgr_trigger`, and starts the master
+    fn _start(&self, (cont, ext): (u32, u32)) {
+        let (master, slave) = (self.0, self.1);
+
+        // NOTE the slave's trigger is ignored but its CONT bit must match
+        // the master's; the results are collected through the common data
+        // register so the DMA requests of each ADC stay disabled
+        master.cfgr.modify(|r, w| unsafe {
+            w.bits(
+                r.bits() & !(CONT | EXTEN | EXTSEL | DMAEN | DMACFG) | cont |
+                    ext,
+            )
+        });
+        slave.cfgr.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(CONT | EXTEN | DMAEN | DMACFG) | cont)
+        });
+
+        master.isr.write(|w| unsafe { w.bits(EOC | EOS | OVR) });
+        slave.isr.write(|w| unsafe { w.bits(EOC | EOS | OVR) });
+
+        // starting the master starts the slave as well
+        master.cr.modify(|r, w| unsafe { w.bits(r.bits() | ADSTART) });
+    }
+}
+
+impl<'a, M> DualAdc<'a, M>
+where
+    M: MASTER,
+{
+    /// Converts the regular sequences repeatedly until `buffer` is full
+    ///
+    /// Each word of `buffer` packs the result of a master conversion (lower
+    /// half word) with the result of the matching slave conversion (upper
+    /// half word). This will mutably lock `buffer`, which can be `release`d
+    /// once it has been filled.
+    ///
+    /// NOTE the packing requires 12-bit or 10-bit resolution
+    pub fn read_sequences<B>(
+        &self,
+        dma: &M::DMA,
+        _channel: &M::RxChannel,
+        rcc: &Rcc,
+        buffer: Ref<Buffer<B, M::RxChannel>>,
+        trigger: Option<(Trigger, Edge)>,
+    ) -> Result<(), Error>
+    where
+        B: Unsize<[u32]>,
+    {
+        if M::RxChannel::is_enabled(dma) {
+            return Err(Error::Dma(dma::Error::InUse));
+        }
+
+        let trigger = cfgr_trigger::<M>(trigger)?;
+        let len = dma::transfer_len(buffer.len::<u32>()).map_err(Error::Dma)?;
+
+        let buffer: &mut [u32] = buffer.lock_mut().map_err(Error::Dma)?;
+
+        self.stop();
+
+        self.configure_dma(dma, rcc, buffer.as_ptr() as u32, len, false);
+        self._start(trigger);
+
+        Ok(())
+    }
+
+    /// Streams the regular sequences into `buffer`
+    ///
+    /// The words of `buffer` are packed like in `read_sequences`. The halves
+    /// of `buffer` can be accessed using `CircBuffer::read` as they get
+    /// filled. The stream runs until `stop` and `CircBuffer::stop` are
+    /// called.
+    pub fn read_sequences_circ<B>(
+        &self,
+        dma: &M::DMA,
+        _channel: &M::RxChannel,
+        rcc: &Rcc,
+        buffer: Ref<CircBuffer<B, M::RxChannel>>,
+        trigger: Option<(Trigger, Edge)>,
+    ) -> Result<(), Error>
+    where
+        B: Unsize<[u32]>,
+    {
+        if M::RxChannel::is_enabled(dma) {
+            return Err(Error::Dma(dma::Error::InUse));
+        }
+
+        let trigger = cfgr_trigger::<M>(trigger)?;
+        let len = dma::transfer_len(buffer.len::<u32>()).map_err(Error::Dma)?;
+
+        let buffer: &[B; 2] = buffer.lock().map_err(Error::Dma)?;
+
+        self.stop();
+
+        self.configure_dma(dma, rcc, buffer.as_ptr() as u32, len, true);
+        self._start(trigger);
+
+        Ok(())
+    }
+
+    fn configure_dma(
+        &self,
+        dma: &M::DMA,
+        rcc: &Rcc,
+        address: u32,
+        len: u16,
+        circular: bool,
+    ) {
+        let common = self.2;
+
+        // MDMA: one DMA request per master / slave pair of 12 or 10-bit
+        // results; DMACFG: circular mode
+        let dmacfg = if circular { CCR_DMACFG } else { 0 };
+        common.ccr.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(MDMA | CCR_DMACFG) | 0b10 << 14 | dmacfg)
+        });
+
+        M::DMA::enable(rcc);
+
+        // 32-bit words; the half and full transfer interrupts drive
+        // `CircBuffer::read`
+        M::RxChannel::configure(
+            dma,
+            &dma::Config {
+                priority: Priority::VeryHigh,
+                circular: circular,
+                transfer_complete_interrupt: circular,
+                half_transfer_interrupt: circular,
+                ..dma::Config::peripheral(
+                    Direction::PeripheralToMemory,
+                    Size::Word,
+                )
+            },
+        );
+        M::RxChannel::start(dma, &common.cdr as *const _ as u32, address, len);
+    }
+}
+
+/// Single conversion on demand
+///
+/// The first call starts the conversion; the result is returned once it's
+/// ready.
+impl<'a, A, PIN> hal::adc::OneShot<A, u16, PIN> for Adc<'a, A>
+where
+    A: ADC,
+    PIN: hal::adc::Channel<A, ID = u8>,
+{
+    type Error = Error;
+
+    fn read(&mut self, _pin: &mut PIN) -> nb::Result<u16, Error> {
+        let adc = self.0;
+
+        if adc.cr.read().bits() & ADSTART == 0 &&
+            adc.isr.read().bits() & EOC == 0
+        {
+            self.start(Channel(PIN::channel()));
+            return Err(nb::Error::WouldBlock);
+        }
+
+        Adc::read(self)
+    }
+}
+
+/// Returns the VDDA voltage, in mV, computed from a 12-bit right aligned
+/// conversion of `VREFINT`
+///
+/// Returns `None` if `vrefint` is zero, e.g. because the internal channels
+/// haven't been enabled
+pub fn vdda(vrefint: u16) -> Option<u32> {
+    if vrefint == 0 {
+        return None;
+    }
+
+    let cal = unsafe { ptr::read(VREFINT_CAL) };
+
+    Some(VDDA_CAL * u32::from(cal) / u32::from(vrefint))
+}
+
+/// Converts a `sample` taken at `resolution` into mV, given the VDDA voltage
+/// in mV
+pub fn millivolts(sample: u16, resolution: Resolution, vdda: u32) -> u32 {
+    u32::from(sample) * vdda / u32::from(resolution.max_sample())
+}
+
+/// Converts a 12-bit right aligned conversion of `VBAT` into mV, given the
+/// VDDA voltage in mV
+pub fn vbat(sample: u16, vdda: u32) -> u32 {
+    // VBAT is sampled through a divider by 2
+    2 * millivolts(sample, Resolution::Twelve, vdda)
+}
+
+/// Converts a 12-bit right aligned conversion of `VTS` into degrees Celsius,
+/// given the VDDA voltage in mV
+pub fn temperature(sample: u16, vdda: u32) -> f32 {
+    let (cal1, cal2) = unsafe { (ptr::read(TS_CAL1), ptr::read(TS_CAL2)) };
+
+    // scale the sample to the VDDA used during the factory calibration
+    let sample = u32::from(sample) as f32 * vdda as f32 / VDDA_CAL as f32;
+
+    (110. - 30.) * (sample - f32::from(cal1)) /
+        (f32::from(cal2) - f32::from(cal1)) + 30.
+}
@@ -0,0 +1,403 @@
+//! Software timers multiplexed on a single hardware timer
+//!
+//! `Alarms` keeps a queue of virtual timers sorted by expiration time. A
+//! hardware timer configured with a periodic timeout drives it: every update
+//! event advances the alarm clock by one tick and fires the alarms that have
+//! expired. Expired alarms raise a flag that can be polled with `expired` and,
+//! optionally, call a callback from the timer interrupt.
+//!
+//! ``` ignore
+//! static ALARMS: Resource<Alarms<[Slot; 4]>, C1> =
+//!     Resource::new(Alarms::new([SLOT; 4]));
+//!
+//! // one alarm tick = 1 ms
+//! timer.init(1.ms(), rcc).unwrap();
+//! timer.resume();
+//!
+//! alarms.start(Alarm(0), 500, Some(500), None).unwrap();
+//!
+//! // in the timer interrupt handler
+//! alarms.on_interrupt(&timer);
+//! ```
+
+use core::marker::Unsize;
+
+use hal;
+use nb;
+
+/// Alarm error
+#[derive(Debug)]
+pub enum Error {
+    /// All the slots of the queue are in use
+    Full,
+    /// The alarm identifier is out of range (it must be less than 32)
+    InvalidId,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Identifier of a virtual timer
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Alarm(pub u8);
+
+/// Function called, in interrupt context, when an alarm expires
+pub type Callback = fn(Alarm);
+
+/// A slot of the alarm queue
+#[derive(Clone, Copy)]
+pub struct Slot {
+    alarm: Alarm,
+    deadline: u32,
+    period: u32,
+    callback: Option<Callback>,
+}
+
+/// An empty slot, used to build the storage of the alarm queue
+pub const SLOT: Slot = Slot {
+    alarm: Alarm(0),
+    deadline: 0,
+    period: 0,
+    callback: None,
+};
+
+/// Virtual timers driven by a hardware timer
+///
+/// `Q` is the storage of the queue; its length is the maximum number of
+/// alarms that can be running at the same time.
+pub struct Alarms<Q>
+where
+    Q: Unsize<[Slot]>,
+{
+    expired: u32,
+    len: usize,
+    now: u32,
+    queue: Q,
+}
+
+impl<Q> Alarms<Q>
+where
+    Q: Unsize<[Slot]>,
+{
+    /// Creates a new alarm service that uses `queue` as storage
+    pub const fn new(queue: Q) -> Self {
+        Alarms {
+            expired: 0,
+            len: 0,
+            now: 0,
+            queue: queue,
+        }
+    }
+
+    /// Returns the number of ticks elapsed since the service was created
+    ///
+    /// NOTE this value wraps around on overflow
+    pub fn now(&self) -> u32 {
+        self.now
+    }
+
+    /// Starts `alarm`, which will expire `timeout` ticks from now
+    ///
+    /// If `period` is `Some` the alarm is re-armed on every expiration and
+    /// keeps firing every `period` ticks until cancelled. `callback`, if any,
+    /// is called from `tick` on every expiration.
+    ///
+    /// Starting an alarm that's already running restarts it. Timeouts and
+    /// periods are clamped to `1..2^31` ticks.
+    pub fn start(
+        &mut self,
+        alarm: Alarm,
+        timeout: u32,
+        period: Option<u32>,
+        callback: Option<Callback>,
+    ) -> Result<(), Error> {
+        if alarm.0 >= 32 {
+            return Err(Error::InvalidId);
+        }
+
+        self.cancel(alarm);
+
+        if self.len == self.queue().len() {
+            return Err(Error::Full);
+        }
+
+        let deadline = self.now.wrapping_add(clamp(timeout));
+        self.insert(Slot {
+            alarm: alarm,
+            deadline: deadline,
+            period: period.map(clamp).unwrap_or(0),
+            callback: callback,
+        });
+
+        Ok(())
+    }
+
+    /// Stops `alarm`
+    ///
+    /// Returns `false` if the alarm wasn't running. This also clears the
+    /// expired flag of the alarm.
+    pub fn cancel(&mut self, alarm: Alarm) -> bool {
+        if alarm.0 < 32 {
+            self.expired &= !(1 << alarm.0);
+        }
+
+        let len = self.len;
+        let position = self.queue()[..len].iter().position(|s| s.alarm == alarm);
+
+        if let Some(i) = position {
+            self.remove(i);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Checks whether `alarm` is running
+    pub fn is_running(&self, alarm: Alarm) -> bool {
+        self.queue()[..self.len].iter().any(|s| s.alarm == alarm)
+    }
+
+    /// Returns the number of ticks left until `alarm` expires
+    pub fn remaining(&self, alarm: Alarm) -> Option<u32> {
+        self.queue()[..self.len]
+            .iter()
+            .find(|s| s.alarm == alarm)
+            .map(|s| s.deadline.wrapping_sub(self.now))
+    }
+
+    /// Checks whether `alarm` has expired since the last call to this
+    /// method, and clears its expired flag
+    pub fn expired(&mut self, alarm: Alarm) -> bool {
+        if alarm.0 >= 32 {
+            return false;
+        }
+
+        let mask = 1 << alarm.0;
+        let expired = self.expired & mask != 0;
+        self.expired &= !mask;
+        expired
+    }
+
+    /// Advances the alarm clock by one tick and fires the alarms that have
+    /// expired
+    pub fn tick(&mut self) {
+        self.now = self.now.wrapping_add(1);
+
+        while self.len != 0 {
+            let head = self.queue()[0];
+
+            // NOTE deadlines are at most 2^31 ticks away so the wrapping
+            // difference tells us whether the deadline is in the past
+            if (self.now.wrapping_sub(head.deadline) as i32) < 0 {
+                break;
+            }
+
+            self.remove(0);
+            self.expired |= 1 << head.alarm.0;
+
+            if head.period != 0 {
+                self.insert(Slot {
+                    deadline: head.deadline.wrapping_add(head.period),
+                    ..head
+                });
+            }
+
+            if let Some(callback) = head.callback {
+                callback(head.alarm);
+            }
+        }
+    }
+
+    /// Services the update event of the hardware `timer`
+    ///
+    /// Call this from the timer interrupt handler. Returns `true` if an
+    /// update event was pending.
+    pub fn on_interrupt<T>(&mut self, timer: &T) -> bool
+    where
+        T: hal::Timer,
+    {
+        match timer.wait() {
+            Ok(()) => {
+                self.tick();
+                true
+            }
+            Err(nb::Error::WouldBlock) => false,
+            Err(nb::Error::Other(_)) => unreachable!(),
+        }
+    }
+
+    // keeps the queue sorted by remaining time; alarms with the same deadline
+    // fire in the order they were started
+    fn insert(&mut self, slot: Slot) {
+        let now = self.now;
+        let len = self.len;
+        let remaining = slot.deadline.wrapping_sub(now);
+
+        let i = self.queue()[..len]
+            .iter()
+            .position(|s| s.deadline.wrapping_sub(now) > remaining)
+            .unwrap_or(len);
+
+        {
+            let queue = self.queue_mut();
+            let mut j = len;
+            while j > i {
+                queue[j] = queue[j - 1];
+                j -= 1;
+            }
+            queue[i] = slot;
+        }
+
+        self.len += 1;
+    }
+
+    fn remove(&mut self, i: usize) {
+        let len = self.len;
+
+        {
+            let queue = self.queue_mut();
+            for j in i..len - 1 {
+                queue[j] = queue[j + 1];
+            }
+        }
+
+        self.len -= 1;
+    }
+
+    fn queue(&self) -> &[Slot] {
+        &self.queue
+    }
+
+    fn queue_mut(&mut self) -> &mut [Slot] {
+        &mut self.queue
+    }
+}
+
+fn clamp(ticks: u32) -> u32 {
+    if ticks == 0 {
+        1
+    } else if ticks > i32::max_value() as u32 {
+        i32::max_value() as u32
+    } else {
+        ticks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Alarm, Alarms, Error, SLOT, Slot};
+
+    fn order(alarms: &Alarms<[Slot; 4]>) -> Vec<u8> {
+        alarms.queue()[..alarms.len].iter().map(|s| s.alarm.0).collect()
+    }
+
+    fn ticks(alarms: &mut Alarms<[Slot; 4]>, n: u32) {
+        for _ in 0..n {
+            alarms.tick();
+        }
+    }
+
+    #[test]
+    fn insert_ordering() {
+        let mut alarms = Alarms::new([SLOT; 4]);
+
+        alarms.start(Alarm(0), 30, None, None).unwrap();
+        alarms.start(Alarm(1), 10, None, None).unwrap();
+        alarms.start(Alarm(2), 20, None, None).unwrap();
+        assert_eq!(order(&alarms), [1, 2, 0]);
+
+        ticks(&mut alarms, 9);
+        assert!(!alarms.expired(Alarm(1)));
+
+        ticks(&mut alarms, 1);
+        assert!(alarms.expired(Alarm(1)));
+        assert!(!alarms.expired(Alarm(2)));
+        assert_eq!(order(&alarms), [2, 0]);
+        assert_eq!(alarms.remaining(Alarm(0)), Some(20));
+    }
+
+    #[test]
+    fn equal_deadlines() {
+        let mut alarms = Alarms::new([SLOT; 4]);
+
+        alarms.start(Alarm(3), 5, None, None).unwrap();
+        alarms.start(Alarm(1), 5, None, None).unwrap();
+        assert_eq!(order(&alarms), [3, 1]);
+
+        ticks(&mut alarms, 5);
+        assert!(alarms.expired(Alarm(3)));
+        assert!(alarms.expired(Alarm(1)));
+        assert!(order(&alarms).is_empty());
+    }
+
+    #[test]
+    fn wrapping_deadline() {
+        let mut alarms = Alarms::new([SLOT; 4]);
+        alarms.now = u32::max_value() - 2;
+
+        alarms.start(Alarm(0), 5, None, None).unwrap();
+        alarms.start(Alarm(1), 2, None, None).unwrap();
+        assert_eq!(order(&alarms), [1, 0]);
+
+        ticks(&mut alarms, 4);
+        assert_eq!(alarms.now(), 1);
+        assert!(alarms.expired(Alarm(1)));
+        assert!(!alarms.expired(Alarm(0)));
+        assert_eq!(alarms.remaining(Alarm(0)), Some(1));
+
+        ticks(&mut alarms, 1);
+        assert!(alarms.expired(Alarm(0)));
+    }
+
+    #[test]
+    fn periodic() {
+        let mut alarms = Alarms::new([SLOT; 4]);
+
+        alarms.start(Alarm(0), 2, Some(3), None).unwrap();
+        ticks(&mut alarms, 2);
+        assert!(alarms.expired(Alarm(0)));
+        assert_eq!(alarms.remaining(Alarm(0)), Some(3));
+
+        ticks(&mut alarms, 3);
+        assert!(alarms.expired(Alarm(0)));
+        assert!(alarms.is_running(Alarm(0)));
+    }
+
+    #[test]
+    fn full() {
+        let mut alarms = Alarms::new([SLOT; 2]);
+
+        alarms.start(Alarm(0), 10, None, None).unwrap();
+        alarms.start(Alarm(1), 10, None, None).unwrap();
+
+        match alarms.start(Alarm(2), 10, None, None) {
+            Err(Error::Full) => {}
+            _ => panic!("queue not full"),
+        }
+
+        // restarting a running alarm reuses its slot
+        alarms.start(Alarm(0), 1, None, None).unwrap();
+        assert_eq!(alarms.remaining(Alarm(0)), Some(1));
+
+        match alarms.start(Alarm(32), 10, None, None) {
+            Err(Error::InvalidId) => {}
+            _ => panic!("invalid id accepted"),
+        }
+    }
+
+    #[test]
+    fn cancel() {
+        let mut alarms = Alarms::new([SLOT; 4]);
+
+        alarms.start(Alarm(0), 5, None, None).unwrap();
+        alarms.start(Alarm(1), 10, None, None).unwrap();
+
+        assert!(alarms.cancel(Alarm(0)));
+        assert!(!alarms.cancel(Alarm(0)));
+        assert!(!alarms.is_running(Alarm(0)));
+        assert_eq!(order(&alarms), [1]);
+
+        ticks(&mut alarms, 10);
+        assert!(!alarms.expired(Alarm(0)));
+        assert!(alarms.expired(Alarm(1)));
+    }
+}
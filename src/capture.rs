@@ -0,0 +1,228 @@
+//! Input capture
+//!
+//! You can use the `Capture` interface with these timers
+//!
+//! # TIM15
+//!
+//! - CH1 = PA2
+//! - CH2 = PA3
+//!
+//! # TIM16
+//!
+//! - CH1 = PB8
+//!
+//! # TIM17
+//!
+//! - CH1 = PB5
+
+use core::cmp;
+
+use cast::{u16, u32};
+use hal;
+use nb;
+use stm32f30x::{Rcc, Tim15, Tim16, Tim17};
+
+use timer::{GTIM, Tim15Channel, Tim16Channel, Tim17Channel, TimerError};
+
+/// Input capture error
+#[derive(Debug)]
+pub enum Error {
+    /// Previous capture value was overwritten
+    Overcapture,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Input capture interface
+pub struct Capture<'a, T>(pub &'a T)
+where
+    T: 'a;
+
+impl<'a, T> Clone for Capture<'a, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T> Copy for Capture<'a, T> {}
+
+/// Computes the prescaler (`PSC`) that gives the counter a resolution of
+/// `resolution` ticks
+fn psc(resolution: u32) -> Result<u16, TimerError> {
+    if resolution == 0 {
+        Err(TimerError::PeriodTooShort)
+    } else {
+        u16(resolution - 1).map_err(|_| TimerError::PeriodTooLong)
+    }
+}
+
+/// Like `psc` but clamps `resolution` to the range the prescaler can produce
+fn psc_saturating(resolution: u32) -> u16 {
+    (cmp::min(cmp::max(resolution, 1), 1 << 16) - 1) as u16
+}
+
+macro_rules! capture {
+    (
+        $TIM:ident,
+        $Channel:ident,
+        [$($CH:ident),+]
+    ) => {
+        impl<'a> Capture<'a, $TIM> {
+            /// Initializes the input capture interface with a resolution of
+            /// `resolution` ticks
+            ///
+            /// All the channels capture on the rising edge of their input.
+            ///
+            /// NOTE After initialization, all the channels will be disabled
+            pub fn init<R>(
+                &self,
+                resolution: R,
+                gpio: &<$TIM as GTIM>::GPIO,
+                rcc: &Rcc,
+            ) -> Result<(), TimerError>
+            where
+                R: Into<::apb2::tim::Ticks>,
+            {
+                let psc = psc(resolution.into().0)?;
+
+                $TIM::configure_pins(gpio, rcc);
+
+                self._init(psc, rcc);
+
+                Ok(())
+            }
+
+            fn _init(&self, psc: u16, rcc: &Rcc) {
+                let tim = self.0;
+
+                $TIM::enable(rcc);
+
+                // CCxS: ICx mapped on TIx, no prescaler, no filter
+                tim.ccmr1_input.write(|w| unsafe { w.bits(0) });
+                $(
+                    capture!(@input tim, $CH);
+                )+
+
+                // rising edge, captures disabled
+                tim.ccer.write(|w| unsafe { w.bits(0) });
+
+                tim.psc.write(|w| unsafe { w.psc().bits(psc) });
+
+                tim.arr.write(|w| unsafe { w.arr().bits(u16::max_value()) });
+
+                // load the prescaler
+                tim.egr.write(|w| unsafe { w.ug().bits(1) });
+
+                tim.cr1.write(|w| unsafe { w.cen().bits(1) });
+            }
+
+            /// Changes the resolution of the counter to `resolution` ticks
+            ///
+            /// Unlike `hal::Capture::set_resolution` this reports
+            /// resolutions the prescaler can't produce instead of clamping
+            /// them.
+            pub fn set_resolution<R>(
+                &self,
+                resolution: R,
+            ) -> Result<(), TimerError>
+            where
+                R: Into<::apb2::tim::Ticks>,
+            {
+                let psc = psc(resolution.into().0)?;
+
+                self.0.psc.write(|w| unsafe { w.psc().bits(psc) });
+
+                Ok(())
+            }
+        }
+
+        impl<'a> hal::Capture for Capture<'a, $TIM> {
+            type Capture = u16;
+            type Channel = $Channel;
+            type Error = Error;
+            type Time = ::apb2::tim::Ticks;
+
+            fn capture(&self, channel: $Channel) -> nb::Result<u16, Error> {
+                let tim = self.0;
+                let sr = tim.sr.read();
+
+                match channel {
+                    $(
+                        capture!(@ch $Channel, $CH) => {
+                            if capture!(@ccof sr, $CH) == 1 {
+                                tim.sr.modify(|_, w| unsafe {
+                                    capture!(@clear_ccof w, $CH)
+                                });
+                                Err(nb::Error::Other(Error::Overcapture))
+                            } else if capture!(@ccif sr, $CH) == 1 {
+                                // NOTE reading CCRx clears the CCxIF flag
+                                Ok(capture!(@ccr tim, $CH).read().bits() as u16)
+                            } else {
+                                Err(nb::Error::WouldBlock)
+                            }
+                        }
+                    )+
+                }
+            }
+
+            fn disable(&self, channel: $Channel) {
+                let ccer = &self.0.ccer;
+
+                match channel {
+                    $(
+                        capture!(@ch $Channel, $CH) => {
+                            ccer.modify(|_, w| unsafe { capture!(@cce w, $CH, 0) })
+                        }
+                    )+
+                }
+            }
+
+            fn enable(&self, channel: $Channel) {
+                let ccer = &self.0.ccer;
+
+                match channel {
+                    $(
+                        capture!(@ch $Channel, $CH) => {
+                            ccer.modify(|_, w| unsafe { capture!(@cce w, $CH, 1) })
+                        }
+                    )+
+                }
+            }
+
+            fn get_resolution(&self) -> ::apb2::tim::Ticks {
+                ::apb2::tim::Ticks(u32(self.0.psc.read().psc().bits()) + 1)
+            }
+
+            fn set_resolution<R>(&self, resolution: R)
+            where
+                R: Into<::apb2::tim::Ticks>,
+            {
+                let psc = psc_saturating(resolution.into().0);
+
+                self.0.psc.write(|w| unsafe { w.psc().bits(psc) });
+            }
+        }
+    };
+    (@input $tim:expr, _1) => {
+        $tim.ccmr1_input.modify(|_, w| unsafe { w.cc1s().bits(0b01) });
+    };
+    (@input $tim:expr, _2) => {
+        $tim.ccmr1_input.modify(|_, w| unsafe { w.cc2s().bits(0b01) });
+    };
+    (@ch $Channel:ident, _1) => { $Channel::_1 };
+    (@ch $Channel:ident, _2) => { $Channel::_2 };
+    (@cce $w:ident, _1, $bit:expr) => { $w.cc1e().bits($bit) };
+    (@cce $w:ident, _2, $bit:expr) => { $w.cc2e().bits($bit) };
+    (@ccif $sr:ident, _1) => { $sr.cc1if().bits() };
+    (@ccif $sr:ident, _2) => { $sr.cc2if().bits() };
+    (@ccof $sr:ident, _1) => { $sr.cc1of().bits() };
+    (@ccof $sr:ident, _2) => { $sr.cc2of().bits() };
+    (@clear_ccof $w:ident, _1) => { $w.cc1of().bits(0) };
+    (@clear_ccof $w:ident, _2) => { $w.cc2of().bits(0) };
+    (@ccr $tim:expr, _1) => { $tim.ccr1 };
+    (@ccr $tim:expr, _2) => { $tim.ccr2 };
+}
+
+capture!(Tim15, Tim15Channel, [_1, _2]);
+capture!(Tim16, Tim16Channel, [_1]);
+capture!(Tim17, Tim17Channel, [_1]);
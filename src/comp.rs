@@ -0,0 +1,481 @@
+//! Analog comparators (COMP1 - COMP7)
+//!
+//! Each comparator compares its non-inverting input (a pin) against its
+//! inverting input (a pin, a fraction of VREFINT or a DAC output). The output
+//! can drive the break inputs and the input captures of the advanced timers
+//! and it's routed to an EXTI line, which can raise interrupts or wake up
+//! the device from stop mode.
+//!
+//! NOTE the input pins must be configured in analog mode; this module doesn't
+//! configure them. See the reference manual for the pins of each comparator.
+
+use core::ptr;
+
+use stm32f30x::{Exti, Rcc, SyscfgCompOpamp};
+
+use power::{self, Edge, ExtiLine, WaitFor};
+
+// COMPx_CSR bits
+const EN: u32 = 1 << 0;
+const INMSEL: u32 = 0b111 << 4 | 1 << 22;
+const NONINSEL: u32 = 1 << 7;
+const WINMODE: u32 = 1 << 9;
+const OUTSEL: u32 = 0b1111 << 10;
+const POL: u32 = 1 << 15;
+const HYST: u32 = 0b11 << 16;
+const BLANKING: u32 = 0b111 << 18;
+const OUT: u32 = 1 << 30;
+const LOCK: u32 = 1 << 31;
+
+/// Comparator error
+#[derive(Debug)]
+pub enum Error {
+    /// The blanking source or the output isn't available on this comparator
+    Unavailable,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Comparator
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Comp {
+    /// COMP1
+    _1,
+    /// COMP2
+    _2,
+    /// COMP3
+    _3,
+    /// COMP4
+    _4,
+    /// COMP5
+    _5,
+    /// COMP6
+    _6,
+    /// COMP7
+    _7,
+}
+
+impl Comp {
+    fn index(&self) -> isize {
+        match *self {
+            Comp::_1 => 0,
+            Comp::_2 => 1,
+            Comp::_3 => 2,
+            Comp::_4 => 3,
+            Comp::_5 => 4,
+            Comp::_6 => 5,
+            Comp::_7 => 6,
+        }
+    }
+
+    /// EXTI line driven by the comparator output
+    pub fn exti_line(&self) -> ExtiLine {
+        ExtiLine(match *self {
+            Comp::_1 => 21,
+            Comp::_2 => 22,
+            Comp::_3 => 29,
+            Comp::_4 => 30,
+            Comp::_5 => 31,
+            Comp::_6 => 32,
+            Comp::_7 => 33,
+        })
+    }
+
+    /// Maps `blanking` to its BLANKING value, if available on this
+    /// comparator
+    fn blanking(&self, blanking: Blanking) -> Option<u32> {
+        use self::Blanking::*;
+
+        Some(match (*self, blanking) {
+            (Comp::_1, Tim1Oc5) | (Comp::_2, Tim1Oc5) => 0b001,
+            (Comp::_1, Tim2Oc3) | (Comp::_2, Tim2Oc3) => 0b010,
+            (Comp::_1, Tim3Oc3) | (Comp::_2, Tim3Oc3) => 0b011,
+            (Comp::_3, Tim1Oc5) => 0b001,
+            (Comp::_3, Tim2Oc4) => 0b011,
+            (Comp::_4, Tim3Oc4) => 0b001,
+            (Comp::_4, Tim8Oc5) => 0b010,
+            (Comp::_4, Tim15Oc1) => 0b011,
+            (Comp::_5, Tim8Oc5) => 0b010,
+            (Comp::_5, Tim3Oc3) => 0b011,
+            (Comp::_6, Tim8Oc5) => 0b010,
+            (Comp::_6, Tim2Oc4) => 0b011,
+            (Comp::_6, Tim15Oc2) => 0b100,
+            (Comp::_7, Tim1Oc5) => 0b001,
+            (Comp::_7, Tim8Oc5) => 0b010,
+            (Comp::_7, Tim15Oc2) => 0b100,
+            _ => return None,
+        })
+    }
+
+    /// Maps `output` to its OUTSEL value, if available on this comparator
+    fn outsel(&self, output: Output) -> Option<u32> {
+        use self::Output::*;
+
+        Some(match (*self, output) {
+            (_, None) => 0b0000,
+            (_, Tim1Break) => 0b0001,
+            (_, Tim1Break2) => 0b0010,
+            (_, Tim8Break) => 0b0011,
+            (_, Tim8Break2) => 0b0100,
+            (_, Tim1Tim8Break2) => 0b0101,
+            (Comp::_1, Tim1OcrefClear) | (Comp::_2, Tim1OcrefClear) => 0b0110,
+            (Comp::_1, Tim1Ic1) | (Comp::_2, Tim1Ic1) => 0b0111,
+            (Comp::_1, Tim2Ic4) | (Comp::_2, Tim2Ic4) => 0b1000,
+            (Comp::_1, Tim2OcrefClear) | (Comp::_2, Tim2OcrefClear) => 0b1001,
+            (Comp::_1, Tim3Ic1) | (Comp::_2, Tim3Ic1) => 0b1010,
+            (Comp::_1, Tim3OcrefClear) | (Comp::_2, Tim3OcrefClear) => 0b1011,
+            (Comp::_3, Tim1OcrefClear) => 0b0110,
+            (Comp::_3, Tim4Ic1) => 0b0111,
+            (Comp::_3, Tim3Ic2) => 0b1000,
+            (Comp::_3, Tim2OcrefClear) => 0b1001,
+            (Comp::_3, Tim15Ic1) => 0b1010,
+            (Comp::_3, Tim15Break) => 0b1011,
+            (Comp::_4, Tim3Ic3) => 0b0110,
+            (Comp::_4, Tim8OcrefClear) => 0b0111,
+            (Comp::_4, Tim15Ic2) => 0b1000,
+            (Comp::_4, Tim4Ic2) => 0b1001,
+            (Comp::_4, Tim15OcrefClear) => 0b1010,
+            (Comp::_4, Tim3OcrefClear) => 0b1011,
+            (Comp::_5, Tim2Ic1) => 0b0110,
+            (Comp::_5, Tim8OcrefClear) => 0b0111,
+            (Comp::_5, Tim17Ic1) => 0b1000,
+            (Comp::_5, Tim4Ic3) => 0b1001,
+            (Comp::_5, Tim16Break) => 0b1010,
+            (Comp::_5, Tim3OcrefClear) => 0b1011,
+            (Comp::_6, Tim2Ic2) => 0b0110,
+            (Comp::_6, Tim8OcrefClear) => 0b0111,
+            (Comp::_6, Tim2OcrefClear) => 0b1000,
+            (Comp::_6, Tim16OcrefClear) => 0b1001,
+            (Comp::_6, Tim16Ic1) => 0b1010,
+            (Comp::_6, Tim4Ic4) => 0b1011,
+            (Comp::_7, Tim1OcrefClear) => 0b0110,
+            (Comp::_7, Tim8OcrefClear) => 0b0111,
+            (Comp::_7, Tim2Ic3) => 0b1000,
+            (Comp::_7, Tim1Ic2) => 0b1001,
+            (Comp::_7, Tim17OcrefClear) => 0b1010,
+            (Comp::_7, Tim17Break) => 0b1011,
+            _ => return Option::None,
+        })
+    }
+}
+
+/// Inverting input
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Inverting {
+    /// 1/4 of VREFINT
+    QuarterVrefint,
+    /// 1/2 of VREFINT
+    HalfVrefint,
+    /// 3/4 of VREFINT
+    ThreeQuartersVrefint,
+    /// VREFINT
+    Vrefint,
+    /// DAC channel 1 output (PA4)
+    Dac1,
+    /// DAC channel 2 output (PA5)
+    Dac2,
+    /// First inverting input pin of the comparator
+    Pin1,
+    /// Second inverting input pin of the comparator
+    Pin2,
+}
+
+impl Inverting {
+    fn inmsel(&self) -> u32 {
+        let bits = match *self {
+            Inverting::QuarterVrefint => 0b000,
+            Inverting::HalfVrefint => 0b001,
+            Inverting::ThreeQuartersVrefint => 0b010,
+            Inverting::Vrefint => 0b011,
+            Inverting::Dac1 => 0b100,
+            Inverting::Dac2 => 0b101,
+            Inverting::Pin1 => 0b110,
+            Inverting::Pin2 => 0b111,
+        };
+
+        bits << 4
+    }
+}
+
+/// Non-inverting input
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NonInverting {
+    /// First non-inverting input pin of the comparator
+    Pin1,
+    /// Second non-inverting input pin of the comparator
+    Pin2,
+    /// Non-inverting input of the previous comparator (window mode)
+    ///
+    /// Only available on COMP2, COMP4 and COMP6
+    Window,
+}
+
+/// Hysteresis level
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Hysteresis {
+    /// No hysteresis
+    None,
+    /// Low hysteresis
+    Low,
+    /// Medium hysteresis
+    Medium,
+    /// High hysteresis
+    High,
+}
+
+/// Timer output compare signal that blanks the comparator output
+///
+/// NOTE each comparator supports only some of the sources; the comparators
+/// that support a source are listed in its documentation
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Blanking {
+    /// TIM1 OC5 (COMP1, COMP2, COMP3, COMP7)
+    Tim1Oc5,
+    /// TIM2 OC3 (COMP1, COMP2)
+    Tim2Oc3,
+    /// TIM2 OC4 (COMP3, COMP6)
+    Tim2Oc4,
+    /// TIM3 OC3 (COMP1, COMP2, COMP5)
+    Tim3Oc3,
+    /// TIM3 OC4 (COMP4)
+    Tim3Oc4,
+    /// TIM8 OC5 (COMP4, COMP5, COMP6, COMP7)
+    Tim8Oc5,
+    /// TIM15 OC1 (COMP4)
+    Tim15Oc1,
+    /// TIM15 OC2 (COMP6, COMP7)
+    Tim15Oc2,
+}
+
+/// Timer input driven by the comparator output
+///
+/// NOTE the break inputs are available on all the comparators; the other
+/// inputs only on the comparators listed in their documentation
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Output {
+    /// The output only drives the EXTI line
+    None,
+    /// TIM1 break input
+    Tim1Break,
+    /// TIM1 break input 2
+    Tim1Break2,
+    /// TIM8 break input
+    Tim8Break,
+    /// TIM8 break input 2
+    Tim8Break2,
+    /// TIM1 and TIM8 break inputs 2
+    Tim1Tim8Break2,
+    /// TIM1 OCREF_CLR input (COMP1, COMP2, COMP3, COMP7)
+    Tim1OcrefClear,
+    /// TIM1 input capture 1 (COMP1, COMP2)
+    Tim1Ic1,
+    /// TIM1 input capture 2 (COMP7)
+    Tim1Ic2,
+    /// TIM2 input capture 1 (COMP5)
+    Tim2Ic1,
+    /// TIM2 input capture 2 (COMP6)
+    Tim2Ic2,
+    /// TIM2 input capture 3 (COMP7)
+    Tim2Ic3,
+    /// TIM2 input capture 4 (COMP1, COMP2)
+    Tim2Ic4,
+    /// TIM2 OCREF_CLR input (COMP1, COMP2, COMP3, COMP6)
+    Tim2OcrefClear,
+    /// TIM3 input capture 1 (COMP1, COMP2)
+    Tim3Ic1,
+    /// TIM3 input capture 2 (COMP3)
+    Tim3Ic2,
+    /// TIM3 input capture 3 (COMP4)
+    Tim3Ic3,
+    /// TIM3 OCREF_CLR input (COMP1, COMP2, COMP4, COMP5)
+    Tim3OcrefClear,
+    /// TIM4 input capture 1 (COMP3)
+    Tim4Ic1,
+    /// TIM4 input capture 2 (COMP4)
+    Tim4Ic2,
+    /// TIM4 input capture 3 (COMP5)
+    Tim4Ic3,
+    /// TIM4 input capture 4 (COMP6)
+    Tim4Ic4,
+    /// TIM8 OCREF_CLR input (COMP4, COMP5, COMP6, COMP7)
+    Tim8OcrefClear,
+    /// TIM15 input capture 1 (COMP3)
+    Tim15Ic1,
+    /// TIM15 input capture 2 (COMP4)
+    Tim15Ic2,
+    /// TIM15 OCREF_CLR input (COMP4)
+    Tim15OcrefClear,
+    /// TIM15 break input (COMP3)
+    Tim15Break,
+    /// TIM16 input capture 1 (COMP6)
+    Tim16Ic1,
+    /// TIM16 OCREF_CLR input (COMP6)
+    Tim16OcrefClear,
+    /// TIM16 break input (COMP5)
+    Tim16Break,
+    /// TIM17 input capture 1 (COMP5)
+    Tim17Ic1,
+    /// TIM17 OCREF_CLR input (COMP7)
+    Tim17OcrefClear,
+    /// TIM17 break input (COMP7)
+    Tim17Break,
+}
+
+/// Comparator
+pub struct Comparator<'a>(pub &'a SyscfgCompOpamp, pub Comp);
+
+impl<'a> Clone for Comparator<'a> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a> Copy for Comparator<'a> {}
+
+impl<'a> Comparator<'a> {
+    /// Initializes and enables the comparator
+    ///
+    /// The comparator starts with no hysteresis, no blanking, a non-inverted
+    /// output that only drives the EXTI line.
+    ///
+    /// # Panics
+    ///
+    /// If `NonInverting::Window` is used with COMP1, COMP3, COMP5 or COMP7
+    pub fn init(
+        &self,
+        non_inverting: NonInverting,
+        inverting: Inverting,
+        rcc: &Rcc,
+    ) {
+        rcc.apb2enr.modify(|_, w| w.syscfgen().enabled());
+
+        let noninsel = match non_inverting {
+            NonInverting::Pin1 => 0,
+            NonInverting::Pin2 => NONINSEL,
+            NonInverting::Window => {
+                assert!(self.1.index() % 2 == 1);
+
+                WINMODE
+            }
+        };
+
+        self.write(noninsel | inverting.inmsel());
+        self.modify(|r| r | EN);
+    }
+
+    /// Disables the comparator
+    pub fn disable(&self) {
+        self.modify(|r| r & !EN);
+    }
+
+    /// Selects the inverting input
+    pub fn set_inverting(&self, inverting: Inverting) {
+        self.modify(|r| r & !INMSEL | inverting.inmsel());
+    }
+
+    /// Sets the hysteresis level
+    pub fn set_hysteresis(&self, hysteresis: Hysteresis) {
+        let hyst = match hysteresis {
+            Hysteresis::None => 0b00,
+            Hysteresis::Low => 0b01,
+            Hysteresis::Medium => 0b10,
+            Hysteresis::High => 0b11,
+        };
+
+        self.modify(|r| r & !HYST | hyst << 16);
+    }
+
+    /// Inverts the output polarity
+    pub fn set_inverted(&self, inverted: bool) {
+        self.modify(|r| if inverted { r | POL } else { r & !POL });
+    }
+
+    /// Selects the timer output compare signal that blanks the comparator
+    /// output
+    ///
+    /// `None` disables the blanking. Returns `Error::Unavailable` if this
+    /// comparator doesn't support `blanking`.
+    pub fn set_blanking(
+        &self,
+        blanking: Option<Blanking>,
+    ) -> Result<(), Error> {
+        let bits = match blanking {
+            Some(blanking) => {
+                self.1.blanking(blanking).ok_or(Error::Unavailable)?
+            }
+            None => 0,
+        };
+
+        self.modify(|r| r & !BLANKING | bits << 18);
+
+        Ok(())
+    }
+
+    /// Selects the timer input driven by the comparator output
+    ///
+    /// Returns `Error::Unavailable` if this comparator can't drive `output`.
+    pub fn set_output(&self, output: Output) -> Result<(), Error> {
+        let outsel = self.1.outsel(output).ok_or(Error::Unavailable)?;
+
+        self.modify(|r| r & !OUTSEL | outsel << 10);
+
+        Ok(())
+    }
+
+    /// Returns the output level, after the polarity selection
+    pub fn output(&self) -> bool {
+        self.read() & OUT != 0
+    }
+
+    /// Makes the comparator configuration read-only until the next reset
+    ///
+    /// Useful when the output drives a timer break input
+    pub fn lock(&self) {
+        self.modify(|r| r | LOCK);
+    }
+
+    /// Raises an interrupt on the given `edge` of the output
+    ///
+    /// NOTE the interrupt must also be enabled in the NVIC
+    pub fn listen(&self, edge: Edge, exti: &Exti) {
+        power::listen_exti(self.1.exti_line(), edge, WaitFor::Interrupt, exti)
+    }
+
+    /// Stops the output from raising interrupts and events
+    pub fn unlisten(&self, exti: &Exti) {
+        power::unlisten_exti(self.1.exti_line(), exti)
+    }
+
+    /// Checks whether the output raised an interrupt
+    pub fn is_pending(&self, exti: &Exti) -> bool {
+        power::is_exti_pending(self.1.exti_line(), exti)
+    }
+
+    /// Clears the pending interrupt
+    pub fn clear(&self, exti: &Exti) {
+        power::clear_exti(self.1.exti_line(), exti)
+    }
+
+    fn csr(&self) -> *mut u32 {
+        // NOTE COMP1_CSR - COMP7_CSR are contiguous
+        let comp1_csr = &self.0.comp1_csr as *const _ as *mut u32;
+
+        unsafe { comp1_csr.offset(self.1.index()) }
+    }
+
+    fn read(&self) -> u32 {
+        unsafe { ptr::read_volatile(self.csr()) }
+    }
+
+    fn write(&self, bits: u32) {
+        unsafe { ptr::write_volatile(self.csr(), bits) }
+    }
+
+    fn modify<F>(&self, f: F)
+    where
+        F: FnOnce(u32) -> u32,
+    {
+        let bits = self.read();
+        self.write(f(bits));
+    }
+}
@@ -0,0 +1,437 @@
+//! Digital to Analog Converter (DAC)
+//!
+//! - Channel 1 = PA4
+//! - Channel 2 = PA5
+//!
+//! NOTE PA4 and PA5 are also the NSS and SCK pins of SPI1; the DAC and SPI1
+//! can't be used at the same time.
+//!
+//! # Waveform playback
+//!
+//! The DMA can feed samples to a channel on every trigger event; use a timer
+//! TRGO to play them at an exact sample rate. The DAC DMA requests are
+//! served by DMA2 (channel 3 for the DAC channel 1 and channel 4 for the DAC
+//! channel 2), their default mapping.
+//!
+//! NOTE the `TIM6_DAC1_DMA_RMP` / `TIM7_DAC2_DMA_RMP` bits of
+//! `SYSCFG_CFGR1` must be left cleared; they'd move the requests to DMA1
+//! channels 3 and 4, which serve SPI1 TX and USART1 TX.
+//!
+//! ``` ignore
+//! // 44.1 KHz sample rate
+//! Timer(&tim6).init(44_100.hz().invert(), rcc).unwrap();
+//! Timer(&tim6).set_trigger_output(TriggerOutput::Update);
+//!
+//! let channels = Dma2Channels::take().unwrap();
+//!
+//! dac.init(Channel::_1, gpioa, rcc);
+//! dac.play1(dma2, &channels.ch3, rcc, WAVEFORM.borrow(), Trigger::Tim6Trgo)
+//!     .unwrap();
+//!
+//! Timer(&tim6).resume();
+//! ```
+
+use core::marker::Unsize;
+
+use static_ref::Ref;
+use stm32f30x::{Dma2, Gpioa, Rcc};
+
+use dma::{self, Buffer, DMA, Direction, Dma2Channel3, Dma2Channel4,
+          DmaChannel, Priority, Size};
+
+// CR bits, channel 1; the channel 2 bits are 16 positions higher
+const EN: u32 = 1 << 0;
+const BOFF: u32 = 1 << 1;
+const TEN: u32 = 1 << 2;
+const TSEL: u32 = 0b111 << 3;
+const WAVE: u32 = 0b11 << 6;
+const MAMP: u32 = 0b1111 << 8;
+const DMAEN: u32 = 1 << 12;
+
+// SR bits, channel 1
+const DMAUDR: u32 = 1 << 13;
+
+/// DAC channel
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Channel {
+    /// Channel 1, PA4
+    _1,
+    /// Channel 2, PA5
+    _2,
+}
+
+impl Channel {
+    fn shift(&self) -> u32 {
+        match *self {
+            Channel::_1 => 0,
+            Channel::_2 => 16,
+        }
+    }
+}
+
+/// Format of the written samples
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Alignment {
+    /// 12-bit samples in bits 0 - 11
+    Right12,
+    /// 12-bit samples in bits 4 - 15
+    Left12,
+    /// 8-bit samples; the DAC converts them as 12-bit `sample << 4`
+    Right8,
+}
+
+/// Conversion trigger
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Trigger {
+    /// TIM6 TRGO
+    Tim6Trgo,
+    /// TIM8 TRGO (TIM3 TRGO if remapped in the SYSCFG)
+    Tim8Trgo,
+    /// TIM7 TRGO
+    Tim7Trgo,
+    /// TIM15 TRGO
+    Tim15Trgo,
+    /// TIM2 TRGO
+    Tim2Trgo,
+    /// TIM4 TRGO
+    Tim4Trgo,
+    /// EXTI line 9
+    Exti9,
+    /// Software trigger, see `Dac::trigger`
+    Software,
+}
+
+impl Trigger {
+    fn tsel(&self) -> u32 {
+        match *self {
+            Trigger::Tim6Trgo => 0b000,
+            Trigger::Tim8Trgo => 0b001,
+            Trigger::Tim7Trgo => 0b010,
+            Trigger::Tim15Trgo => 0b011,
+            Trigger::Tim2Trgo => 0b100,
+            Trigger::Tim4Trgo => 0b101,
+            Trigger::Exti9 => 0b110,
+            Trigger::Software => 0b111,
+        }
+    }
+}
+
+/// Hardware waveform generation
+///
+/// The generator adds its output to the last written sample on every
+/// trigger event; waveforms require a trigger (see `Dac::set_trigger`).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Wave {
+    /// Waveform generation disabled
+    None,
+    /// Pseudo noise; `bits` (1 - 12) lowest bits of the LFSR are used
+    Noise {
+        /// Number of unmasked LFSR bits
+        bits: u8,
+    },
+    /// Triangle with an amplitude of `2^bits - 1` (`bits` in 1 - 12)
+    Triangle {
+        /// Amplitude, in bits
+        bits: u8,
+    },
+}
+
+/// Digital to Analog Converter
+pub struct Dac<'a>(pub &'a ::stm32f30x::Dac);
+
+impl<'a> Clone for Dac<'a> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a> Copy for Dac<'a> {}
+
+impl<'a> Dac<'a> {
+    /// Initializes and enables `channel`
+    ///
+    /// The channel starts with its output buffer enabled and converts every
+    /// written sample right away (no trigger).
+    pub fn init(&self, channel: Channel, gpioa: &Gpioa, rcc: &Rcc) {
+        let dac = self.0;
+
+        rcc.ahbenr.modify(|_, w| w.iopaen().enabled());
+        // DAC1EN
+        rcc.apb1enr.modify(|r, w| unsafe { w.bits(r.bits() | 1 << 29) });
+
+        // DAC_OUT1 = PA4 = Analog
+        // DAC_OUT2 = PA5 = Analog
+        match channel {
+            Channel::_1 => gpioa.moder.modify(|_, w| w.moder4().analog()),
+            Channel::_2 => gpioa.moder.modify(|_, w| w.moder5().analog()),
+        }
+
+        let shift = channel.shift();
+        let mask = (EN | BOFF | TEN | TSEL | WAVE | MAMP | DMAEN) << shift;
+        dac.cr.modify(|r, w| unsafe {
+            w.bits(r.bits() & !mask | EN << shift)
+        });
+    }
+
+    /// Disables `channel`
+    pub fn disable(&self, channel: Channel) {
+        let en = EN << channel.shift();
+
+        self.0.cr.modify(|r, w| unsafe { w.bits(r.bits() & !en) });
+    }
+
+    /// Enables or disables the output buffer of `channel`
+    ///
+    /// The buffer reduces the output impedance so the DAC can drive
+    /// external loads without an external op-amp.
+    pub fn set_buffer(&self, channel: Channel, enabled: bool) {
+        let boff = BOFF << channel.shift();
+
+        self.0.cr.modify(|r, w| unsafe {
+            w.bits(if enabled {
+                r.bits() & !boff
+            } else {
+                r.bits() | boff
+            })
+        });
+    }
+
+    /// Selects the conversion trigger of `channel`
+    ///
+    /// With `None` every written sample is converted right away; otherwise
+    /// written samples are converted on the next trigger event.
+    pub fn set_trigger(&self, channel: Channel, trigger: Option<Trigger>) {
+        let shift = channel.shift();
+
+        let bits = match trigger {
+            Some(trigger) => TEN | trigger.tsel() << 3,
+            None => 0,
+        };
+
+        self.0.cr.modify(|r, w| unsafe {
+            w.bits(r.bits() & !((TEN | TSEL) << shift) | bits << shift)
+        });
+    }
+
+    /// Triggers a conversion of `channel` (with `Trigger::Software`)
+    pub fn trigger(&self, channel: Channel) {
+        let bit = match channel {
+            Channel::_1 => 1 << 0,
+            Channel::_2 => 1 << 1,
+        };
+
+        self.0.swtrigr.write(|w| unsafe { w.bits(bit) });
+    }
+
+    /// Selects the hardware waveform generated by `channel`
+    ///
+    /// # Panics
+    ///
+    /// If the number of bits is out of range
+    pub fn set_wave(&self, channel: Channel, wave: Wave) {
+        let shift = channel.shift();
+
+        let bits = match wave {
+            Wave::None => 0,
+            Wave::Noise { bits } => {
+                assert!(bits >= 1 && bits <= 12);
+
+                0b01 << 6 | u32::from(bits - 1) << 8
+            }
+            Wave::Triangle { bits } => {
+                assert!(bits >= 1 && bits <= 12);
+
+                0b10 << 6 | u32::from(bits - 1) << 8
+            }
+        };
+
+        self.0.cr.modify(|r, w| unsafe {
+            w.bits(r.bits() & !((WAVE | MAMP) << shift) | bits << shift)
+        });
+    }
+
+    /// Writes a `sample` to `channel`
+    pub fn write(&self, channel: Channel, sample: u16, alignment: Alignment) {
+        let dac = self.0;
+        let sample = u32::from(sample);
+
+        match (channel, alignment) {
+            (Channel::_1, Alignment::Right12) => {
+                dac.dhr12r1.write(|w| unsafe { w.bits(sample) })
+            }
+            (Channel::_1, Alignment::Left12) => {
+                dac.dhr12l1.write(|w| unsafe { w.bits(sample) })
+            }
+            (Channel::_1, Alignment::Right8) => {
+                dac.dhr8r1.write(|w| unsafe { w.bits(sample) })
+            }
+            (Channel::_2, Alignment::Right12) => {
+                dac.dhr12r2.write(|w| unsafe { w.bits(sample) })
+            }
+            (Channel::_2, Alignment::Left12) => {
+                dac.dhr12l2.write(|w| unsafe { w.bits(sample) })
+            }
+            (Channel::_2, Alignment::Right8) => {
+                dac.dhr8r2.write(|w| unsafe { w.bits(sample) })
+            }
+        }
+    }
+
+    /// Writes a sample to each channel at the same time
+    pub fn write_both(&self, ch1: u16, ch2: u16, alignment: Alignment) {
+        let dac = self.0;
+
+        match alignment {
+            Alignment::Right12 => dac.dhr12rd.write(|w| unsafe {
+                w.bits(u32::from(ch2) << 16 | u32::from(ch1))
+            }),
+            Alignment::Left12 => dac.dhr12ld.write(|w| unsafe {
+                w.bits(u32::from(ch2) << 16 | u32::from(ch1))
+            }),
+            Alignment::Right8 => dac.dhr8rd.write(|w| unsafe {
+                w.bits(u32::from(ch2) << 8 | u32::from(ch1))
+            }),
+        }
+    }
+
+    /// Returns the 12-bit value `channel` is currently converting
+    pub fn output(&self, channel: Channel) -> u16 {
+        match channel {
+            Channel::_1 => self.0.dor1.read().bits() as u16,
+            Channel::_2 => self.0.dor2.read().bits() as u16,
+        }
+    }
+
+    /// Checks whether the DMA couldn't keep up with the trigger of `channel`
+    ///
+    /// NOTE this also clears the underrun flag
+    pub fn has_underrun(&self, channel: Channel) -> bool {
+        let dmaudr = DMAUDR << channel.shift();
+
+        if self.0.sr.read().bits() & dmaudr != 0 {
+            // NOTE the flag is cleared by writing 1
+            self.0.sr.write(|w| unsafe { w.bits(dmaudr) });
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Plays `buffer` in a loop on channel 1
+    ///
+    /// A 12-bit right aligned sample is sent to the DAC on every `trigger`
+    /// event; the DMA wraps around to the start of `buffer` after the last
+    /// sample. This will immutably lock `buffer`. Call `stop` and then
+    /// `Buffer::abort` to end the playback and unlock `buffer`.
+    ///
+    /// NOTE `Buffer::release` is not suited for this: it only returns once
+    /// `buffer` has been played in full, which never happens if `stop` is
+    /// called before the end of the first pass
+    pub fn play1<B>(
+        &self,
+        dma2: &Dma2,
+        _channel: &Dma2Channel3,
+        rcc: &Rcc,
+        buffer: Ref<Buffer<B, Dma2Channel3>>,
+        trigger: Trigger,
+    ) -> ::core::result::Result<(), dma::Error>
+    where
+        B: Unsize<[u16]>,
+    {
+        if Dma2Channel3::is_enabled(dma2) {
+            return Err(dma::Error::InUse);
+        }
+
+        let len = dma::transfer_len(buffer.len::<u16>())?;
+
+        Dma2::enable(rcc);
+
+        let buffer: &[u16] = buffer.lock()?;
+
+        self._play::<Dma2Channel3>(
+            dma2,
+            &self.0.dhr12r1 as *const _ as u32,
+            buffer.as_ptr() as u32,
+            len,
+        );
+        self.enable_dma(Channel::_1, trigger);
+
+        Ok(())
+    }
+
+    /// Plays `buffer` in a loop on channel 2
+    ///
+    /// See `play1`
+    pub fn play2<B>(
+        &self,
+        dma2: &Dma2,
+        _channel: &Dma2Channel4,
+        rcc: &Rcc,
+        buffer: Ref<Buffer<B, Dma2Channel4>>,
+        trigger: Trigger,
+    ) -> ::core::result::Result<(), dma::Error>
+    where
+        B: Unsize<[u16]>,
+    {
+        if Dma2Channel4::is_enabled(dma2) {
+            return Err(dma::Error::InUse);
+        }
+
+        let len = dma::transfer_len(buffer.len::<u16>())?;
+
+        Dma2::enable(rcc);
+
+        let buffer: &[u16] = buffer.lock()?;
+
+        self._play::<Dma2Channel4>(
+            dma2,
+            &self.0.dhr12r2 as *const _ as u32,
+            buffer.as_ptr() as u32,
+            len,
+        );
+        self.enable_dma(Channel::_2, trigger);
+
+        Ok(())
+    }
+
+    /// Stops requesting samples from the DMA on `channel`
+    ///
+    /// The DMA channel keeps `buffer` locked; see `play1`.
+    pub fn stop(&self, channel: Channel) {
+        let dmaen = DMAEN << channel.shift();
+
+        self.0.cr.modify(|r, w| unsafe { w.bits(r.bits() & !dmaen) });
+    }
+
+    fn _play<C>(&self, dma2: &Dma2, dhr: u32, address: u32, len: u16)
+    where
+        C: DmaChannel<Dma = Dma2>,
+    {
+        // 16-bit samples written to a 32-bit data holding register
+        C::configure(
+            dma2,
+            &dma::Config {
+                priority: Priority::High,
+                peripheral_size: Size::Word,
+                circular: true,
+                ..dma::Config::peripheral(
+                    Direction::MemoryToPeripheral,
+                    Size::HalfWord,
+                )
+            },
+        );
+        C::start(dma2, dhr, address, len);
+    }
+
+    fn enable_dma(&self, channel: Channel, trigger: Trigger) {
+        let shift = channel.shift();
+
+        self.0.sr.write(|w| unsafe { w.bits(DMAUDR << shift) });
+        self.0.cr.modify(|r, w| unsafe {
+            w.bits(
+                r.bits() & !((TSEL | WAVE) << shift) |
+                    (DMAEN | TEN | trigger.tsel() << 3) << shift,
+            )
+        });
+    }
+}
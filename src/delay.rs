@@ -0,0 +1,90 @@
+//! Blocking delays
+//!
+//! `Delay` uses the SysTick timer, clocked from the core clock
+//! (`ahb::FREQUENCY`), to busy wait. Long delays are split in several SysTick
+//! periods so any `u32` number of milliseconds or microseconds can be
+//! requested.
+//!
+//! `Delay` implements the `embedded-hal` `DelayMs` and `DelayUs` traits.
+
+use cortex_m::peripheral::{Syst, SystClkSource};
+use hal::blocking::delay::{DelayMs, DelayUs};
+
+const FREQUENCY: u64 = ::ahb::FREQUENCY as u64;
+
+/// Maximum value of the 24-bit SysTick reload register
+const RVR_MAX: u64 = (1 << 24) - 1;
+
+/// SysTick based delay provider
+pub struct Delay<'a>(pub &'a Syst);
+
+impl<'a> Delay<'a> {
+    /// Configures SysTick to be clocked from the core clock
+    ///
+    /// NOTE SysTick can't be used for anything else while it's being used as
+    /// a delay provider
+    pub fn init(&self) {
+        let syst = self.0;
+
+        syst.disable_counter();
+        syst.disable_interrupt();
+        syst.set_clock_source(SystClkSource::Core);
+    }
+
+    // busy waits for at least `ticks` core clock cycles
+    fn delay_ticks(&self, mut ticks: u64) {
+        let syst = self.0;
+
+        while ticks != 0 {
+            let chunk = if ticks > RVR_MAX { RVR_MAX } else { ticks };
+
+            // NOTE a reload value of 0 would stop the counter
+            syst.set_reload(if chunk > 1 { chunk as u32 - 1 } else { 1 });
+            syst.clear_current();
+            syst.enable_counter();
+
+            while !syst.has_wrapped() {}
+
+            syst.disable_counter();
+
+            ticks -= chunk;
+        }
+    }
+}
+
+impl<'a> DelayMs<u32> for Delay<'a> {
+    fn delay_ms(&mut self, ms: u32) {
+        self.delay_ticks(u64::from(ms) * FREQUENCY / 1_000)
+    }
+}
+
+impl<'a> DelayMs<u16> for Delay<'a> {
+    fn delay_ms(&mut self, ms: u16) {
+        self.delay_ms(u32::from(ms))
+    }
+}
+
+impl<'a> DelayMs<u8> for Delay<'a> {
+    fn delay_ms(&mut self, ms: u8) {
+        self.delay_ms(u32::from(ms))
+    }
+}
+
+impl<'a> DelayUs<u32> for Delay<'a> {
+    fn delay_us(&mut self, us: u32) {
+        // round up: the delay must not be shorter than requested
+        self.delay_ticks((u64::from(us) * FREQUENCY + 999_999) / 1_000_000)
+    }
+}
+
+impl<'a> DelayUs<u16> for Delay<'a> {
+    fn delay_us(&mut self, us: u16) {
+        self.delay_us(u32::from(us))
+    }
+}
+
+impl<'a> DelayUs<u8> for Delay<'a> {
+    fn delay_us(&mut self, us: u8) {
+        self.delay_us(u32::from(us))
+    }
+}
@@ -1,36 +1,520 @@
 //! Direct Memory Access (DMA)
+//!
+//! - DMA1 = 7 channels
+//! - DMA2 = 5 channels
+//!
+//! Each channel is represented by a marker type (e.g. `Dma1Channel4`) that
+//! implements `DmaChannel`. `Buffer`s and `CircBuffer`s are tied to a channel
+//! through their `CHANNEL` type parameter.
+//!
+//! Any channel can also move data between two memory buffers without CPU
+//! intervention, see `copy` and `fill`.
+//!
+//! # Owned transfers
+//!
+//! `OwnedTransfer` is an alternative to the `Buffer` API that checks its
+//! invariants at compile time: starting a transfer takes ownership of a
+//! channel (see `Dma1Channels::take`) and of a `&'static mut` buffer, and
+//! `OwnedTransfer::wait` hands both back once the DMA is done with the
+//! buffer.
+//!
+//! The `Buffer` entry points (e.g. `copy`) take a reference to the channel,
+//! so they can't be used while an `OwnedTransfer` owns it.
+//!
+//! ``` ignore
+//! static mut BUFFER: [u8; 16] = [0; 16];
+//!
+//! let channels = Dma1Channels::take().unwrap();
+//! let transfer = serial
+//!     .receive(dma1, channels.ch5, unsafe { &mut BUFFER })
+//!     .ok()
+//!     .unwrap();
+//!
+//! // .. do something else ..
+//!
+//! let (buffer, ch5) = transfer.wait(dma1).ok().unwrap();
+//! ```
+//!
+//! # Interrupts
+//!
+//! Each channel has its own interrupt; enable its events with
+//! `DmaChannel::listen`. The interrupt handler must call
+//! `DmaChannel::on_interrupt`, which clears the flags that fired and returns
+//! them. The cleared flags are still seen by the main loop, so a transfer
+//! started from the main loop can be `release`d (or `wait`ed on) as usual:
+//!
+//! ``` ignore
+//! fn dma1_channel5(dma1: &Dma1) {
+//!     let events = Dma1Channel5::on_interrupt(dma1);
+//!
+//!     if events.transfer_complete {
+//!         // wake up the main loop
+//!     }
+//! }
+//! ```
 
 use core::cell::{Cell, UnsafeCell};
-use core::marker::PhantomData;
-use core::ops;
+use core::marker::{PhantomData, Unsize};
+use core::ops::{self, Deref};
+use core::sync::atomic::{self, AtomicBool, AtomicUsize, Ordering};
 
+use cast::u16;
 use nb;
-use stm32f30x::Dma1;
+use static_ref;
+use stm32f30x::{Dma1, Dma2, Rcc, dma1};
+
+// ISR / IFCR flags of a channel, relative to the channel position
+const GIF: u32 = 1 << 0;
+const TCIF: u32 = 1 << 1;
+const HTIF: u32 = 1 << 2;
+const TEIF: u32 = 1 << 3;
+
+// CCR bits
+const EN: u32 = 1 << 0;
+const TCIE: u32 = 1 << 1;
+const HTIE: u32 = 1 << 2;
+const TEIE: u32 = 1 << 3;
+const DIR: u32 = 1 << 4;
+const CIRC: u32 = 1 << 5;
+const PINC: u32 = 1 << 6;
+const MINC: u32 = 1 << 7;
+const MEM2MEM: u32 = 1 << 14;
 
 /// DMA error
 #[derive(Debug)]
 pub enum Error {
     /// DMA channel in use
     InUse,
+    /// The DMA overwrote (or re-sent) a half of a `CircBuffer` before the
+    /// CPU was done with it
+    Overrun,
     /// Transfer error
     Transfer,
+    /// The buffer holds more items than a channel can transfer (`u16::MAX`)
+    TooLong,
+    /// The buffers of the transfer don't have the same length
+    LengthMismatch,
+}
+
+/// Channel priority
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Priority {
+    /// Low
+    Low,
+    /// Medium
+    Medium,
+    /// High
+    High,
+    /// Very high
+    VeryHigh,
 }
 
-/// Channel 2 of DMA1
-pub struct Dma1Channel2 {
-    _0: (),
+/// Size of the transferred items
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Size {
+    /// 8 bits
+    Byte,
+    /// 16 bits
+    HalfWord,
+    /// 32 bits
+    Word,
 }
 
-/// Channel 4 of DMA1
-pub struct Dma1Channel4 {
-    _0: (),
+impl Size {
+    fn bits(&self) -> u32 {
+        match *self {
+            Size::Byte => 0b00,
+            Size::HalfWord => 0b01,
+            Size::Word => 0b10,
+        }
+    }
 }
 
-/// Channel 5 of DMA1
-pub struct Dma1Channel5 {
-    _0: (),
+/// Transfer direction
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Direction {
+    /// Peripheral to memory
+    PeripheralToMemory,
+    /// Memory to peripheral
+    MemoryToPeripheral,
+    /// Memory to memory; the peripheral address is the source and the
+    /// memory address is the destination
+    MemoryToMemory,
+}
+
+/// Channel configuration
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Config {
+    /// Priority of the channel requests
+    pub priority: Priority,
+    /// Size of the items in memory
+    pub memory_size: Size,
+    /// Size of the peripheral register
+    pub peripheral_size: Size,
+    /// Increment the memory address after each item
+    pub memory_increment: bool,
+    /// Increment the peripheral address after each item
+    pub peripheral_increment: bool,
+    /// Transfer direction
+    pub direction: Direction,
+    /// Restart the transfer from the beginning once it completes
+    pub circular: bool,
+    /// Raise an interrupt when the transfer completes
+    pub transfer_complete_interrupt: bool,
+    /// Raise an interrupt when half of the transfer is done
+    pub half_transfer_interrupt: bool,
+    /// Raise an interrupt on transfer errors
+    pub transfer_error_interrupt: bool,
+}
+
+impl Config {
+    /// Configuration for non-circular transfers between a peripheral
+    /// register and a memory buffer of the same item `size`
+    ///
+    /// The transfer complete interrupt is enabled.
+    pub fn peripheral(direction: Direction, size: Size) -> Self {
+        Config {
+            priority: Priority::Medium,
+            memory_size: size,
+            peripheral_size: size,
+            memory_increment: true,
+            peripheral_increment: false,
+            direction: direction,
+            circular: false,
+            transfer_complete_interrupt: true,
+            half_transfer_interrupt: false,
+            transfer_error_interrupt: false,
+        }
+    }
+
+    fn ccr(&self) -> u32 {
+        let pl = match self.priority {
+            Priority::Low => 0b00,
+            Priority::Medium => 0b01,
+            Priority::High => 0b10,
+            Priority::VeryHigh => 0b11,
+        };
+
+        let mut ccr = pl << 12 | self.memory_size.bits() << 10 |
+            self.peripheral_size.bits() << 8;
+
+        if self.memory_increment {
+            ccr |= MINC;
+        }
+        if self.peripheral_increment {
+            ccr |= PINC;
+        }
+        match self.direction {
+            Direction::PeripheralToMemory => {}
+            Direction::MemoryToPeripheral => ccr |= DIR,
+            Direction::MemoryToMemory => ccr |= MEM2MEM,
+        }
+        if self.circular {
+            ccr |= CIRC;
+        }
+        if self.transfer_complete_interrupt {
+            ccr |= TCIE;
+        }
+        if self.half_transfer_interrupt {
+            ccr |= HTIE;
+        }
+        if self.transfer_error_interrupt {
+            ccr |= TEIE;
+        }
+
+        ccr
+    }
+}
+
+/// Item that can be transferred by the DMA
+///
+/// IMPLEMENTATION DETAIL. Do not implement this trait
+pub unsafe trait Word: Copy {
+    /// IMPLEMENTATION DETAIL
+    fn size() -> Size;
+}
+
+unsafe impl Word for u8 {
+    fn size() -> Size {
+        Size::Byte
+    }
+}
+
+unsafe impl Word for u16 {
+    fn size() -> Size {
+        Size::HalfWord
+    }
 }
 
+unsafe impl Word for u32 {
+    fn size() -> Size {
+        Size::Word
+    }
+}
+
+/// Channel interrupt event
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// Transfer complete
+    TransferComplete,
+    /// Half of the transfer done
+    HalfTransfer,
+    /// Transfer error
+    TransferError,
+}
+
+impl Event {
+    fn ccr(&self) -> u32 {
+        match *self {
+            Event::TransferComplete => TCIE,
+            Event::HalfTransfer => HTIE,
+            Event::TransferError => TEIE,
+        }
+    }
+}
+
+/// Events that fired on a channel
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Events {
+    /// Transfer complete
+    pub transfer_complete: bool,
+    /// Half of the transfer done
+    pub half_transfer: bool,
+    /// Transfer error
+    pub transfer_error: bool,
+}
+
+/// Flags cleared by `DmaChannel::on_interrupt`, in the ISR layout
+static DMA1_PENDING: AtomicUsize = AtomicUsize::new(0);
+static DMA2_PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// IMPLEMENTATION DETAIL
+pub unsafe trait DMA: Deref<Target = dma1::RegisterBlock> {
+    /// IMPLEMENTATION DETAIL
+    fn enable(rcc: &Rcc);
+    /// IMPLEMENTATION DETAIL
+    fn pending() -> &'static AtomicUsize;
+}
+
+unsafe impl DMA for Dma1 {
+    fn enable(rcc: &Rcc) {
+        rcc.ahbenr.modify(|_, w| w.dmaen().enabled());
+    }
+
+    fn pending() -> &'static AtomicUsize {
+        &DMA1_PENDING
+    }
+}
+
+unsafe impl DMA for Dma2 {
+    fn enable(rcc: &Rcc) {
+        rcc.ahbenr.modify(|_, w| w.dma2en().enabled());
+    }
+
+    fn pending() -> &'static AtomicUsize {
+        &DMA2_PENDING
+    }
+}
+
+/// A DMA channel
+///
+/// IMPLEMENTATION DETAIL. Do not implement this trait
+pub unsafe trait DmaChannel {
+    /// DMA controller this channel belongs to
+    type Dma: DMA;
+
+    /// IMPLEMENTATION DETAIL
+    fn number() -> u8;
+
+    /// Configures the channel
+    ///
+    /// NOTE this also disables the channel
+    fn configure(dma: &Self::Dma, config: &Config);
+
+    /// Starts a transfer of `len` items between the `peripheral` and
+    /// `memory` addresses
+    ///
+    /// See `transfer_len` to validate the length of a buffer
+    fn start(dma: &Self::Dma, peripheral: u32, memory: u32, len: u16);
+
+    /// Disables the channel
+    fn stop(dma: &Self::Dma);
+
+    /// Checks whether the channel is enabled
+    fn is_enabled(dma: &Self::Dma) -> bool;
+
+    /// Returns the number of items that remain to be transferred
+    fn remaining(dma: &Self::Dma) -> u16;
+
+    /// Enables the interrupt of `event`
+    fn listen(dma: &Self::Dma, event: Event);
+
+    /// Disables the interrupt of `event`
+    fn unlisten(dma: &Self::Dma, event: Event);
+
+    /// Identifies and clears the events that fired on this channel
+    ///
+    /// Call this from the channel interrupt handler. The cleared flags are
+    /// recorded so that `Buffer::release`, `CircBuffer::read` / `write` and
+    /// `OwnedTransfer::wait` still observe them from the main loop.
+    fn on_interrupt(dma: &Self::Dma) -> Events {
+        let shift = 4 * u32::from(Self::number() - 1);
+
+        // NOTE GIF is left out: CGIF clears all the flags of the channel,
+        // including the ones that fire between the read and the write
+        let flags = (dma.isr.read().bits() >> shift) & (TCIF | HTIF | TEIF);
+
+        // NOTE the flags are recorded before being cleared so that the main
+        // loop never misses them
+        Self::Dma::pending()
+            .fetch_or((flags << shift) as usize, Ordering::SeqCst);
+        dma.ifcr.write(|w| unsafe { w.bits(flags << shift) });
+
+        Events {
+            transfer_complete: flags & TCIF != 0,
+            half_transfer: flags & HTIF != 0,
+            transfer_error: flags & TEIF != 0,
+        }
+    }
+
+    /// IMPLEMENTATION DETAIL
+    fn flags(dma: &Self::Dma) -> u32 {
+        let shift = 4 * u32::from(Self::number() - 1);
+
+        let pending = Self::Dma::pending().load(Ordering::SeqCst) as u32;
+
+        ((dma.isr.read().bits() | pending) >> shift) & 0b1111
+    }
+
+    /// IMPLEMENTATION DETAIL
+    fn clear_flags(dma: &Self::Dma, flags: u32) {
+        let shift = 4 * u32::from(Self::number() - 1);
+
+        // NOTE the flags are cleared by writing 1
+        dma.ifcr.write(|w| unsafe { w.bits(flags << shift) });
+        Self::Dma::pending()
+            .fetch_and(!((flags << shift) as usize), Ordering::SeqCst);
+    }
+}
+
+macro_rules! channels {
+    ($DMA:ident, $Channels:ident, $TAKEN:ident, $channels_doc:expr, {
+        $($CHANNEL:ident: ($doc:expr, $ch:ident, $i:expr, $ccr:ident,
+                           $cndtr:ident, $cpar:ident, $cmar:ident),)+
+    }) => {
+        static $TAKEN: AtomicBool = AtomicBool::new(false);
+
+        #[doc = $channels_doc]
+        pub struct $Channels {
+            $(
+                #[doc = $doc]
+                pub $ch: $CHANNEL,
+            )+
+        }
+
+        impl $Channels {
+            /// Takes ownership of the channels
+            ///
+            /// Returns `None` if the channels have already been taken
+            pub fn take() -> Option<Self> {
+                if $TAKEN.swap(true, Ordering::SeqCst) {
+                    None
+                } else {
+                    Some($Channels {
+                        $(
+                            $ch: $CHANNEL { _0: () },
+                        )+
+                    })
+                }
+            }
+        }
+
+        $(
+            #[doc = $doc]
+            pub struct $CHANNEL {
+                _0: (),
+            }
+
+            unsafe impl DmaChannel for $CHANNEL {
+                type Dma = $DMA;
+
+                fn number() -> u8 {
+                    $i
+                }
+
+                fn configure(dma: &$DMA, config: &Config) {
+                    let ccr = config.ccr();
+
+                    dma.$ccr.write(|w| unsafe { w.bits(ccr) });
+                }
+
+                fn start(dma: &$DMA, peripheral: u32, memory: u32, len: u16) {
+                    dma.$cndtr.write(|w| unsafe { w.ndt().bits(len) });
+                    dma.$cpar.write(|w| unsafe { w.bits(peripheral) });
+                    dma.$cmar.write(|w| unsafe { w.bits(memory) });
+
+                    // discard the flags of the previous transfer
+                    Self::clear_flags(dma, GIF | TCIF | HTIF | TEIF);
+
+                    // NOTE(compiler_fence) the CPU writes to the buffer must
+                    // not be reordered after the start of the transfer
+                    atomic::compiler_fence(Ordering::SeqCst);
+
+                    dma.$ccr.modify(|r, w| unsafe { w.bits(r.bits() | EN) });
+                }
+
+                fn stop(dma: &$DMA) {
+                    dma.$ccr.modify(|r, w| unsafe { w.bits(r.bits() & !EN) });
+                }
+
+                fn is_enabled(dma: &$DMA) -> bool {
+                    dma.$ccr.read().bits() & EN != 0
+                }
+
+                fn remaining(dma: &$DMA) -> u16 {
+                    dma.$cndtr.read().ndt().bits()
+                }
+
+                fn listen(dma: &$DMA, event: Event) {
+                    let bit = event.ccr();
+
+                    dma.$ccr.modify(|r, w| unsafe { w.bits(r.bits() | bit) });
+                }
+
+                fn unlisten(dma: &$DMA, event: Event) {
+                    let bit = event.ccr();
+
+                    dma.$ccr.modify(|r, w| unsafe { w.bits(r.bits() & !bit) });
+                }
+            }
+        )+
+    }
+}
+
+/// Checks that a channel can transfer `len` items and returns the value of
+/// the NDT field
+pub(crate) fn transfer_len(len: usize) -> Result<u16, Error> {
+    u16(len).map_err(|_| Error::TooLong)
+}
+
+channels!(Dma1, Dma1Channels, DMA1_TAKEN, "Channels of DMA1", {
+    Dma1Channel1: ("Channel 1 of DMA1", ch1, 1, ccr1, cndtr1, cpar1, cmar1),
+    Dma1Channel2: ("Channel 2 of DMA1", ch2, 2, ccr2, cndtr2, cpar2, cmar2),
+    Dma1Channel3: ("Channel 3 of DMA1", ch3, 3, ccr3, cndtr3, cpar3, cmar3),
+    Dma1Channel4: ("Channel 4 of DMA1", ch4, 4, ccr4, cndtr4, cpar4, cmar4),
+    Dma1Channel5: ("Channel 5 of DMA1", ch5, 5, ccr5, cndtr5, cpar5, cmar5),
+    Dma1Channel6: ("Channel 6 of DMA1", ch6, 6, ccr6, cndtr6, cpar6, cmar6),
+    Dma1Channel7: ("Channel 7 of DMA1", ch7, 7, ccr7, cndtr7, cpar7, cmar7),
+});
+
+channels!(Dma2, Dma2Channels, DMA2_TAKEN, "Channels of DMA2", {
+    Dma2Channel1: ("Channel 1 of DMA2", ch1, 1, ccr1, cndtr1, cpar1, cmar1),
+    Dma2Channel2: ("Channel 2 of DMA2", ch2, 2, ccr2, cndtr2, cpar2, cmar2),
+    Dma2Channel3: ("Channel 3 of DMA2", ch3, 3, ccr3, cndtr3, cpar3, cmar3),
+    Dma2Channel4: ("Channel 4 of DMA2", ch4, 4, ccr4, cndtr4, cpar4, cmar4),
+    Dma2Channel5: ("Channel 5 of DMA2", ch5, 5, ccr5, cndtr5, cpar5, cmar5),
+});
+
 /// Buffer to be used with a certain DMA `CHANNEL`
 pub struct Buffer<T, CHANNEL> {
     _marker: PhantomData<CHANNEL>,
@@ -152,24 +636,50 @@ impl<T, CHANNEL> Buffer<T, CHANNEL> {
         }
     }
 
-    pub(crate) fn lock(&self) -> &T {
-        assert_eq!(self.status.get(), Status::Unlocked);
-        assert_ne!(self.flag.get(), WRITING);
+    /// Returns the number of `W` items in the buffer
+    ///
+    /// NOTE the length is part of the type `T`; the contents are not read so
+    /// this can be called while the DMA owns the buffer
+    pub(crate) fn len<W>(&self) -> usize
+    where
+        T: Unsize<[W]>,
+    {
+        let data: *const [W] = self.data.get() as *const T;
+
+        unsafe { (*data).len() }
+    }
+
+    /// Locks the buffer for a transfer that reads it
+    ///
+    /// Returns `Error::InUse` if the buffer is already locked or mutably
+    /// borrowed
+    pub(crate) fn lock(&self) -> Result<&T, Error> {
+        if self.status.get() != Status::Unlocked ||
+            self.flag.get() == WRITING
+        {
+            return Err(Error::InUse);
+        }
 
         self.flag.set(self.flag.get() + 1);
         self.status.set(Status::Locked);
 
-        unsafe { &*self.data.get() }
+        Ok(unsafe { &*self.data.get() })
     }
 
-    pub(crate) fn lock_mut(&self) -> &mut T {
-        assert_eq!(self.status.get(), Status::Unlocked);
-        assert_eq!(self.flag.get(), UNUSED);
+    /// Locks the buffer for a transfer that writes it
+    ///
+    /// Returns `Error::InUse` if the buffer is already locked or borrowed
+    pub(crate) fn lock_mut(&self) -> Result<&mut T, Error> {
+        if self.status.get() != Status::Unlocked ||
+            self.flag.get() != UNUSED
+        {
+            return Err(Error::InUse);
+        }
 
         self.flag.set(WRITING);
         self.status.set(Status::MutLocked);
 
-        unsafe { &mut *self.data.get() }
+        Ok(unsafe { &mut *self.data.get() })
     }
 
     unsafe fn unlock(&self, status: Status) {
@@ -183,69 +693,570 @@ impl<T, CHANNEL> Buffer<T, CHANNEL> {
     }
 }
 
-// FIXME these `release` methods probably want some of sort of barrier
-impl<T> Buffer<T, Dma1Channel2> {
-    /// Waits until the DMA releases this buffer
-    pub fn release(&self, dma1: &Dma1) -> nb::Result<(), Error> {
-        let status = self.status.get();
+/// Circular buffer to be used with a certain DMA `CHANNEL`
+///
+/// The DMA works on one half of the buffer while the CPU accesses the other
+/// half.
+pub struct CircBuffer<B, CHANNEL> {
+    _marker: PhantomData<CHANNEL>,
+    buffer: UnsafeCell<[B; 2]>,
+    state: Cell<CircState>,
+}
 
-        if status == Status::Unlocked {
-            return Ok(());
+/// Half of the circular buffer the DMA is currently working on
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum CircState {
+    Free,
+    FirstHalf,
+    SecondHalf,
+}
+
+impl<B, CHANNEL> CircBuffer<B, CHANNEL> {
+    /// Creates a new circular buffer
+    ///
+    /// For memory to peripheral transfers `buffer` holds the data that the
+    /// DMA sends first.
+    pub const fn new(buffer: [B; 2]) -> Self {
+        CircBuffer {
+            _marker: PhantomData,
+            buffer: UnsafeCell::new(buffer),
+            state: Cell::new(CircState::Free),
         }
+    }
 
-        if dma1.isr.read().teif2().bits() == 1 {
-            Err(nb::Error::Other(Error::Transfer))
-        } else if dma1.isr.read().tcif2().bits() == 1 {
-            unsafe { self.unlock(status) }
-            dma1.ifcr.write(|w| unsafe { w.ctcif2().bits(1) });
-            dma1.ccr2.modify(|_, w| unsafe { w.en().bits(0) });
-            Ok(())
-        } else {
-            Err(nb::Error::WouldBlock)
+    /// Returns the number of `W` items in the whole buffer
+    ///
+    /// NOTE like `Buffer::len` this doesn't read the contents
+    pub(crate) fn len<W>(&self) -> usize
+    where
+        B: Unsize<[W]>,
+    {
+        let half: *const [W] = self.buffer.get() as *const B;
+
+        unsafe { (*half).len() * 2 }
+    }
+
+    /// Locks the buffer for a circular transfer
+    ///
+    /// Returns `Error::InUse` if a circular transfer already uses the buffer
+    pub(crate) fn lock(&self) -> Result<&[B; 2], Error> {
+        if self.state.get() != CircState::Free {
+            return Err(Error::InUse);
         }
+
+        self.state.set(CircState::FirstHalf);
+
+        Ok(unsafe { &*self.buffer.get() })
+    }
+
+    unsafe fn unlock(&self) {
+        self.state.set(CircState::Free);
     }
 }
 
-impl<T> Buffer<T, Dma1Channel4> {
+impl<T, CHANNEL> Buffer<T, CHANNEL>
+where
+    CHANNEL: DmaChannel,
+{
     /// Waits until the DMA releases this buffer
-    pub fn release(&self, dma1: &Dma1) -> nb::Result<(), Error> {
+    ///
+    /// On a transfer error the channel is disabled and the buffer is
+    /// released as well; its contents are only partially transferred.
+    pub fn release(&self, dma: &CHANNEL::Dma) -> nb::Result<(), Error> {
         let status = self.status.get();
 
         if status == Status::Unlocked {
             return Ok(());
         }
 
-        if dma1.isr.read().teif4().bits() == 1 {
+        let flags = CHANNEL::flags(dma);
+
+        if flags & TEIF != 0 {
+            self.finish(dma, status);
             Err(nb::Error::Other(Error::Transfer))
-        } else if dma1.isr.read().tcif4().bits() == 1 {
-            unsafe { self.unlock(status) }
-            dma1.ifcr.write(|w| unsafe { w.ctcif4().bits(1) });
-            dma1.ccr4.modify(|_, w| unsafe { w.en().bits(0) });
+        } else if flags & TCIF != 0 {
+            self.finish(dma, status);
             Ok(())
         } else {
             Err(nb::Error::WouldBlock)
         }
     }
-}
 
-impl<T> Buffer<T, Dma1Channel5> {
-    /// Waits until the DMA releases this buffer
-    pub fn release(&self, dma1: &Dma1) -> nb::Result<(), Error> {
+    /// Returns the number of items the DMA has yet to transfer
+    ///
+    /// Returns `0` if the buffer is not locked by a transfer.
+    pub fn progress(&self, dma: &CHANNEL::Dma) -> u16 {
+        if self.status.get() == Status::Unlocked {
+            0
+        } else {
+            CHANNEL::remaining(dma)
+        }
+    }
+
+    /// Checks whether the DMA has transferred half of the buffer
+    ///
+    /// NOTE this also clears the half transfer flag, so it returns `true`
+    /// only once per transfer
+    pub fn is_half_done(&self, dma: &CHANNEL::Dma) -> bool {
+        if self.status.get() == Status::Unlocked {
+            return false;
+        }
+
+        if CHANNEL::flags(dma) & HTIF != 0 {
+            CHANNEL::clear_flags(dma, HTIF);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Stops the in-flight transfer and releases the buffer
+    ///
+    /// The contents of the buffer are only partially transferred; use
+    /// `progress` beforehand to find out how much.
+    pub fn abort(&self, dma: &CHANNEL::Dma) {
         let status = self.status.get();
 
-        if status == Status::Unlocked {
-            return Ok(());
+        if status != Status::Unlocked {
+            self.finish(dma, status);
         }
+    }
 
-        if dma1.isr.read().teif5().bits() == 1 {
-            Err(nb::Error::Other(Error::Transfer))
-        } else if dma1.isr.read().tcif5().bits() == 1 {
-            unsafe { self.unlock(status) }
-            dma1.ifcr.write(|w| unsafe { w.ctcif5().bits(1) });
-            dma1.ccr5.modify(|_, w| unsafe { w.en().bits(0) });
+    /// Disables the channel, clears its flags and unlocks the buffer
+    fn finish(&self, dma: &CHANNEL::Dma, status: Status) {
+        CHANNEL::stop(dma);
+        CHANNEL::clear_flags(dma, GIF | TCIF | HTIF | TEIF);
+
+        // NOTE(compiler_fence) the CPU accesses to the buffer must not be
+        // reordered before the end of the transfer
+        atomic::compiler_fence(Ordering::SeqCst);
+
+        unsafe { self.unlock(status) }
+    }
+}
+
+impl<B, CHANNEL> CircBuffer<B, CHANNEL>
+where
+    CHANNEL: DmaChannel,
+{
+    /// Reads the half of the buffer the DMA has just filled
+    ///
+    /// Returns `Error::Overrun` if the DMA started overwriting that half
+    /// while `f` was still running. On `Error::Transfer` the stream has been
+    /// stopped and the buffer released.
+    pub fn read<R, F>(&self, dma: &CHANNEL::Dma, f: F) -> nb::Result<R, Error>
+    where
+        F: FnOnce(&B) -> R,
+    {
+        let half = self.next_half(dma)?;
+
+        let ret = f(unsafe { &(*self.buffer.get())[half] });
+
+        self.check_overrun(dma, half).map(|_| ret)
+    }
+
+    /// Refills the half of the buffer the DMA has just sent
+    ///
+    /// Returns `Error::Overrun` if the DMA started sending that half while
+    /// `f` was still running.
+    pub fn write<R, F>(&self, dma: &CHANNEL::Dma, f: F) -> nb::Result<R, Error>
+    where
+        F: FnOnce(&mut B) -> R,
+    {
+        let half = self.next_half(dma)?;
+
+        let ret = f(unsafe { &mut (*self.buffer.get())[half] });
+
+        self.check_overrun(dma, half).map(|_| ret)
+    }
+
+    /// Stops the circular transfer and releases the buffer
+    pub fn stop(&self, dma: &CHANNEL::Dma) {
+        CHANNEL::stop(dma);
+        CHANNEL::clear_flags(dma, GIF | TCIF | HTIF | TEIF);
+
+        // NOTE(compiler_fence) the CPU accesses to the buffer must not be
+        // reordered before the end of the transfer
+        atomic::compiler_fence(Ordering::SeqCst);
+
+        unsafe { self.unlock() }
+    }
+
+    /// Waits until the DMA is done with a half of the buffer and returns
+    /// the index of that half
+    ///
+    /// On a transfer error the circular transfer is stopped and the buffer
+    /// released, like `Buffer::release` does.
+    fn next_half(&self, dma: &CHANNEL::Dma) -> nb::Result<usize, Error> {
+        let state = self.state.get();
+
+        if state == CircState::Free {
+            // no transfer in progress; nothing will ever fill the buffer
+            return Err(nb::Error::Other(Error::Transfer));
+        }
+
+        let flags = CHANNEL::flags(dma);
+
+        if flags & TEIF != 0 {
+            self.stop(dma);
+            return Err(nb::Error::Other(Error::Transfer));
+        }
+
+        match state {
+            CircState::FirstHalf => {
+                if flags & TCIF != 0 {
+                    Err(nb::Error::Other(Error::Overrun))
+                } else if flags & HTIF != 0 {
+                    CHANNEL::clear_flags(dma, HTIF);
+                    self.state.set(CircState::SecondHalf);
+                    Ok(0)
+                } else {
+                    Err(nb::Error::WouldBlock)
+                }
+            }
+            _ => {
+                if flags & HTIF != 0 {
+                    Err(nb::Error::Other(Error::Overrun))
+                } else if flags & TCIF != 0 {
+                    CHANNEL::clear_flags(dma, TCIF);
+                    self.state.set(CircState::FirstHalf);
+                    Ok(1)
+                } else {
+                    Err(nb::Error::WouldBlock)
+                }
+            }
+        }
+    }
+
+    /// Checks that the DMA didn't move into `half` while the CPU was
+    /// accessing it
+    fn check_overrun(
+        &self,
+        dma: &CHANNEL::Dma,
+        half: usize,
+    ) -> nb::Result<(), Error> {
+        let flag = if half == 0 { TCIF } else { HTIF };
+
+        if CHANNEL::flags(dma) & flag != 0 {
+            Err(nb::Error::Other(Error::Overrun))
+        } else {
             Ok(())
+        }
+    }
+}
+
+/// An in-progress memory to memory transfer
+///
+/// See `copy` and `fill`
+pub struct Transfer<'a, S, D, CHANNEL>
+where
+    S: 'a,
+    D: 'a,
+    CHANNEL: 'a,
+{
+    src: static_ref::Ref<'a, Buffer<S, CHANNEL>>,
+    dst: static_ref::Ref<'a, Buffer<D, CHANNEL>>,
+}
+
+impl<'a, S, D, CHANNEL> Transfer<'a, S, D, CHANNEL>
+where
+    CHANNEL: DmaChannel,
+{
+    /// Waits until the DMA releases the source and destination buffers
+    pub fn release(&self, dma: &CHANNEL::Dma) -> nb::Result<(), Error> {
+        let result = self.dst.release(dma);
+
+        match result {
+            Err(nb::Error::WouldBlock) => {}
+            _ => {
+                // NOTE the source doesn't get a transfer complete flag of
+                // its own; it's released along with the destination
+                unsafe { self.src.unlock(self.src.status.get()) }
+            }
+        }
+
+        result
+    }
+
+    /// Returns the number of items the DMA has yet to copy
+    pub fn progress(&self, dma: &CHANNEL::Dma) -> u16 {
+        self.dst.progress(dma)
+    }
+
+    /// Stops the in-flight transfer and releases both buffers
+    pub fn abort(&self, dma: &CHANNEL::Dma) {
+        self.dst.abort(dma);
+
+        unsafe { self.src.unlock(self.src.status.get()) }
+    }
+}
+
+/// Copies the contents of `src` into `dst` using `CHANNEL`
+///
+/// This will immutably lock `src` and mutably lock `dst`. Both buffers are
+/// released by `Transfer::release` once the copy is done. Returns
+/// `Error::LengthMismatch` if `src` and `dst` don't have the same length.
+///
+/// `_channel` proves that `CHANNEL` is not in use by an `OwnedTransfer`.
+pub fn copy<'a, W, S, D, CHANNEL>(
+    dma: &CHANNEL::Dma,
+    _channel: &CHANNEL,
+    rcc: &Rcc,
+    src: static_ref::Ref<'a, Buffer<S, CHANNEL>>,
+    dst: static_ref::Ref<'a, Buffer<D, CHANNEL>>,
+) -> Result<Transfer<'a, S, D, CHANNEL>, Error>
+where
+    CHANNEL: DmaChannel,
+    D: Unsize<[W]>,
+    S: Unsize<[W]>,
+    W: Word,
+{
+    if CHANNEL::is_enabled(dma) {
+        return Err(Error::InUse);
+    }
+
+    if src.len::<W>() != dst.len::<W>() {
+        return Err(Error::LengthMismatch);
+    }
+
+    let len = transfer_len(dst.len::<W>())?;
+
+    {
+        let to: &mut [W] = dst.lock_mut()?;
+        let from: &[W] = match src.lock() {
+            Ok(from) => from,
+            Err(e) => {
+                dst.abort(dma);
+                return Err(e);
+            }
+        };
+
+        let (from, to) = (from.as_ptr() as u32, to.as_ptr() as u32);
+        mem2mem::<W, CHANNEL>(dma, rcc, from, to, len, true);
+    }
+
+    Ok(Transfer { src: src, dst: dst })
+}
+
+/// Fills `dst` with the value stored in `value` using `CHANNEL`
+///
+/// This will immutably lock `value` and mutably lock `dst`. Both buffers are
+/// released by `Transfer::release` once `dst` has been filled.
+///
+/// `_channel` proves that `CHANNEL` is not in use by an `OwnedTransfer`.
+pub fn fill<'a, W, D, CHANNEL>(
+    dma: &CHANNEL::Dma,
+    _channel: &CHANNEL,
+    rcc: &Rcc,
+    value: static_ref::Ref<'a, Buffer<W, CHANNEL>>,
+    dst: static_ref::Ref<'a, Buffer<D, CHANNEL>>,
+) -> Result<Transfer<'a, W, D, CHANNEL>, Error>
+where
+    CHANNEL: DmaChannel,
+    D: Unsize<[W]>,
+    W: Word,
+{
+    if CHANNEL::is_enabled(dma) {
+        return Err(Error::InUse);
+    }
+
+    let len = transfer_len(dst.len::<W>())?;
+
+    {
+        let to: &mut [W] = dst.lock_mut()?;
+        let from: &W = match value.lock() {
+            Ok(from) => from,
+            Err(e) => {
+                dst.abort(dma);
+                return Err(e);
+            }
+        };
+
+        let (from, to) = (from as *const W as u32, to.as_ptr() as u32);
+        mem2mem::<W, CHANNEL>(dma, rcc, from, to, len, false);
+    }
+
+    Ok(Transfer { src: value, dst: dst })
+}
+
+/// Starts a memory to memory transfer of `len` items from `src` into `dst`
+fn mem2mem<W, CHANNEL>(
+    dma: &CHANNEL::Dma,
+    rcc: &Rcc,
+    src: u32,
+    dst: u32,
+    len: u16,
+    increment_src: bool,
+) where
+    CHANNEL: DmaChannel,
+    W: Word,
+{
+    CHANNEL::Dma::enable(rcc);
+
+    // the source goes through the peripheral port
+    CHANNEL::configure(
+        dma,
+        &Config {
+            priority: Priority::Low,
+            memory_size: W::size(),
+            peripheral_size: W::size(),
+            memory_increment: true,
+            peripheral_increment: increment_src,
+            direction: Direction::MemoryToMemory,
+            circular: false,
+            transfer_complete_interrupt: false,
+            half_transfer_interrupt: false,
+            transfer_error_interrupt: false,
+        },
+    );
+    CHANNEL::start(dma, src, dst, len);
+}
+
+/// A DMA transfer that owns its buffer and its channel
+pub struct OwnedTransfer<B, CHANNEL>
+where
+    B: ?Sized + 'static,
+{
+    buffer: &'static mut B,
+    channel: CHANNEL,
+}
+
+/// A failed `OwnedTransfer`
+///
+/// The buffer and the channel are handed back along with the error.
+pub struct TransferError<B, CHANNEL>
+where
+    B: ?Sized + 'static,
+{
+    /// The error
+    pub error: Error,
+    /// The buffer of the transfer; its contents are only partially
+    /// transferred
+    pub buffer: &'static mut B,
+    /// The channel of the transfer
+    pub channel: CHANNEL,
+}
+
+impl<B, CHANNEL> OwnedTransfer<B, CHANNEL>
+where
+    B: ?Sized + 'static,
+    CHANNEL: DmaChannel,
+{
+    /// Starts a transfer between the `peripheral` register and `buffer`
+    ///
+    /// Returns `Error::InUse` if the channel is still running a transfer
+    /// started through the `Buffer` API, and `Error::TooLong` if `buffer`
+    /// holds more than `u16::MAX` items.
+    ///
+    /// `config.memory_size` is ignored; the size of `W` is used instead.
+    ///
+    /// # Safety
+    ///
+    /// `peripheral` must be the address of a register that accepts
+    /// transfers of `config.peripheral_size`. The register must be the
+    /// source (`config.direction` = `PeripheralToMemory`) or the
+    /// destination (`MemoryToPeripheral`) of the peripheral requests served
+    /// by `CHANNEL`.
+    pub unsafe fn start<W>(
+        channel: CHANNEL,
+        dma: &CHANNEL::Dma,
+        config: &Config,
+        peripheral: u32,
+        buffer: &'static mut B,
+    ) -> Result<Self, TransferError<B, CHANNEL>>
+    where
+        B: Unsize<[W]>,
+        W: Word,
+    {
+        let (address, len) = {
+            let slice: &mut [W] = &mut *buffer;
+
+            (slice.as_ptr() as u32, transfer_len(slice.len()))
+        };
+
+        let len = if CHANNEL::is_enabled(dma) {
+            Err(Error::InUse)
         } else {
-            Err(nb::Error::WouldBlock)
+            len
+        };
+
+        let len = match len {
+            Ok(len) => len,
+            Err(error) => {
+                return Err(TransferError {
+                    error: error,
+                    buffer: buffer,
+                    channel: channel,
+                })
+            }
+        };
+
+        CHANNEL::configure(
+            dma,
+            &Config {
+                memory_size: W::size(),
+                ..*config
+            },
+        );
+        CHANNEL::start(dma, peripheral, address, len);
+
+        Ok(OwnedTransfer {
+            buffer: buffer,
+            channel: channel,
+        })
+    }
+
+    /// Checks whether the transfer is done
+    pub fn is_done(&self, dma: &CHANNEL::Dma) -> Result<bool, Error> {
+        let flags = CHANNEL::flags(dma);
+
+        if flags & TEIF != 0 {
+            Err(Error::Transfer)
+        } else {
+            Ok(flags & TCIF != 0)
+        }
+    }
+
+    /// Waits until the transfer is done and returns the buffer and the
+    /// channel
+    pub fn wait(
+        self,
+        dma: &CHANNEL::Dma,
+    ) -> Result<(&'static mut B, CHANNEL), TransferError<B, CHANNEL>> {
+        let result = loop {
+            match self.is_done(dma) {
+                Ok(true) => break Ok(()),
+                Ok(false) => {}
+                Err(e) => break Err(e),
+            }
+        };
+
+        CHANNEL::stop(dma);
+        CHANNEL::clear_flags(dma, GIF | TCIF | HTIF | TEIF);
+
+        // NOTE(compiler_fence) the CPU accesses to the buffer must not be
+        // reordered before the end of the transfer
+        atomic::compiler_fence(Ordering::SeqCst);
+
+        match result {
+            Ok(()) => Ok((self.buffer, self.channel)),
+            Err(error) => Err(TransferError {
+                error: error,
+                buffer: self.buffer,
+                channel: self.channel,
+            }),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::transfer_len;
+
+    #[test]
+    fn transfer_len_limit() {
+        assert_eq!(transfer_len(0).ok(), Some(0));
+        assert_eq!(transfer_len(0xffff).ok(), Some(0xffff));
+        assert!(transfer_len(0x1_0000).is_err());
+    }
+}
@@ -0,0 +1,636 @@
+//! Inter-IC Sound (I2S)
+//!
+//! You can use the `I2s` interface with these SPI instances
+//!
+//! # SPI2 / I2S2
+//!
+//! - WS = PB12
+//! - CK = PB13
+//! - ext_SD = PB14 (full duplex only)
+//! - SD = PB15
+//! - MCK = PC6 (not configured by this driver)
+//! - DMA RX = DMA1 channel 4
+//! - DMA TX = DMA1 channel 5
+//!
+//! # SPI3 / I2S3
+//!
+//! - WS = PA15 (not configured by this driver)
+//! - CK = PC10
+//! - ext_SD = PC11 (full duplex only)
+//! - SD = PC12
+//! - MCK = PC7 (not configured by this driver)
+//! - DMA RX = DMA2 channel 1
+//! - DMA TX = DMA2 channel 2
+//!
+//! The I2S clock is SYSCLK. Its frequency is read from the RCC configuration
+//! when the interface is initialized; to get accurate audio sample rates
+//! SYSCLK should be driven by the PLL (see `hse` and `hsi` for the frequency
+//! of the PLL inputs).
+//!
+//! # Full duplex
+//!
+//! Circular DMA streaming (`receive_circ` / `transmit_circ`) is only
+//! available on the main instance. The I2S2ext / I2S3ext block, which carries
+//! the opposite direction in full duplex mode, only has the polled
+//! `read_ext` / `write_ext` methods; it doesn't get a DMA channel.
+
+use core::cmp;
+use core::marker::Unsize;
+use core::ops::Deref;
+use core::ptr;
+
+use nb;
+use static_ref::Ref;
+use stm32f30x::{I2s2ext, I2s3ext, Rcc, Spi2, Spi3, spi1};
+
+use dma::{self, CircBuffer, DMA, Direction, DmaChannel, Priority, Size};
+use spi::SPI;
+use time::Hertz;
+
+/// SPI instance that can be used with the `I2s` abstraction
+pub unsafe trait I2S: SPI {
+    /// Full duplex extension block associated to this instance
+    type EXT: Deref<Target = spi1::RegisterBlock>;
+}
+
+unsafe impl I2S for Spi2 {
+    type EXT = I2s2ext;
+}
+
+unsafe impl I2S for Spi3 {
+    type EXT = I2s3ext;
+}
+
+/// I2S result
+pub type Result<T> = ::core::result::Result<T, nb::Error<Error>>;
+
+/// I2S error
+#[derive(Debug)]
+pub enum Error {
+    /// Frame format error (slave mode)
+    Frame,
+    /// Overrun occurred
+    Overrun,
+    /// Underrun occurred
+    Underrun,
+    /// The sample rate can't be generated from the I2S clock
+    SampleRate,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Operating mode
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mode {
+    /// Slave transmit
+    SlaveTransmit,
+    /// Slave receive
+    SlaveReceive,
+    /// Master transmit
+    MasterTransmit,
+    /// Master receive
+    MasterReceive,
+}
+
+impl Mode {
+    fn bits(self) -> u8 {
+        match self {
+            Mode::SlaveTransmit => 0b00,
+            Mode::SlaveReceive => 0b01,
+            Mode::MasterTransmit => 0b10,
+            Mode::MasterReceive => 0b11,
+        }
+    }
+
+    /// Mode of the full duplex extension: always slave, opposite direction
+    fn ext(self) -> Mode {
+        match self {
+            Mode::SlaveTransmit | Mode::MasterTransmit => Mode::SlaveReceive,
+            Mode::SlaveReceive | Mode::MasterReceive => Mode::SlaveTransmit,
+        }
+    }
+
+    fn is_master(self) -> bool {
+        self == Mode::MasterTransmit || self == Mode::MasterReceive
+    }
+}
+
+/// I2S standard
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Standard {
+    /// Philips I2S
+    Philips,
+    /// MSB justified (left justified)
+    Msb,
+    /// LSB justified (right justified)
+    Lsb,
+    /// PCM with short frame synchronization
+    PcmShort,
+    /// PCM with long frame synchronization
+    PcmLong,
+}
+
+/// Data length and channel length
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    /// 16-bit data in a 16-bit channel
+    Data16Channel16,
+    /// 16-bit data in a 32-bit channel
+    Data16Channel32,
+    /// 24-bit data in a 32-bit channel
+    Data24Channel32,
+    /// 32-bit data in a 32-bit channel
+    Data32Channel32,
+}
+
+impl Format {
+    fn channel_bits(self) -> u32 {
+        match self {
+            Format::Data16Channel16 => 16,
+            _ => 32,
+        }
+    }
+}
+
+/// Audio channel
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Channel {
+    /// Left channel
+    Left,
+    /// Right channel
+    Right,
+}
+
+/// I2S configuration
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    /// Operating mode
+    pub mode: Mode,
+    /// I2S standard
+    pub standard: Standard,
+    /// Data and channel length
+    pub format: Format,
+    /// Audio sample rate (only used in master mode)
+    pub sample_rate: Hertz,
+    /// Output the master clock (256 x sample rate) on the MCK pin (only used
+    /// in master mode)
+    pub master_clock: bool,
+}
+
+/// Computes the I2S prescaler (`I2SDIV`, `ODD`) that gets the closest to the
+/// requested `sample_rate`
+///
+/// Returns `None` if the sample rate can't be reached from `i2sclk`.
+pub fn prescaler(
+    i2sclk: u32,
+    sample_rate: Hertz,
+    format: Format,
+    master_clock: bool,
+) -> Option<(u8, bool)> {
+    let bits_per_frame = if master_clock {
+        256
+    } else {
+        format.channel_bits() * 2
+    };
+
+    let fs = sample_rate.0;
+    if fs == 0 {
+        return None;
+    }
+
+    // 2 * I2SDIV + ODD, rounded to the nearest integer
+    let den = u64::from(fs) * u64::from(bits_per_frame);
+    let div = (u64::from(i2sclk) + den / 2) / den;
+
+    if div < 4 || div > 511 {
+        None
+    } else {
+        Some(((div / 2) as u8, div % 2 == 1))
+    }
+}
+
+/// Returns the frequency of SYSCLK as configured in the RCC
+fn sysclk(rcc: &Rcc) -> u32 {
+    let cfgr = rcc.cfgr.read();
+
+    match cfgr.sws().bits() {
+        0b01 => ::hse::FREQUENCY,
+        0b10 => {
+            let input = if cfgr.pllsrc().bits() == 0 {
+                ::hsi::FREQUENCY / 2
+            } else {
+                let prediv = u32::from(rcc.cfgr2.read().prediv().bits()) + 1;
+
+                ::hse::FREQUENCY / prediv
+            };
+
+            // PLLMUL = 0b0000 is x2, ..., 0b1110 and 0b1111 are x16
+            input * cmp::min(u32::from(cfgr.pllmul().bits()) + 2, 16)
+        }
+        _ => ::hsi::FREQUENCY,
+    }
+}
+
+/// Inter-IC Sound interface
+pub struct I2s<'a, S>(pub &'a S)
+where
+    S: I2S;
+
+impl<'a, S> I2s<'a, S>
+where
+    S: I2S,
+{
+    /// Initializes the I2S interface
+    ///
+    /// If `ext` is provided the full duplex extension gets configured to
+    /// work in the opposite direction, as a slave of this interface.
+    ///
+    /// NOTE After initialization, the interface will be disabled.
+    ///
+    /// Returns `Error::SampleRate` if the sample rate can't be generated from
+    /// the I2S clock; the interface is left untouched in that case.
+    pub fn init(
+        &self,
+        config: Config,
+        ext: Option<&S::EXT>,
+        gpio: &S::GPIO,
+        rcc: &Rcc,
+    ) -> ::core::result::Result<(), Error> {
+        let spi = self.0;
+
+        let prescaler = if config.mode.is_master() {
+            Some(prescaler(
+                sysclk(rcc),
+                config.sample_rate,
+                config.format,
+                config.master_clock,
+            ).ok_or(Error::SampleRate)?)
+        } else {
+            None
+        };
+
+        // NOTE the I2S pins use the same alternate functions as the SPI ones
+        S::enable(rcc);
+        S::reset(rcc);
+        S::configure_pins(gpio, rcc);
+
+        // I2S clock = SYSCLK
+        rcc.cfgr.modify(|_, w| unsafe { w.i2ssrc().bits(0) });
+
+        if let Some((div, odd)) = prescaler {
+            spi.i2spr.write(|w| unsafe {
+                w.i2sdiv()
+                    .bits(div)
+                    .odd()
+                    .bits(if odd { 1 } else { 0 })
+                    .mckoe()
+                    .bits(if config.master_clock { 1 } else { 0 })
+            });
+        } else {
+            // reset value
+            spi.i2spr.write(|w| unsafe { w.i2sdiv().bits(2) });
+        }
+
+        configure(spi, config.mode, config);
+
+        if let Some(ext) = ext {
+            configure(ext, config.mode.ext(), config);
+        }
+
+        Ok(())
+    }
+
+    /// Disables the I2S interface (and its full duplex extension)
+    pub fn disable(&self, ext: Option<&S::EXT>) {
+        if let Some(ext) = ext {
+            ext.i2scfgr.modify(|_, w| unsafe { w.i2se().bits(0) });
+        }
+        self.0.i2scfgr.modify(|_, w| unsafe { w.i2se().bits(0) });
+    }
+
+    /// Enables the I2S interface (and its full duplex extension)
+    ///
+    /// In master mode this starts generating the clocks. The extension, a
+    /// slave, is enabled first so it doesn't miss the first frame.
+    pub fn enable(&self, ext: Option<&S::EXT>) {
+        if let Some(ext) = ext {
+            ext.i2scfgr.modify(|_, w| unsafe { w.i2se().bits(1) });
+        }
+        self.0.i2scfgr.modify(|_, w| unsafe { w.i2se().bits(1) });
+    }
+
+    /// Returns the channel the next half-word read or written belongs to
+    pub fn channel(&self) -> Channel {
+        channel(self.0)
+    }
+
+    /// Reads a half-word
+    ///
+    /// 24 and 32-bit samples are read as two half-words, MSB first.
+    pub fn read(&self) -> Result<u16> {
+        read(self.0)
+    }
+
+    /// Writes a half-word
+    ///
+    /// 24 and 32-bit samples are written as two half-words, MSB first.
+    pub fn write(&self, half_word: u16) -> Result<()> {
+        write(self.0, half_word)
+    }
+
+    /// Reads a half-word from the full duplex extension
+    pub fn read_ext(&self, ext: &S::EXT) -> Result<u16> {
+        read(ext)
+    }
+
+    /// Writes a half-word to the full duplex extension
+    pub fn write_ext(&self, ext: &S::EXT, half_word: u16) -> Result<()> {
+        write(ext, half_word)
+    }
+}
+
+impl<'a, S> I2s<'a, S>
+where
+    S: I2S,
+{
+    /// Starts streaming received samples into the circular `buffer`
+    ///
+    /// The halves of the `buffer` can be accessed using `CircBuffer::read`
+    /// as they get filled. The stream runs until `CircBuffer::stop` is
+    /// called.
+    ///
+    /// **NOTE** the RX channel of SPI2 (DMA1 channel 4) is shared with the
+    /// USART1 TX
+    pub fn receive_circ<B>(
+        &self,
+        dma: &S::DMA,
+        _channel: &S::RxChannel,
+        rcc: &Rcc,
+        buffer: Ref<CircBuffer<B, S::RxChannel>>,
+    ) -> ::core::result::Result<(), dma::Error>
+    where
+        B: Unsize<[u16]>,
+    {
+        let spi = self.0;
+
+        if S::RxChannel::is_enabled(dma) {
+            return Err(dma::Error::InUse);
+        }
+
+        let len = dma::transfer_len(buffer.len::<u16>())?;
+
+        S::DMA::enable(rcc);
+
+        let buffer: &[B; 2] = buffer.lock()?;
+
+        S::RxChannel::configure(
+            dma,
+            &circ_config(Direction::PeripheralToMemory),
+        );
+        S::RxChannel::start(
+            dma,
+            &spi.dr as *const _ as u32,
+            buffer.as_ptr() as u32,
+            len,
+        );
+
+        spi.cr2.modify(|_, w| unsafe { w.rxdmaen().bits(1) });
+
+        Ok(())
+    }
+
+    /// Starts streaming the contents of the circular `buffer`
+    ///
+    /// The halves of the `buffer` can be refilled using `CircBuffer::write`
+    /// once they have been sent. The stream runs until `CircBuffer::stop` is
+    /// called.
+    ///
+    /// **NOTE** the TX channel of SPI2 (DMA1 channel 5) is shared with the
+    /// USART1 RX
+    pub fn transmit_circ<B>(
+        &self,
+        dma: &S::DMA,
+        _channel: &S::TxChannel,
+        rcc: &Rcc,
+        buffer: Ref<CircBuffer<B, S::TxChannel>>,
+    ) -> ::core::result::Result<(), dma::Error>
+    where
+        B: Unsize<[u16]>,
+    {
+        let spi = self.0;
+
+        if S::TxChannel::is_enabled(dma) {
+            return Err(dma::Error::InUse);
+        }
+
+        let len = dma::transfer_len(buffer.len::<u16>())?;
+
+        S::DMA::enable(rcc);
+
+        let buffer: &[B; 2] = buffer.lock()?;
+
+        S::TxChannel::configure(
+            dma,
+            &circ_config(Direction::MemoryToPeripheral),
+        );
+        S::TxChannel::start(
+            dma,
+            &spi.dr as *const _ as u32,
+            buffer.as_ptr() as u32,
+            len,
+        );
+
+        spi.cr2.modify(|_, w| unsafe { w.txdmaen().bits(1) });
+
+        Ok(())
+    }
+}
+
+/// DMA configuration of the circular streams: 16-bit samples with half and
+/// full transfer interrupts
+fn circ_config(direction: Direction) -> dma::Config {
+    dma::Config {
+        priority: Priority::VeryHigh,
+        circular: true,
+        half_transfer_interrupt: true,
+        ..dma::Config::peripheral(direction, Size::HalfWord)
+    }
+}
+
+fn configure(spi: &spi1::RegisterBlock, mode: Mode, config: Config) {
+    let (std, pcmsync) = match config.standard {
+        Standard::Philips => (0b00, 0),
+        Standard::Msb => (0b01, 0),
+        Standard::Lsb => (0b10, 0),
+        Standard::PcmShort => (0b11, 0),
+        Standard::PcmLong => (0b11, 1),
+    };
+
+    let (datlen, chlen) = match config.format {
+        Format::Data16Channel16 => (0b00, 0),
+        Format::Data16Channel32 => (0b00, 1),
+        Format::Data24Channel32 => (0b01, 1),
+        Format::Data32Channel32 => (0b10, 1),
+    };
+
+    // i2smod: I2S mode
+    // i2se: Disabled
+    // ckpol: Clock low when idle
+    spi.i2scfgr.write(|w| unsafe {
+        w.i2smod()
+            .bits(1)
+            .i2se()
+            .bits(0)
+            .i2scfg()
+            .bits(mode.bits())
+            .i2sstd()
+            .bits(std)
+            .pcmsync()
+            .bits(pcmsync)
+            .ckpol()
+            .bits(0)
+            .datlen()
+            .bits(datlen)
+            .chlen()
+            .bits(chlen)
+    });
+}
+
+fn channel(spi: &spi1::RegisterBlock) -> Channel {
+    if spi.sr.read().chside().bits() == 0 {
+        Channel::Left
+    } else {
+        Channel::Right
+    }
+}
+
+fn read(spi: &spi1::RegisterBlock) -> Result<u16> {
+    let sr = spi.sr.read();
+
+    if sr.ovr().bits() == 1 {
+        Err(nb::Error::Other(Error::Overrun))
+    } else if sr.fre().bits() == 1 {
+        Err(nb::Error::Other(Error::Frame))
+    } else if sr.rxne().bits() == 1 {
+        Ok(unsafe { ptr::read_volatile(&spi.dr as *const _ as *const u16) })
+    } else {
+        Err(nb::Error::WouldBlock)
+    }
+}
+
+fn write(spi: &spi1::RegisterBlock, half_word: u16) -> Result<()> {
+    let sr = spi.sr.read();
+
+    if sr.udr().bits() == 1 {
+        Err(nb::Error::Other(Error::Underrun))
+    } else if sr.fre().bits() == 1 {
+        Err(nb::Error::Other(Error::Frame))
+    } else if sr.txe().bits() == 1 {
+        unsafe {
+            ptr::write_volatile(&spi.dr as *const _ as *mut u16, half_word)
+        }
+        Ok(())
+    } else {
+        Err(nb::Error::WouldBlock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::Hertz;
+
+    use super::Format::*;
+    use super::prescaler;
+
+    #[test]
+    fn exact() {
+        // 64 MHz / (2 * 32 bits * 50 KHz) = 20
+        assert_eq!(
+            prescaler(64_000_000, Hertz(50_000), Data32Channel32, false),
+            Some((10, false))
+        );
+    }
+
+    #[test]
+    fn odd() {
+        // 72 MHz / (2 * 16 bits * 48 KHz) = 46.875 -> 47
+        assert_eq!(
+            prescaler(72_000_000, Hertz(48_000), Data16Channel16, false),
+            Some((23, true))
+        );
+    }
+
+    #[test]
+    fn master_clock() {
+        // 72 MHz / (256 * 48 KHz) = 5.86 -> 6
+        assert_eq!(
+            prescaler(72_000_000, Hertz(48_000), Data16Channel16, true),
+            Some((3, false))
+        );
+    }
+
+    #[test]
+    fn rounding() {
+        // 32.5 rounds up, 32.49 rounds down
+        assert_eq!(
+            prescaler(2_080, Hertz(2), Data16Channel16, false),
+            Some((16, true))
+        );
+        assert_eq!(
+            prescaler(2_079, Hertz(2), Data16Channel16, false),
+            Some((16, false))
+        );
+    }
+
+    #[test]
+    fn bounds() {
+        // smallest divider: 4
+        assert_eq!(
+            prescaler(256, Hertz(1), Data32Channel32, false),
+            Some((2, false))
+        );
+        assert_eq!(
+            prescaler(192, Hertz(1), Data32Channel32, false),
+            None
+        );
+
+        // largest divider: 511
+        assert_eq!(
+            prescaler(511 * 64, Hertz(1), Data32Channel32, false),
+            Some((255, true))
+        );
+        assert_eq!(
+            prescaler(512 * 64, Hertz(1), Data32Channel32, false),
+            None
+        );
+    }
+
+    #[test]
+    fn zero() {
+        assert_eq!(
+            prescaler(72_000_000, Hertz(0), Data16Channel16, false),
+            None
+        );
+        assert_eq!(
+            prescaler(0, Hertz(48_000), Data16Channel16, false),
+            None
+        );
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(
+            prescaler(
+                u32::max_value(),
+                Hertz(u32::max_value()),
+                Data32Channel32,
+                true,
+            ),
+            None
+        );
+        assert_eq!(
+            prescaler(u32::max_value(), Hertz(1), Data16Channel16, false),
+            None
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! Independent watchdog (IWDG)
+//!
+//! The IWDG is clocked by the LSI oscillator (see `lsi`) so it keeps running
+//! even if the main clock fails. Once started it can't be stopped; the
+//! device will be reset unless the watchdog is fed before the timeout
+//! expires.
+//!
+//! NOTE timeouts are computed for the nominal LSI frequency; the actual
+//! timeout can be up to 25% shorter or longer.
+
+use stm32f30x::Iwdg;
+
+/// Key that starts the watchdog
+const KEY_START: u16 = 0xcccc;
+/// Key that enables write access to the PR, RLR and WINR registers
+const KEY_UNLOCK: u16 = 0x5555;
+/// Key that reloads the counter
+const KEY_FEED: u16 = 0xaaaa;
+
+/// Maximum value of the 12-bit reload and window registers
+const RLR_MAX: u32 = 0xfff;
+
+/// IWDG error
+#[derive(Debug)]
+pub enum Error {
+    /// The timeout is zero
+    TimeoutTooShort,
+    /// The timeout is longer than the watchdog can count
+    TimeoutTooLong,
+    /// The window is shorter than one prescaled LSI tick; the watchdog
+    /// couldn't be fed
+    WindowTooShort,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Computes the prescaler (PR) and reload (RLR) values for a timeout of
+/// `timeout` LSI ticks
+///
+/// The actual timeout is rounded up to the next multiple of the prescaler.
+pub fn pr_rlr(timeout: u32) -> Result<(u8, u16), Error> {
+    if timeout == 0 {
+        return Err(Error::TimeoutTooShort);
+    }
+
+    // the prescaler divides the LSI by 4 * 2^PR, PR in 0..=6
+    for pr in 0..7 {
+        let div = 4 << pr;
+        // NOTE `timeout + div - 1` could overflow
+        let count = timeout / div + (timeout % div != 0) as u32;
+
+        if count <= RLR_MAX + 1 {
+            return Ok((pr as u8, (count - 1) as u16));
+        }
+    }
+
+    Err(Error::TimeoutTooLong)
+}
+
+/// Computes the window (WINR) value for a `window` of LSI ticks, given the
+/// `pr` and `rlr` values returned by `pr_rlr`
+///
+/// The window is rounded down to a multiple of the prescaler.
+pub fn winr(window: u32, pr: u8, rlr: u16) -> Result<u16, Error> {
+    // the counter must be at or below WINR when the watchdog is fed
+    let win = window / (4 << pr);
+
+    if win == 0 {
+        Err(Error::WindowTooShort)
+    } else if win > u32::from(rlr) {
+        Ok(RLR_MAX as u16)
+    } else {
+        Ok(win as u16)
+    }
+}
+
+/// Independent watchdog
+pub struct IndependentWatchdog<'a>(pub &'a Iwdg);
+
+impl<'a> Clone for IndependentWatchdog<'a> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a> Copy for IndependentWatchdog<'a> {}
+
+impl<'a> IndependentWatchdog<'a> {
+    /// Starts the watchdog with a timeout of `timeout` ticks
+    ///
+    /// NOTE the watchdog can't be stopped once started
+    pub fn start<T>(&self, timeout: T) -> Result<(), Error>
+    where
+        T: Into<::lsi::Ticks>,
+    {
+        let (pr, rlr) = pr_rlr(timeout.into().0)?;
+
+        self._start(pr, rlr, RLR_MAX as u16);
+
+        Ok(())
+    }
+
+    /// Starts the watchdog in window mode
+    ///
+    /// The watchdog must be fed within the last `window` ticks of the
+    /// `timeout`; feeding it earlier resets the device as well. A `window`
+    /// as long as the `timeout` disables the window. Returns
+    /// `Error::WindowTooShort` if `window` is shorter than one tick of the
+    /// prescaled LSI.
+    ///
+    /// NOTE the watchdog can't be stopped once started
+    pub fn start_windowed<T, W>(&self, timeout: T, window: W) -> Result<(), Error>
+    where
+        T: Into<::lsi::Ticks>,
+        W: Into<::lsi::Ticks>,
+    {
+        let (pr, rlr) = pr_rlr(timeout.into().0)?;
+        let win = winr(window.into().0, pr, rlr)?;
+
+        self._start(pr, rlr, win);
+
+        Ok(())
+    }
+
+    /// Feeds the watchdog, restarting the timeout
+    pub fn feed(&self) {
+        self.0.kr.write(|w| unsafe { w.key().bits(KEY_FEED) });
+    }
+
+    fn _start(&self, pr: u8, rlr: u16, win: u16) {
+        let iwdg = self.0;
+
+        // NOTE this also starts the LSI
+        iwdg.kr.write(|w| unsafe { w.key().bits(KEY_START) });
+        iwdg.kr.write(|w| unsafe { w.key().bits(KEY_UNLOCK) });
+
+        iwdg.pr.write(|w| unsafe { w.pr().bits(pr) });
+        iwdg.rlr.write(|w| unsafe { w.rl().bits(rlr) });
+
+        // wait until the prescaler and reload values have been updated
+        while iwdg.sr.read().bits() & 0b011 != 0 {}
+
+        // NOTE writing WINR reloads the counter
+        iwdg.winr.write(|w| unsafe { w.win().bits(win) });
+
+        while iwdg.sr.read().bits() != 0 {}
+
+        self.feed();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pr_rlr, winr};
+
+    #[test]
+    fn edges() {
+        assert!(pr_rlr(0).is_err());
+        assert_eq!(pr_rlr(1).ok(), Some((0, 0)));
+        assert_eq!(pr_rlr(4 * 4096).ok(), Some((0, 0xfff)));
+        assert_eq!(pr_rlr(4 * 4096 + 1).ok(), Some((1, 0x800)));
+        assert_eq!(pr_rlr(256 * 4096).ok(), Some((6, 0xfff)));
+        assert!(pr_rlr(256 * 4096 + 1).is_err());
+        assert!(pr_rlr(u32::max_value()).is_err());
+    }
+
+    #[test]
+    fn window() {
+        assert!(winr(0, 0, 0xfff).is_err());
+        assert!(winr(3, 0, 0xfff).is_err());
+        assert_eq!(winr(4, 0, 0xfff).ok(), Some(1));
+        assert!(winr(255, 6, 0xfff).is_err());
+        assert_eq!(winr(256, 6, 0xfff).ok(), Some(1));
+        assert_eq!(winr(4 * 0x800, 0, 0x800).ok(), Some(0x800));
+        assert_eq!(winr(4 * 0x801, 0, 0x800).ok(), Some(0xfff));
+    }
+}
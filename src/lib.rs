@@ -1,12 +1,12 @@
 //#![deny(missing_docs)]
 //#![deny(warnings)]
 #![feature(const_fn)]
-#![feature(get_type_id)]
 #![feature(never_type)]
 #![feature(unsize)]
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 extern crate cast;
+extern crate cortex_m;
 extern crate either;
 extern crate embedded_hal as hal;
 extern crate nb;
@@ -14,26 +14,70 @@ extern crate static_ref;
 
 pub extern crate stm32f30x;
 
-//pub mod capture;
+pub mod adc;
+pub mod alarm;
+pub mod capture;
+pub mod comp;
+pub mod dac;
+pub mod delay;
 pub mod dma;
 //pub mod gpio;
+pub mod i2s;
+pub mod iwdg;
 pub mod led;
-//pub mod pwm;
+pub mod monotonic;
+pub mod opamp;
+pub mod power;
+pub mod pwm;
 //pub mod qei;
+pub mod rtc;
 pub mod serial;
 pub mod spi;
 pub mod time;
 pub mod timer;
+pub mod wwdg;
 
-//pub use capture::Capture;
-//pub use pwm::Pwm;
+pub use capture::Capture;
+pub use i2s::I2s;
+pub use pwm::Pwm;
 //pub use qei::Qei;
 pub use serial::Serial;
 pub use spi::Spi;
 pub use timer::{Channel, Timer};
 
+/// Conversions between `Ticks` and a unit of time with `$per_second` units
+/// per second
+macro_rules! ticks {
+    ($Unit:ident, $per_second:expr) => {
+        impl From<Ticks> for $Unit {
+            fn from(ticks: Ticks) -> Self {
+                $Unit(::time::saturate(
+                    ticks.0 as u64 * $per_second / FREQUENCY as u64,
+                ))
+            }
+        }
+
+        impl From<$Unit> for Ticks {
+            fn from(x: $Unit) -> Ticks {
+                Ticks(::time::saturate(
+                    x.0 as u64 * FREQUENCY as u64 / $per_second,
+                ))
+            }
+        }
+
+        impl CheckedFrom<$Unit> for Ticks {
+            fn checked_from(x: $Unit) -> Option<Ticks> {
+                ::time::checked(x.0 as u64 * FREQUENCY as u64 / $per_second)
+                    .map(Ticks)
+            }
+        }
+    }
+}
+
 macro_rules! frequency {
     ($FREQUENCY:expr) => {
+        use core::ops;
+
         use time::*;
 
         /// Frequency
@@ -50,47 +94,56 @@ macro_rules! frequency {
             {
                 Ticks(f(self.0))
             }
-        }
 
-        impl From<Ticks> for Microseconds {
-            fn from(ticks: Ticks) -> Self {
-                Microseconds(ticks.0 / (FREQUENCY / 1_000_000))
+            /// Checked addition. Returns `None` on overflow
+            pub fn checked_add(self, rhs: Ticks) -> Option<Ticks> {
+                self.0.checked_add(rhs.0).map(Ticks)
             }
-        }
 
-        impl From<Ticks> for Milliseconds {
-            fn from(ticks: Ticks) -> Self {
-                Milliseconds(ticks.0 / (FREQUENCY / 1_000))
+            /// Checked subtraction. Returns `None` on underflow
+            pub fn checked_sub(self, rhs: Ticks) -> Option<Ticks> {
+                self.0.checked_sub(rhs.0).map(Ticks)
             }
-        }
 
-        impl From<Ticks> for Seconds {
-            fn from(ticks: Ticks) -> Self {
-                Seconds(ticks.0 / FREQUENCY)
+            /// Checked multiplication. Returns `None` on overflow
+            pub fn checked_mul(self, rhs: u32) -> Option<Ticks> {
+                self.0.checked_mul(rhs).map(Ticks)
             }
         }
 
-        impl From<IHertz> for Ticks {
-            fn from(ihz: IHertz) -> Ticks {
-                Ticks(FREQUENCY / ihz.0)
+        impl ops::Add for Ticks {
+            type Output = Ticks;
+
+            fn add(self, rhs: Ticks) -> Ticks {
+                Ticks(self.0.saturating_add(rhs.0))
             }
         }
 
-        impl From<Microseconds> for Ticks {
-            fn from(us: Microseconds) -> Ticks {
-                Ticks(us.0 * (FREQUENCY / 1_000_000))
+        impl ops::Sub for Ticks {
+            type Output = Ticks;
+
+            fn sub(self, rhs: Ticks) -> Ticks {
+                Ticks(self.0.saturating_sub(rhs.0))
             }
         }
 
-        impl From<Milliseconds> for Ticks {
-            fn from(ms: Milliseconds) -> Ticks {
-                Ticks(ms.0 * (FREQUENCY / 1_000))
+        impl ops::Mul<u32> for Ticks {
+            type Output = Ticks;
+
+            fn mul(self, rhs: u32) -> Ticks {
+                Ticks(self.0.saturating_mul(rhs))
             }
         }
 
-        impl From<Seconds> for Ticks {
-            fn from(s: Seconds) -> Ticks {
-                Ticks(s.0 * FREQUENCY)
+        ticks!(Nanoseconds, 1_000_000_000);
+        ticks!(Microseconds, 1_000_000);
+        ticks!(Milliseconds, 1_000);
+        ticks!(Seconds, 1);
+
+        impl From<IHertz> for Ticks {
+            fn from(ihz: IHertz) -> Ticks {
+                // NOTE the period of a 0 Hz signal saturates
+                Ticks(FREQUENCY.checked_div(ihz.0).unwrap_or(u32::max_value()))
             }
         }
 
@@ -110,9 +163,141 @@ pub mod ahb {
 /// Advance Peripheral Bus 1 (APB1)
 pub mod apb1 {
     frequency!(8_000_000);
+
+    /// Prescaler between the AHB and this bus
+    pub const PRESCALER: u32 = 1;
+
+    /// Clock of the timers connected to this bus
+    ///
+    /// The timer clock runs at twice the bus frequency when the bus is
+    /// prescaled
+    pub mod tim {
+        frequency!(super::FREQUENCY * (1 + (super::PRESCALER != 1) as u32));
+    }
 }
 
 /// Advance Peripheral Bus 2 (APB2)
 pub mod apb2 {
     frequency!(8_000_000);
+
+    /// Prescaler between the AHB and this bus
+    pub const PRESCALER: u32 = 1;
+
+    /// Clock of the timers connected to this bus
+    ///
+    /// The timer clock runs at twice the bus frequency when the bus is
+    /// prescaled
+    pub mod tim {
+        frequency!(super::FREQUENCY * (1 + (super::PRESCALER != 1) as u32));
+    }
+}
+
+/// High Speed Internal (HSI) RC oscillator
+pub mod hsi {
+    frequency!(8_000_000);
+}
+
+/// High Speed External (HSE) oscillator
+///
+/// NOTE on the STM32F3DISCOVERY the HSE input is the 8 MHz MCO output of the
+/// on-board ST-LINK
+pub mod hse {
+    frequency!(8_000_000);
+}
+
+/// Low Speed Internal (LSI) RC oscillator
+///
+/// NOTE the actual frequency of the LSI varies between 30 and 50 KHz from
+/// device to device and with temperature
+pub mod lsi {
+    frequency!(40_000);
+}
+
+/// Low Speed External (LSE) crystal oscillator
+pub mod lse {
+    frequency!(32_768);
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{CheckedFrom, IHertz, Microseconds, Milliseconds, Nanoseconds,
+               Seconds};
+
+    #[test]
+    fn ticks_from_unit() {
+        use ahb::Ticks;
+
+        let max = u32::max_value();
+
+        assert_eq!(Ticks::from(Seconds(0)), Ticks(0));
+        assert_eq!(Ticks::from(Seconds(1)), Ticks(8_000_000));
+        assert_eq!(Ticks::from(Milliseconds(1)), Ticks(8_000));
+        assert_eq!(Ticks::from(Nanoseconds(124)), Ticks(0));
+        assert_eq!(Ticks::from(Nanoseconds(125)), Ticks(1));
+        assert_eq!(Ticks::from(Seconds(max)), Ticks(max));
+        assert_eq!(Ticks::from(Milliseconds(max)), Ticks(max));
+
+        let ticks = Ticks::checked_from(Seconds(536));
+        assert_eq!(ticks, Some(Ticks(4_288_000_000)));
+        assert_eq!(Ticks::checked_from(Seconds(537)), None);
+        let ticks = Ticks::checked_from(Nanoseconds(max));
+        assert_eq!(ticks, Some(Ticks(34_359_738)));
+    }
+
+    #[test]
+    fn unit_from_ticks() {
+        use lse::Ticks;
+
+        let max = u32::max_value();
+
+        assert_eq!(Seconds::from(Ticks(0)), Seconds(0));
+        assert_eq!(Seconds::from(Ticks(32_767)), Seconds(0));
+        assert_eq!(Seconds::from(Ticks(32_768)), Seconds(1));
+        assert_eq!(Nanoseconds::from(Ticks(1)), Nanoseconds(30_517));
+        assert_eq!(Seconds::from(Ticks(max)), Seconds(131_071));
+        assert_eq!(Nanoseconds::from(Ticks(max)), Nanoseconds(max));
+        assert_eq!(Microseconds::from(Ticks(max)), Microseconds(max));
+    }
+
+    #[test]
+    fn round_trip() {
+        use lse::Ticks;
+
+        for &s in &[0, 1, 60, 131_071] {
+            assert_eq!(Seconds::from(Ticks::from(Seconds(s))), Seconds(s));
+        }
+
+        for &ms in &[0, 125, 1_000, 60_000] {
+            let ms = Milliseconds(ms);
+
+            assert_eq!(Milliseconds::from(Ticks::from(ms)), ms);
+        }
+    }
+
+    #[test]
+    fn ticks_arithmetic() {
+        use ahb::Ticks;
+
+        let max = u32::max_value();
+
+        assert_eq!(Ticks(max) + Ticks(1), Ticks(max));
+        assert_eq!(Ticks(0) - Ticks(1), Ticks(0));
+        assert_eq!(Ticks(max) * 2, Ticks(max));
+        assert_eq!(Ticks(max).checked_add(Ticks(1)), None);
+        assert_eq!(Ticks(1).checked_sub(Ticks(2)), None);
+        assert_eq!(Ticks(max / 2).checked_mul(2), Some(Ticks(max - 1)));
+        assert_eq!(Ticks(1).map(|t| t + 1), Ticks(2));
+    }
+
+    #[test]
+    fn ticks_from_ihertz() {
+        use ahb::Ticks;
+
+        // the period of a 0 Hz signal saturates
+        assert_eq!(Ticks::from(IHertz(0)), Ticks(u32::max_value()));
+        assert_eq!(Ticks::from(IHertz(1)), Ticks(8_000_000));
+        assert_eq!(Ticks::from(IHertz(8_000_000)), Ticks(1));
+        // frequencies above the clock round down to zero ticks
+        assert_eq!(Ticks::from(IHertz(8_000_001)), Ticks(0));
+    }
 }
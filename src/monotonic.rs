@@ -0,0 +1,284 @@
+//! Monotonic system time
+//!
+//! `Monotonic` turns TIM2 into a free running 64-bit clock: the 32-bit
+//! counter of TIM2 runs at the timer clock frequency (`apb1::tim::FREQUENCY`)
+//! and its overflows are counted in software, in the TIM2 interrupt. At 8 MHz
+//! the counter overflows every ~9 minutes and the 64-bit clock will never
+//! wrap around.
+//!
+//! NOTE TIM2 can't be used as a `Timer`, `Pwm` or `Capture` while it's being
+//! used as the monotonic clock.
+//!
+//! ``` ignore
+//! Monotonic(&tim2).init(rcc);
+//!
+//! // TIM2 interrupt handler
+//! Monotonic(&tim2).on_interrupt();
+//!
+//! // anywhere else, including other interrupt handlers
+//! let clock = Monotonic(&tim2);
+//! let timeout = clock.timeout(10.ms());
+//! while serial.read().is_err() {
+//!     if timeout.wait(&clock).is_ok() {
+//!         // timed out
+//!     }
+//! }
+//! ```
+
+use core::ops;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use cortex_m::interrupt;
+use nb;
+use stm32f30x::{Rcc, Tim2};
+
+use time::{Microseconds, Milliseconds, Seconds};
+
+const FREQUENCY: u64 = ::apb1::tim::FREQUENCY as u64;
+
+/// Number of times the TIM2 counter has overflowed
+static OVERFLOWS: AtomicUsize = AtomicUsize::new(0);
+
+/// A point in time, as measured by the `Monotonic` clock
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// Returns the number of ticks elapsed between the initialization of the
+    /// clock and this instant
+    pub fn ticks(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns the time elapsed between this instant and `earlier`
+    ///
+    /// Returns a zero duration if `earlier` is actually later than this
+    /// instant.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration(self.0.saturating_sub(earlier.0))
+    }
+
+    /// Returns the time elapsed since this instant was taken
+    pub fn elapsed(&self, clock: &Monotonic) -> Duration {
+        clock.now().duration_since(*self)
+    }
+}
+
+impl ops::Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Instant(self.0.saturating_add(rhs.0))
+    }
+}
+
+impl ops::Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, rhs: Duration) -> Instant {
+        Instant(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl ops::Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+/// A span of time, in ticks of the `Monotonic` clock
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Duration(u64);
+
+impl Duration {
+    /// Creates a duration of `ticks` clock ticks
+    pub const fn from_ticks(ticks: u64) -> Self {
+        Duration(ticks)
+    }
+
+    /// Returns the number of clock ticks in this duration
+    pub fn ticks(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns this duration in whole microseconds
+    pub fn as_micros(&self) -> u64 {
+        self.scale(1_000_000)
+    }
+
+    /// Returns this duration in whole milliseconds
+    pub fn as_millis(&self) -> u64 {
+        self.scale(1_000)
+    }
+
+    /// Returns this duration in whole seconds
+    pub fn as_secs(&self) -> u64 {
+        self.0 / FREQUENCY
+    }
+
+    // converts ticks to `1 / unit` seconds without overflowing the
+    // intermediate product
+    fn scale(&self, unit: u64) -> u64 {
+        let secs = self.0 / FREQUENCY;
+        let rem = self.0 % FREQUENCY;
+
+        secs.saturating_mul(unit)
+            .saturating_add(rem * unit / FREQUENCY)
+    }
+}
+
+impl ops::Add for Duration {
+    type Output = Duration;
+
+    fn add(self, rhs: Duration) -> Duration {
+        Duration(self.0.saturating_add(rhs.0))
+    }
+}
+
+impl ops::Sub for Duration {
+    type Output = Duration;
+
+    fn sub(self, rhs: Duration) -> Duration {
+        Duration(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl From<Microseconds> for Duration {
+    fn from(us: Microseconds) -> Duration {
+        Duration(us.0 as u64 * FREQUENCY / 1_000_000)
+    }
+}
+
+impl From<Milliseconds> for Duration {
+    fn from(ms: Milliseconds) -> Duration {
+        Duration(ms.0 as u64 * FREQUENCY / 1_000)
+    }
+}
+
+impl From<Seconds> for Duration {
+    fn from(s: Seconds) -> Duration {
+        Duration(s.0 as u64 * FREQUENCY)
+    }
+}
+
+/// A deadline on the `Monotonic` clock
+#[derive(Clone, Copy, Debug)]
+pub struct Timeout {
+    deadline: Instant,
+}
+
+impl Timeout {
+    /// Returns the instant at which this timeout expires
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Checks whether the timeout has expired
+    pub fn expired(&self, clock: &Monotonic) -> bool {
+        clock.now() >= self.deadline
+    }
+
+    /// Waits until the timeout expires
+    pub fn wait(&self, clock: &Monotonic) -> nb::Result<(), !> {
+        if self.expired(clock) {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+/// Monotonic clock built on top of TIM2
+pub struct Monotonic<'a>(pub &'a Tim2);
+
+impl<'a> Clone for Monotonic<'a> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a> Copy for Monotonic<'a> {}
+
+impl<'a> Monotonic<'a> {
+    /// Starts the clock
+    ///
+    /// NOTE the TIM2 interrupt must be enabled in the NVIC and must call
+    /// `on_interrupt`, otherwise the clock will go back in time every time
+    /// the TIM2 counter overflows
+    pub fn init(&self, rcc: &Rcc) {
+        let tim2 = self.0;
+
+        // Enable and reset TIM2
+        rcc.apb1enr.modify(|_, w| w.tim2en().enabled());
+        rcc.apb1rstr.modify(|_, w| unsafe { w.tim2rst().bits(1) });
+        rcc.apb1rstr.modify(|_, w| unsafe { w.tim2rst().bits(0) });
+
+        OVERFLOWS.store(0, Ordering::Relaxed);
+
+        // count every tick of the timer clock, over the whole 32-bit range
+        tim2.psc.write(|w| unsafe { w.psc().bits(0) });
+        tim2.arr.write(|w| unsafe { w.bits(0xffff_ffff) });
+
+        // urs: only counter overflows raise the update flag
+        tim2.cr1.write(|w| unsafe { w.urs().bits(1) });
+
+        // Load the prescaler
+        tim2.egr.write(|w| unsafe { w.ug().bits(1) });
+
+        // Enable the update event interrupt
+        tim2.dier.write(|w| unsafe { w.uie().bits(1) });
+
+        tim2.cr1.modify(|_, w| unsafe { w.cen().bits(1) });
+    }
+
+    /// Accounts for an overflow of the TIM2 counter
+    ///
+    /// This must be called from the TIM2 interrupt handler
+    pub fn on_interrupt(&self) {
+        let tim2 = self.0;
+
+        interrupt::free(|_| if tim2.sr.read().uif().bits() == 1 {
+            tim2.sr.modify(|_, w| unsafe { w.uif().bits(0) });
+
+            let overflows = OVERFLOWS.load(Ordering::Relaxed);
+            OVERFLOWS.store(overflows.wrapping_add(1), Ordering::Relaxed);
+        })
+    }
+
+    /// Returns the current time
+    ///
+    /// This can be called from any context, including interrupt handlers
+    /// that preempt (or are masking) the TIM2 interrupt.
+    pub fn now(&self) -> Instant {
+        let tim2 = self.0;
+
+        interrupt::free(|_| {
+            let high = OVERFLOWS.load(Ordering::Relaxed) as u32;
+            let low = tim2.cnt.read().bits();
+
+            // The counter may have overflowed and the TIM2 interrupt not run
+            // yet. If the overflow is pending and the counter value is small
+            // then `low` was read after the overflow and must be accounted
+            // for
+            let high = if tim2.sr.read().uif().bits() == 1 && low < 1 << 31 {
+                high.wrapping_add(1)
+            } else {
+                high
+            };
+
+            Instant((high as u64) << 32 | low as u64)
+        })
+    }
+
+    /// Returns a timeout that expires `duration` from now
+    pub fn timeout<D>(&self, duration: D) -> Timeout
+    where
+        D: Into<Duration>,
+    {
+        Timeout {
+            deadline: self.now() + duration.into(),
+        }
+    }
+}
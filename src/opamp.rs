@@ -0,0 +1,205 @@
+//! Operational amplifiers (OPAMP1 - OPAMP4)
+//!
+//! Each op-amp can work as a standalone amplifier (both inputs and the
+//! output on pins), as a follower or as a Programmable Gain Amplifier (PGA)
+//! with a non-inverting gain of 2, 4, 8 or 16. The outputs are also
+//! connected to ADC channels, see the reference manual.
+//!
+//! NOTE the input and output pins must be configured in analog mode; this
+//! module doesn't configure them.
+//!
+//! | Op-amp | VINP0 | VINP1 | VINP2 | VINP3 | VINM0 | VINM1 | VOUT |
+//! |--------|-------|-------|-------|-------|-------|-------|------|
+//! | OPAMP1 | PA7   | PA5   | PA3   | PA1   | PC5   | PA3   | PA2  |
+//! | OPAMP2 | PD14  | PB14  | PB0   | PA7   | PC5   | PA5   | PA6  |
+//! | OPAMP3 | PB13  | PA5   | PA1   | PB0   | PB10  | PB2   | PB1  |
+//! | OPAMP4 | PD11  | PB11  | PA4   | PB13  | PB10  | PD8   | PB12 |
+
+use core::ptr;
+
+use stm32f30x::{Rcc, SyscfgCompOpamp};
+
+// OPAMPx_CSR bits
+const EN: u32 = 1 << 0;
+const VP_SEL: u32 = 0b11 << 2;
+const VM_SEL: u32 = 0b11 << 5;
+const PGA_GAIN: u32 = 0b1111 << 14;
+const OUTCAL: u32 = 1 << 30;
+const LOCK: u32 = 1 << 31;
+
+/// Operational amplifier
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Opamp {
+    /// OPAMP1
+    _1,
+    /// OPAMP2
+    _2,
+    /// OPAMP3
+    _3,
+    /// OPAMP4
+    _4,
+}
+
+impl Opamp {
+    fn index(&self) -> isize {
+        match *self {
+            Opamp::_1 => 0,
+            Opamp::_2 => 1,
+            Opamp::_3 => 2,
+            Opamp::_4 => 3,
+        }
+    }
+}
+
+/// Non-inverting input pin
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NonInverting {
+    /// VINP0
+    Vinp0,
+    /// VINP1
+    Vinp1,
+    /// VINP2
+    Vinp2,
+    /// VINP3
+    Vinp3,
+}
+
+/// Inverting input pin
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Inverting {
+    /// VINM0
+    Vinm0,
+    /// VINM1
+    Vinm1,
+}
+
+impl Inverting {
+    fn vm_sel(&self) -> u32 {
+        match *self {
+            Inverting::Vinm0 => 0b00,
+            Inverting::Vinm1 => 0b01,
+        }
+    }
+}
+
+/// PGA gain
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Gain {
+    /// x2
+    _2,
+    /// x4
+    _4,
+    /// x8
+    _8,
+    /// x16
+    _16,
+}
+
+impl Gain {
+    fn pga_gain(&self) -> u32 {
+        match *self {
+            Gain::_2 => 0b00,
+            Gain::_4 => 0b01,
+            Gain::_8 => 0b10,
+            Gain::_16 => 0b11,
+        }
+    }
+}
+
+/// Operating mode
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mode {
+    /// Standalone amplifier; the feedback network is external
+    Standalone(Inverting),
+    /// Voltage follower
+    Follower,
+    /// Programmable gain amplifier
+    Pga(Gain),
+    /// Programmable gain amplifier with the internal feedback network
+    /// exposed on an inverting input pin, e.g. to add a filtering capacitor
+    PgaFiltered(Gain, Inverting),
+}
+
+/// Operational amplifier
+pub struct OpAmp<'a>(pub &'a SyscfgCompOpamp, pub Opamp);
+
+impl<'a> Clone for OpAmp<'a> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a> Copy for OpAmp<'a> {}
+
+impl<'a> OpAmp<'a> {
+    /// Initializes and enables the op-amp
+    pub fn init(&self, input: NonInverting, mode: Mode, rcc: &Rcc) {
+        rcc.apb2enr.modify(|_, w| w.syscfgen().enabled());
+
+        let vp_sel = match input {
+            NonInverting::Vinp0 => 0b00,
+            NonInverting::Vinp1 => 0b01,
+            NonInverting::Vinp2 => 0b10,
+            NonInverting::Vinp3 => 0b11,
+        };
+
+        self.modify(|r| r & !(EN | VP_SEL) | vp_sel << 2);
+        self.set_mode(mode);
+        self.modify(|r| r | EN);
+    }
+
+    /// Disables the op-amp
+    pub fn disable(&self) {
+        self.modify(|r| r & !EN);
+    }
+
+    /// Changes the operating mode
+    pub fn set_mode(&self, mode: Mode) {
+        let (vm_sel, pga_gain) = match mode {
+            Mode::Standalone(inverting) => (inverting.vm_sel(), 0),
+            Mode::Follower => (0b11, 0),
+            Mode::Pga(gain) => (0b10, gain.pga_gain()),
+            Mode::PgaFiltered(gain, inverting) => {
+                // the feedback network is connected to VINM0 (0b10xx) or
+                // VINM1 (0b11xx)
+                (0b10, 0b1000 | inverting.vm_sel() << 2 | gain.pga_gain())
+            }
+        };
+
+        self.modify(|r| {
+            r & !(VM_SEL | PGA_GAIN) | vm_sel << 5 | pga_gain << 14
+        });
+    }
+
+    /// Returns the output of the calibration comparator
+    ///
+    /// NOTE only meaningful while the op-amp is in calibration mode; see the
+    /// reference manual for the offset trimming procedure
+    pub fn calibration_output(&self) -> bool {
+        self.read() & OUTCAL != 0
+    }
+
+    /// Makes the op-amp configuration read-only until the next reset
+    pub fn lock(&self) {
+        self.modify(|r| r | LOCK);
+    }
+
+    fn csr(&self) -> *mut u32 {
+        // NOTE OPAMP1_CSR - OPAMP4_CSR are contiguous
+        let opamp1_csr = &self.0.opamp1_csr as *const _ as *mut u32;
+
+        unsafe { opamp1_csr.offset(self.1.index()) }
+    }
+
+    fn read(&self) -> u32 {
+        unsafe { ptr::read_volatile(self.csr()) }
+    }
+
+    fn modify<F>(&self, f: F)
+    where
+        F: FnOnce(u32) -> u32,
+    {
+        let bits = self.read();
+        unsafe { ptr::write_volatile(self.csr(), f(bits)) }
+    }
+}
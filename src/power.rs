@@ -0,0 +1,340 @@
+//! Low power modes
+//!
+//! - Sleep: the core clock is stopped; any interrupt (WFI) or event (WFE)
+//!   wakes up the core.
+//! - Stop: all the clocks of the 1.8V domain are stopped; RAM and registers
+//!   are preserved. Any EXTI line configured as interrupt / event wakes up
+//!   the device, which then runs from the HSI. `stop` restores the previous
+//!   clock configuration before returning.
+//! - Standby: the 1.8V domain is powered off; only the backup domain
+//!   survives. The WKUP pins, the RTC alarms / wakeup timer, the IWDG and
+//!   the NRST pin wake up the device, which then goes through a reset. Use
+//!   `reset_flags` to tell a wakeup from standby apart from other resets.
+//!
+//! The RTC (see `rtc::RealTimeClock::listen`) drives EXTI lines 17 (alarms)
+//! and 20 (wakeup timer); `listen_exti` configures any other EXTI line,
+//! including lines 32 - 35 (e.g. the COMP7 output).
+//!
+//! NOTE GPIO pins are routed to EXTI lines 0 - 15 through the SYSCFG, which
+//! is not configured by this module
+
+use cortex_m::asm;
+use cortex_m::peripheral::Scb;
+use stm32f30x::{Exti, Pwr, Rcc};
+
+// SCB.SCR bits
+const SLEEPONEXIT: u32 = 1 << 1;
+const SLEEPDEEP: u32 = 1 << 2;
+
+/// Number of EXTI lines
+const EXTI_LINES: u8 = 36;
+
+/// First direct EXTI line of the second register bank; lines 34 and 35 have
+/// no edge selection
+const EXTI_DIRECT: u8 = 34;
+
+/// How the core waits in sleep and stop modes
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WaitFor {
+    /// Wait For Interrupt (WFI)
+    Interrupt,
+    /// Wait For Event (WFE)
+    Event,
+}
+
+/// Voltage regulator mode in stop mode
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Regulator {
+    /// Regulator on; faster wakeup
+    On,
+    /// Regulator in low power mode; lower consumption
+    LowPower,
+}
+
+/// Pin that wakes up the device from standby mode on a rising edge
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WakeupPin {
+    /// WKUP1 = PA0
+    Pa0,
+    /// WKUP2 = PC13
+    Pc13,
+    /// WKUP3 = PE6
+    Pe6,
+}
+
+/// Edge that triggers an EXTI line
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Edge {
+    /// Rising edge
+    Rising,
+    /// Falling edge
+    Falling,
+    /// Both edges
+    Both,
+}
+
+/// EXTI line (0 - 35)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ExtiLine(pub(crate) u8);
+
+impl ExtiLine {
+    /// Returns EXTI line `line`, or `None` if the line doesn't exist
+    pub fn new(line: u8) -> Option<Self> {
+        if line < EXTI_LINES {
+            Some(ExtiLine(line))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the line number
+    pub fn number(&self) -> u8 {
+        self.0
+    }
+}
+
+/// Cause of the last reset
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ResetFlags {
+    /// Low power management reset
+    pub low_power: bool,
+    /// Window watchdog reset
+    pub window_watchdog: bool,
+    /// Independent watchdog reset
+    pub independent_watchdog: bool,
+    /// Software reset
+    pub software: bool,
+    /// Power on / power down reset
+    pub power_on: bool,
+    /// NRST pin reset
+    pub pin: bool,
+    /// Option byte loader reset
+    pub option_byte: bool,
+    /// The device was in standby mode
+    pub standby: bool,
+    /// A wakeup event occurred (WKUP pin or RTC)
+    pub wakeup: bool,
+}
+
+/// Power controller
+pub struct Power<'a>(pub &'a Pwr);
+
+impl<'a> Clone for Power<'a> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a> Copy for Power<'a> {}
+
+impl<'a> Power<'a> {
+    /// Enables the power controller
+    pub fn init(&self, rcc: &Rcc) {
+        rcc.apb1enr.modify(|_, w| w.pwren().enabled());
+    }
+
+    /// Enters sleep mode
+    pub fn sleep(&self, wait: WaitFor, scb: &Scb) {
+        unsafe { scb.scr.modify(|r| r & !SLEEPDEEP) }
+
+        self.wait(wait);
+    }
+
+    /// Makes the core go back to sleep mode when it returns from the last
+    /// pending interrupt handler
+    ///
+    /// Useful for applications that run exclusively from interrupts
+    pub fn set_sleep_on_exit(&self, enabled: bool, scb: &Scb) {
+        unsafe {
+            scb.scr.modify(|r| if enabled {
+                r | SLEEPONEXIT
+            } else {
+                r & !SLEEPONEXIT
+            })
+        }
+    }
+
+    /// Enters stop mode
+    ///
+    /// The device wakes up running from the HSI; this function re-enables
+    /// the HSE and the PLL, if they were on, and switches the system clock
+    /// back to its previous source before returning.
+    pub fn stop(&self, regulator: Regulator, wait: WaitFor, scb: &Scb, rcc: &Rcc) {
+        let pwr = self.0;
+
+        let cr = rcc.cr.read();
+        let sw = rcc.cfgr.read().sw().bits();
+
+        let lpds = match regulator {
+            Regulator::On => 0,
+            Regulator::LowPower => 1,
+        };
+        pwr.cr.modify(|_, w| unsafe {
+            w.pdds().bits(0).lpds().bits(lpds).cwuf().bits(1)
+        });
+
+        unsafe { scb.scr.modify(|r| r | SLEEPDEEP) }
+        self.wait(wait);
+        unsafe { scb.scr.modify(|r| r & !SLEEPDEEP) }
+
+        // restore the clock tree
+        if cr.hseon().bits() == 1 {
+            rcc.cr.modify(|_, w| unsafe { w.hseon().bits(1) });
+            while rcc.cr.read().hserdy().bits() == 0 {}
+        }
+
+        if cr.pllon().bits() == 1 {
+            rcc.cr.modify(|_, w| unsafe { w.pllon().bits(1) });
+            while rcc.cr.read().pllrdy().bits() == 0 {}
+        }
+
+        // SW: system clock switch, SWS: system clock switch status
+        rcc.cfgr.modify(|_, w| unsafe { w.sw().bits(sw) });
+        while rcc.cfgr.read().sws().bits() != sw {}
+    }
+
+    /// Enters standby mode
+    ///
+    /// The device goes through a reset when it wakes up so this function
+    /// never returns.
+    pub fn standby(&self, scb: &Scb) -> ! {
+        let pwr = self.0;
+
+        // clear the wakeup flag, otherwise the device would wake up
+        // immediately
+        pwr.cr.modify(|_, w| unsafe { w.pdds().bits(1).cwuf().bits(1) });
+
+        unsafe { scb.scr.modify(|r| r | SLEEPDEEP) }
+
+        loop {
+            asm::wfi();
+        }
+    }
+
+    /// Enables `pin` as a wakeup source from standby mode
+    ///
+    /// NOTE this forces the pin into input pull-down mode
+    pub fn enable_wakeup_pin(&self, pin: WakeupPin) {
+        self.set_wakeup_pin(pin, 1);
+    }
+
+    /// Disables `pin` as a wakeup source from standby mode
+    pub fn disable_wakeup_pin(&self, pin: WakeupPin) {
+        self.set_wakeup_pin(pin, 0);
+    }
+
+    /// Returns the cause of the last reset
+    pub fn reset_flags(&self, rcc: &Rcc) -> ResetFlags {
+        let csr = rcc.csr.read();
+        let pwr_csr = self.0.csr.read();
+
+        ResetFlags {
+            low_power: csr.lpwrrstf().bits() == 1,
+            window_watchdog: csr.wwdgrstf().bits() == 1,
+            independent_watchdog: csr.iwdgrstf().bits() == 1,
+            software: csr.sftrstf().bits() == 1,
+            power_on: csr.porrstf().bits() == 1,
+            pin: csr.pinrstf().bits() == 1,
+            option_byte: csr.oblrstf().bits() == 1,
+            standby: pwr_csr.sbf().bits() == 1,
+            wakeup: pwr_csr.wuf().bits() == 1,
+        }
+    }
+
+    /// Clears the reset, standby and wakeup flags
+    pub fn clear_reset_flags(&self, rcc: &Rcc) {
+        rcc.csr.modify(|_, w| unsafe { w.rmvf().bits(1) });
+        self.0.cr.modify(|_, w| unsafe { w.csbf().bits(1).cwuf().bits(1) });
+    }
+
+    fn set_wakeup_pin(&self, pin: WakeupPin, bit: u8) {
+        self.0.csr.modify(|_, w| unsafe {
+            match pin {
+                WakeupPin::Pa0 => w.ewup1().bits(bit),
+                WakeupPin::Pc13 => w.ewup2().bits(bit),
+                WakeupPin::Pe6 => w.ewup3().bits(bit),
+            }
+        });
+    }
+
+    fn wait(&self, wait: WaitFor) {
+        match wait {
+            WaitFor::Interrupt => asm::wfi(),
+            WaitFor::Event => asm::wfe(),
+        }
+    }
+}
+
+/// Sets or clears the bit of `line` in the pair of EXTI registers
+/// `$reg1` (lines 0 - 31) / `$reg2` (lines 32 - 35)
+macro_rules! exti_modify {
+    ($exti:ident, $reg1:ident, $reg2:ident, $line:expr, $set:expr) => {{
+        let line = $line.0;
+        let set = $set;
+
+        if line < 32 {
+            let mask = 1 << line;
+
+            $exti.$reg1.modify(|r, w| unsafe {
+                w.bits(if set { r.bits() | mask } else { r.bits() & !mask })
+            });
+        } else {
+            let mask = 1 << (line - 32);
+
+            $exti.$reg2.modify(|r, w| unsafe {
+                w.bits(if set { r.bits() | mask } else { r.bits() & !mask })
+            });
+        }
+    }}
+}
+
+/// Configures EXTI `line` to wake up the device from sleep or stop mode
+///
+/// With `WaitFor::Interrupt` the line raises an interrupt, which must also
+/// be enabled in the NVIC; with `WaitFor::Event` it raises an event, which
+/// only wakes up the core.
+///
+/// NOTE lines 34 and 35 are direct lines: their peripheral sets the level
+/// and `edge` is ignored
+pub fn listen_exti(line: ExtiLine, edge: Edge, wait: WaitFor, exti: &Exti) {
+    let (rising, falling) = match edge {
+        Edge::Rising => (true, false),
+        Edge::Falling => (false, true),
+        Edge::Both => (true, true),
+    };
+
+    if line.0 < EXTI_DIRECT {
+        exti_modify!(exti, rtsr1, rtsr2, line, rising);
+        exti_modify!(exti, ftsr1, ftsr2, line, falling);
+    }
+
+    match wait {
+        WaitFor::Interrupt => exti_modify!(exti, imr1, imr2, line, true),
+        WaitFor::Event => exti_modify!(exti, emr1, emr2, line, true),
+    }
+}
+
+/// Stops EXTI `line` from raising interrupts and events
+pub fn unlisten_exti(line: ExtiLine, exti: &Exti) {
+    exti_modify!(exti, imr1, imr2, line, false);
+    exti_modify!(exti, emr1, emr2, line, false);
+}
+
+/// Checks whether EXTI `line` has a pending interrupt
+pub fn is_exti_pending(line: ExtiLine, exti: &Exti) -> bool {
+    if line.0 < 32 {
+        exti.pr1.read().bits() & (1 << line.0) != 0
+    } else {
+        exti.pr2.read().bits() & (1 << (line.0 - 32)) != 0
+    }
+}
+
+/// Clears the pending interrupt of EXTI `line`
+pub fn clear_exti(line: ExtiLine, exti: &Exti) {
+    // NOTE the pending bits are cleared by writing 1
+    if line.0 < 32 {
+        exti.pr1.write(|w| unsafe { w.bits(1 << line.0) });
+    } else {
+        exti.pr2.write(|w| unsafe { w.bits(1 << (line.0 - 32)) });
+    }
+}
@@ -0,0 +1,796 @@
+//! Pulse Width Modulation
+//!
+//! You can use the `Pwm` interface with these timers
+//!
+//! # TIM1
+//!
+//! - CH1 = PE9, CH1N = PE8
+//! - CH2 = PE11, CH2N = PE10
+//! - CH3 = PE13, CH3N = PE12
+//! - CH4 = PE14
+//! - BKIN = PE15
+//!
+//! **NOTE** These pins are connected to the user LEDs
+//!
+//! # TIM8
+//!
+//! - CH1 = PC6, CH1N = PC10
+//! - CH2 = PC7, CH2N = PC11
+//! - CH3 = PC8, CH3N = PC12
+//! - CH4 = PC9
+//! - BKIN = PD2 (not configured by this driver)
+//!
+//! The BKIN2 inputs (TIM1: PE14, TIM8: PC9) share their pin with CH4 and are
+//! not configured by this driver.
+//!
+//! # TIM15
+//!
+//! - CH1 = PA2, CH1N = PA1
+//! - CH2 = PA3
+//!
+//! # TIM16
+//!
+//! - CH1 = PB8, CH1N = PB6
+//!
+//! # TIM17
+//!
+//! - CH1 = PB5, CH1N = PB7
+//!
+//! # IR_OUT
+//!
+//! - IR_OUT = PB9
+
+use cast::u32;
+use hal;
+use stm32f30x::{Gpiob, Rcc, Tim15, Tim16, Tim17};
+
+use timer::{self, ATIM, Channel, GTIM, Tim15Channel, Tim16Channel,
+            Tim17Channel};
+
+/// PWM error
+#[derive(Debug)]
+pub enum Error {
+    /// The dead time is longer than 4032 ticks
+    DeadTimeTooLong,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Channel with a complementary output
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ComplementaryChannel {
+    /// TxC1N
+    _1,
+    /// TxC2N
+    _2,
+    /// TxC3N
+    _3,
+}
+
+/// Break input
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Break {
+    /// BKIN
+    Bkin,
+    /// BKIN2
+    Bkin2,
+}
+
+/// Active level of a break input
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Polarity {
+    /// The break input is active low
+    ActiveLow,
+    /// The break input is active high
+    ActiveHigh,
+}
+
+/// Output level
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Level {
+    /// Low
+    Low,
+    /// High
+    High,
+}
+
+impl Level {
+    fn bits(self) -> u8 {
+        match self {
+            Level::Low => 0,
+            Level::High => 1,
+        }
+    }
+}
+
+/// Digital filter applied to a break input
+///
+/// An edge of the input is only validated after `N` consecutive samples at
+/// the new level; the samples are taken at the given fraction of the dead
+/// time generator clock (`DTS`) or at the timer clock (`CkInt`).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Filter {
+    /// No filter
+    None,
+    /// Timer clock, N = 2
+    CkIntN2,
+    /// Timer clock, N = 4
+    CkIntN4,
+    /// Timer clock, N = 8
+    CkIntN8,
+    /// DTS / 2, N = 6
+    Dts2N6,
+    /// DTS / 2, N = 8
+    Dts2N8,
+    /// DTS / 4, N = 6
+    Dts4N6,
+    /// DTS / 4, N = 8
+    Dts4N8,
+    /// DTS / 8, N = 6
+    Dts8N6,
+    /// DTS / 8, N = 8
+    Dts8N8,
+    /// DTS / 16, N = 5
+    Dts16N5,
+    /// DTS / 16, N = 6
+    Dts16N6,
+    /// DTS / 16, N = 8
+    Dts16N8,
+    /// DTS / 32, N = 5
+    Dts32N5,
+    /// DTS / 32, N = 6
+    Dts32N6,
+    /// DTS / 32, N = 8
+    Dts32N8,
+}
+
+impl Filter {
+    fn bits(self) -> u8 {
+        match self {
+            Filter::None => 0b0000,
+            Filter::CkIntN2 => 0b0001,
+            Filter::CkIntN4 => 0b0010,
+            Filter::CkIntN8 => 0b0011,
+            Filter::Dts2N6 => 0b0100,
+            Filter::Dts2N8 => 0b0101,
+            Filter::Dts4N6 => 0b0110,
+            Filter::Dts4N8 => 0b0111,
+            Filter::Dts8N6 => 0b1000,
+            Filter::Dts8N8 => 0b1001,
+            Filter::Dts16N5 => 0b1010,
+            Filter::Dts16N6 => 0b1011,
+            Filter::Dts16N8 => 0b1100,
+            Filter::Dts32N5 => 0b1101,
+            Filter::Dts32N6 => 0b1110,
+            Filter::Dts32N8 => 0b1111,
+        }
+    }
+}
+
+/// Computes the clock division (`CKD`) and dead-time generator (`DTG`)
+/// settings for a dead time of at least `ticks` timer clock cycles
+///
+/// Returns `Error::DeadTimeTooLong` if the dead time is longer than 4032
+/// ticks.
+pub fn dead_time(ticks: u32) -> Result<(u8, u8), Error> {
+    let (ckd, dts) = if ticks <= 1008 {
+        (0b00, ticks)
+    } else if ticks <= 2016 {
+        (0b01, (ticks + 1) / 2)
+    } else if ticks <= 4032 {
+        (0b10, (ticks + 3) / 4)
+    } else {
+        return Err(Error::DeadTimeTooLong);
+    };
+
+    let dtg = if dts < 128 {
+        // DT = DTG[7:0] * tDTS
+        dts
+    } else if dts <= 254 {
+        // DT = (64 + DTG[5:0]) * 2 * tDTS
+        0b1000_0000 | ((dts + 1) / 2 - 64)
+    } else if dts <= 504 {
+        // DT = (32 + DTG[4:0]) * 8 * tDTS
+        0b1100_0000 | ((dts + 7) / 8 - 32)
+    } else {
+        // DT = (32 + DTG[4:0]) * 16 * tDTS
+        0b1110_0000 | ((dts + 15) / 16 - 32)
+    };
+
+    Ok((ckd, dtg as u8))
+}
+
+/// PWM driver
+pub struct Pwm<'a, T>(pub &'a T)
+where
+    T: 'a;
+
+impl<'a, T> Clone for Pwm<'a, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T> Copy for Pwm<'a, T> {}
+
+impl<'a, T> Pwm<'a, T>
+where
+    T: ATIM,
+{
+    /// Initializes the PWM module with a period of `period` ticks
+    ///
+    /// All the channels are configured in PWM mode 1 with a duty cycle of
+    /// zero. The main output stays disabled until `enable_outputs` is
+    /// called; the timer starts counting right away.
+    pub fn init<P>(&self, period: P, gpio: &T::GPIO, rcc: &Rcc)
+    where
+        P: Into<::apb2::tim::Ticks>,
+    {
+        self._init(period.into(), gpio, rcc)
+    }
+
+    fn _init(&self, period: ::apb2::tim::Ticks, gpio: &T::GPIO, rcc: &Rcc) {
+        let tim = self.0;
+
+        T::enable(rcc);
+        T::reset(rcc);
+        T::configure_pins(gpio, rcc);
+
+        // PWM mode 1, preload enabled
+        tim.ccmr1_output.write(|w| unsafe {
+            w.oc1pe()
+                .bits(1)
+                .oc1m()
+                .bits(0b110)
+                .oc2pe()
+                .bits(1)
+                .oc2m()
+                .bits(0b110)
+        });
+        tim.ccmr2_output.write(|w| unsafe {
+            w.oc3pe()
+                .bits(1)
+                .oc3m()
+                .bits(0b110)
+                .oc4pe()
+                .bits(1)
+                .oc4m()
+                .bits(0b110)
+        });
+
+        // active high outputs, all disabled
+        tim.ccer.write(|w| unsafe { w.bits(0) });
+
+        // main output disabled
+        tim.bdtr.write(|w| unsafe { w.moe().bits(0) });
+
+        self._set_period(period);
+
+        // arpe: ARR is buffered
+        // cms: edge-aligned mode
+        // dir: upcounter
+        // opm: continuous mode
+        tim.cr1.write(|w| unsafe {
+            w.arpe()
+                .bits(1)
+                .cms()
+                .bits(0b00)
+                .dir()
+                .bits(0)
+                .opm()
+                .bits(0)
+        });
+
+        // load the prescaler and the duty cycles
+        tim.egr.write(|w| unsafe { w.ug().bits(1) });
+
+        tim.cr1.modify(|_, w| unsafe { w.cen().bits(1) });
+    }
+
+    fn _set_period(&self, period: ::apb2::tim::Ticks) {
+        let (psc, arr) = timer::psc_arr_saturating(period.0, 0xffff);
+
+        self.0.psc.write(|w| unsafe { w.psc().bits(psc) });
+        self.0.arr.write(|w| unsafe { w.arr().bits(arr as u16) });
+    }
+
+    /// Enables the complementary output of a `channel`
+    pub fn enable_complementary(&self, channel: ComplementaryChannel) {
+        let ccer = &self.0.ccer;
+
+        match channel {
+            ComplementaryChannel::_1 => {
+                ccer.modify(|_, w| unsafe { w.cc1ne().bits(1) })
+            }
+            ComplementaryChannel::_2 => {
+                ccer.modify(|_, w| unsafe { w.cc2ne().bits(1) })
+            }
+            ComplementaryChannel::_3 => {
+                ccer.modify(|_, w| unsafe { w.cc3ne().bits(1) })
+            }
+        }
+    }
+
+    /// Disables the complementary output of a `channel`
+    pub fn disable_complementary(&self, channel: ComplementaryChannel) {
+        let ccer = &self.0.ccer;
+
+        match channel {
+            ComplementaryChannel::_1 => {
+                ccer.modify(|_, w| unsafe { w.cc1ne().bits(0) })
+            }
+            ComplementaryChannel::_2 => {
+                ccer.modify(|_, w| unsafe { w.cc2ne().bits(0) })
+            }
+            ComplementaryChannel::_3 => {
+                ccer.modify(|_, w| unsafe { w.cc3ne().bits(0) })
+            }
+        }
+    }
+
+    /// Sets the dead time inserted between the switching of an output and
+    /// its complementary output
+    ///
+    /// The dead time is rounded up to the next value the dead time generator
+    /// can produce. This also changes the sampling clock of the break input
+    /// filters for dead times longer than 1008 ticks.
+    ///
+    /// **NOTE** This must be called while the main output is disabled
+    ///
+    /// Returns `Error::DeadTimeTooLong` if the dead time is longer than 4032
+    /// ticks
+    pub fn set_dead_time<D>(&self, dead_time: D) -> Result<(), Error>
+    where
+        D: Into<::apb2::tim::Ticks>,
+    {
+        let (ckd, dtg) = self::dead_time(dead_time.into().0)?;
+
+        self.0.cr1.modify(|_, w| unsafe { w.ckd().bits(ckd) });
+        self.0.bdtr.modify(|_, w| unsafe { w.dtg().bits(dtg) });
+
+        Ok(())
+    }
+
+    /// Sets the levels a `channel` and its complementary output are driven
+    /// to when the main output is disabled, e.g. after a break
+    pub fn set_safe_state(
+        &self,
+        channel: Channel,
+        output: Level,
+        complementary: Level,
+    ) {
+        let (ois, oisn) = (output.bits(), complementary.bits());
+        let cr2 = &self.0.cr2;
+
+        match channel {
+            Channel::_1 => cr2.modify(|_, w| unsafe {
+                w.ois1().bits(ois).ois1n().bits(oisn)
+            }),
+            Channel::_2 => cr2.modify(|_, w| unsafe {
+                w.ois2().bits(ois).ois2n().bits(oisn)
+            }),
+            Channel::_3 => cr2.modify(|_, w| unsafe {
+                w.ois3().bits(ois).ois3n().bits(oisn)
+            }),
+            Channel::_4 => cr2.modify(|_, w| unsafe { w.ois4().bits(ois) }),
+        }
+
+        // ossi, ossr: keep driving the outputs with their idle levels instead
+        // of releasing them while disabled
+        self.0.bdtr.modify(|_, w| unsafe { w.ossi().bits(1).ossr().bits(1) });
+    }
+
+    /// Enables a break `input`
+    ///
+    /// When the input becomes active the main output gets disabled and all
+    /// the outputs switch to their safe state. `filter` is applied to the
+    /// input.
+    pub fn enable_break(
+        &self,
+        input: Break,
+        polarity: Polarity,
+        filter: Filter,
+    ) {
+        let filter = filter.bits();
+        let p = if polarity == Polarity::ActiveHigh { 1 } else { 0 };
+        let bdtr = &self.0.bdtr;
+
+        match input {
+            Break::Bkin => bdtr.modify(|_, w| unsafe {
+                w.bke().bits(1).bkp().bits(p).bkf().bits(filter)
+            }),
+            Break::Bkin2 => bdtr.modify(|_, w| unsafe {
+                w.bk2e().bits(1).bk2p().bits(p).bk2f().bits(filter)
+            }),
+        }
+    }
+
+    /// Disables a break `input`
+    pub fn disable_break(&self, input: Break) {
+        let bdtr = &self.0.bdtr;
+
+        match input {
+            Break::Bkin => bdtr.modify(|_, w| unsafe { w.bke().bits(0) }),
+            Break::Bkin2 => bdtr.modify(|_, w| unsafe { w.bk2e().bits(0) }),
+        }
+    }
+
+    /// Checks if a break `input` has been triggered and clears the flag
+    pub fn has_broken(&self, input: Break) -> bool {
+        let sr = &self.0.sr;
+
+        match input {
+            Break::Bkin => if sr.read().bif().bits() == 1 {
+                sr.modify(|_, w| unsafe { w.bif().bits(0) });
+                true
+            } else {
+                false
+            },
+            Break::Bkin2 => if sr.read().b2if().bits() == 1 {
+                sr.modify(|_, w| unsafe { w.b2if().bits(0) });
+                true
+            } else {
+                false
+            },
+        }
+    }
+
+    /// Sets the repetition counter
+    ///
+    /// The duty cycles and the period are only reloaded every
+    /// `repetitions + 1` PWM periods (or half periods in center aligned
+    /// mode).
+    pub fn set_repetition(&self, repetitions: u16) {
+        self.0.rcr.write(|w| unsafe { w.rep().bits(repetitions) });
+    }
+
+    /// Enables the main output (MOE)
+    ///
+    /// This fails and returns `false` if a break input is still active.
+    pub fn enable_outputs(&self) -> bool {
+        self.0.bdtr.modify(|_, w| unsafe { w.moe().bits(1) });
+
+        self.0.bdtr.read().moe().bits() == 1
+    }
+
+    /// Disables the main output (MOE), switching all the outputs to their
+    /// safe state
+    pub fn disable_outputs(&self) {
+        self.0.bdtr.modify(|_, w| unsafe { w.moe().bits(0) });
+    }
+}
+
+impl<'a, T> hal::Pwm for Pwm<'a, T>
+where
+    T: ATIM,
+{
+    type Channel = Channel;
+    type Time = ::apb2::tim::Ticks;
+    type Duty = u16;
+
+    fn disable(&self, channel: Channel) {
+        let ccer = &self.0.ccer;
+
+        match channel {
+            Channel::_1 => ccer.modify(|_, w| unsafe { w.cc1e().bits(0) }),
+            Channel::_2 => ccer.modify(|_, w| unsafe { w.cc2e().bits(0) }),
+            Channel::_3 => ccer.modify(|_, w| unsafe { w.cc3e().bits(0) }),
+            Channel::_4 => ccer.modify(|_, w| unsafe { w.cc4e().bits(0) }),
+        }
+    }
+
+    fn enable(&self, channel: Channel) {
+        let ccer = &self.0.ccer;
+
+        match channel {
+            Channel::_1 => ccer.modify(|_, w| unsafe { w.cc1e().bits(1) }),
+            Channel::_2 => ccer.modify(|_, w| unsafe { w.cc2e().bits(1) }),
+            Channel::_3 => ccer.modify(|_, w| unsafe { w.cc3e().bits(1) }),
+            Channel::_4 => ccer.modify(|_, w| unsafe { w.cc4e().bits(1) }),
+        }
+    }
+
+    fn get_duty(&self, channel: Channel) -> u16 {
+        match channel {
+            Channel::_1 => self.0.ccr1.read().ccr1().bits(),
+            Channel::_2 => self.0.ccr2.read().ccr2().bits(),
+            Channel::_3 => self.0.ccr3.read().ccr3().bits(),
+            Channel::_4 => self.0.ccr4.read().ccr4().bits(),
+        }
+    }
+
+    fn get_max_duty(&self) -> u16 {
+        self.0.arr.read().arr().bits()
+    }
+
+    fn get_period(&self) -> ::apb2::tim::Ticks {
+        ::apb2::tim::Ticks(timer::period(
+            self.0.psc.read().psc().bits(),
+            u32(self.0.arr.read().arr().bits()),
+        ))
+    }
+
+    fn set_duty(&self, channel: Channel, duty: u16) {
+        match channel {
+            Channel::_1 => self.0.ccr1.write(|w| unsafe { w.ccr1().bits(duty) }),
+            Channel::_2 => self.0.ccr2.write(|w| unsafe { w.ccr2().bits(duty) }),
+            Channel::_3 => self.0.ccr3.write(|w| unsafe { w.ccr3().bits(duty) }),
+            Channel::_4 => self.0.ccr4.write(|w| unsafe { w.ccr4().bits(duty) }),
+        }
+    }
+
+    fn set_period<P>(&self, period: P)
+    where
+        P: Into<::apb2::tim::Ticks>,
+    {
+        self._set_period(period.into())
+    }
+}
+
+macro_rules! pwm {
+    (
+        $TIM:ident,
+        $Channel:ident,
+        [$($CH:ident),+]
+    ) => {
+        impl<'a> Pwm<'a, $TIM> {
+            /// Initializes the PWM module with a period of `period` ticks
+            ///
+            /// All the channels are configured in PWM mode 1 with a duty
+            /// cycle of zero. The timer starts counting right away.
+            pub fn init<P>(
+                &self,
+                period: P,
+                gpio: &<$TIM as GTIM>::GPIO,
+                rcc: &Rcc,
+            ) where
+                P: Into<::apb2::tim::Ticks>,
+            {
+                $TIM::configure_pins(gpio, rcc);
+
+                self._init(period.into(), rcc)
+            }
+
+            fn _init(&self, period: ::apb2::tim::Ticks, rcc: &Rcc) {
+                let tim = self.0;
+
+                $TIM::enable(rcc);
+
+                // PWM mode 1, preload enabled
+                tim.ccmr1_output.write(|w| unsafe {
+                    w.oc1pe().bits(1).oc1m().bits(0b110)
+                });
+                $(
+                    pwm!(@mode tim, $CH);
+                )+
+
+                // active high outputs, all disabled
+                tim.ccer.write(|w| unsafe { w.bits(0) });
+
+                // main output enabled; the outputs are still gated by their
+                // CCxE bits
+                tim.bdtr.write(|w| unsafe { w.moe().bits(1) });
+
+                self._set_period(period);
+
+                // arpe: ARR is buffered
+                // opm: continuous mode
+                tim.cr1.write(|w| unsafe { w.arpe().bits(1).opm().bits(0) });
+
+                // load the prescaler and the duty cycles
+                tim.egr.write(|w| unsafe { w.ug().bits(1) });
+
+                tim.cr1.modify(|_, w| unsafe { w.cen().bits(1) });
+            }
+
+            fn _set_period(&self, period: ::apb2::tim::Ticks) {
+                let (psc, arr) = timer::psc_arr_saturating(period.0, 0xffff);
+
+                self.0.psc.write(|w| unsafe { w.psc().bits(psc) });
+                self.0.arr.write(|w| unsafe { w.arr().bits(arr as u16) });
+            }
+
+            /// Enables the complementary output of channel 1
+            pub fn enable_complementary(&self) {
+                self.0.ccer.modify(|_, w| unsafe { w.cc1ne().bits(1) });
+            }
+
+            /// Disables the complementary output of channel 1
+            pub fn disable_complementary(&self) {
+                self.0.ccer.modify(|_, w| unsafe { w.cc1ne().bits(0) });
+            }
+
+            /// Sets the dead time inserted between the switching of channel
+            /// 1 and its complementary output
+            ///
+            /// See `Pwm::set_dead_time` for the advanced-control timers.
+            ///
+            /// Returns `Error::DeadTimeTooLong` if the dead time is longer
+            /// than 4032 ticks
+            pub fn set_dead_time<D>(&self, dead_time: D) -> Result<(), Error>
+            where
+                D: Into<::apb2::tim::Ticks>,
+            {
+                let (ckd, dtg) = self::dead_time(dead_time.into().0)?;
+
+                self.0.cr1.modify(|_, w| unsafe { w.ckd().bits(ckd) });
+                self.0.bdtr.modify(|_, w| unsafe { w.dtg().bits(dtg) });
+
+                Ok(())
+            }
+        }
+
+        impl<'a> hal::Pwm for Pwm<'a, $TIM> {
+            type Channel = $Channel;
+            type Time = ::apb2::tim::Ticks;
+            type Duty = u16;
+
+            fn disable(&self, channel: $Channel) {
+                let ccer = &self.0.ccer;
+
+                match channel {
+                    $(
+                        pwm!(@ch $Channel, $CH) => {
+                            ccer.modify(|_, w| unsafe { pwm!(@cce w, $CH, 0) })
+                        }
+                    )+
+                }
+            }
+
+            fn enable(&self, channel: $Channel) {
+                let ccer = &self.0.ccer;
+
+                match channel {
+                    $(
+                        pwm!(@ch $Channel, $CH) => {
+                            ccer.modify(|_, w| unsafe { pwm!(@cce w, $CH, 1) })
+                        }
+                    )+
+                }
+            }
+
+            fn get_duty(&self, channel: $Channel) -> u16 {
+                match channel {
+                    $(
+                        pwm!(@ch $Channel, $CH) => pwm!(@ccr self.0, $CH).read().bits() as u16,
+                    )+
+                }
+            }
+
+            fn get_max_duty(&self) -> u16 {
+                self.0.arr.read().arr().bits()
+            }
+
+            fn get_period(&self) -> ::apb2::tim::Ticks {
+                ::apb2::tim::Ticks(timer::period(
+                    self.0.psc.read().psc().bits(),
+                    u32(self.0.arr.read().arr().bits()),
+                ))
+            }
+
+            fn set_duty(&self, channel: $Channel, duty: u16) {
+                match channel {
+                    $(
+                        pwm!(@ch $Channel, $CH) => pwm!(@ccr self.0, $CH)
+                            .write(|w| unsafe { w.bits(u32(duty)) }),
+                    )+
+                }
+            }
+
+            fn set_period<P>(&self, period: P)
+            where
+                P: Into<::apb2::tim::Ticks>,
+            {
+                self._set_period(period.into())
+            }
+        }
+    };
+    (@mode $tim:expr, _1) => {};
+    (@mode $tim:expr, _2) => {
+        $tim.ccmr1_output.modify(|_, w| unsafe {
+            w.oc2pe().bits(1).oc2m().bits(0b110)
+        });
+    };
+    (@ch $Channel:ident, _1) => { $Channel::_1 };
+    (@ch $Channel:ident, _2) => { $Channel::_2 };
+    (@cce $w:ident, _1, $bit:expr) => { $w.cc1e().bits($bit) };
+    (@cce $w:ident, _2, $bit:expr) => { $w.cc2e().bits($bit) };
+    (@ccr $tim:expr, _1) => { $tim.ccr1 };
+    (@ccr $tim:expr, _2) => { $tim.ccr2 };
+}
+
+pwm!(Tim15, Tim15Channel, [_1, _2]);
+pwm!(Tim16, Tim16Channel, [_1]);
+pwm!(Tim17, Tim17Channel, [_1]);
+
+/// Infrared output
+///
+/// The IR_OUT pin outputs the channel 1 of TIM17, the carrier, AND-ed with
+/// the channel 1 of TIM16, the envelope. The envelope is driven by software
+/// using `mark` and `space`; alternatively TIM16 can be used as a regular
+/// PWM to generate the envelope in hardware.
+pub struct IrOut<'a>(pub &'a Tim16, pub &'a Tim17);
+
+impl<'a> IrOut<'a> {
+    /// Initializes the infrared output with a carrier period of `carrier`
+    /// ticks and a 1/3 carrier duty cycle
+    ///
+    /// The output starts in the `space` state.
+    pub fn init<P>(&self, carrier: P, gpiob: &Gpiob, rcc: &Rcc)
+    where
+        P: Into<::apb2::tim::Ticks>,
+    {
+        let envelope = Pwm(self.0);
+        let carrier_pwm = Pwm(self.1);
+
+        rcc.ahbenr.modify(|_, w| w.iopben().enabled());
+
+        // IR_OUT = PB9
+        gpiob.afrh.modify(|_, w| unsafe { w.afrh9().bits(6) });
+        gpiob.moder.modify(|_, w| w.moder9().alternate());
+
+        carrier_pwm._init(carrier.into(), rcc);
+        let duty = hal::Pwm::get_max_duty(&carrier_pwm) / 3;
+        hal::Pwm::set_duty(&carrier_pwm, Tim17Channel::_1, duty);
+        hal::Pwm::enable(&carrier_pwm, Tim17Channel::_1);
+
+        // the envelope timer only needs to run its output stage
+        envelope._init(::apb2::tim::Ticks(0xffff), rcc);
+        self.space();
+        hal::Pwm::enable(&envelope, Tim16Channel::_1);
+    }
+
+    /// Starts sending the carrier (forces the envelope high)
+    pub fn mark(&self) {
+        // OC1M: force active level
+        self.0.ccmr1_output.modify(|_, w| unsafe { w.oc1m().bits(0b101) });
+    }
+
+    /// Stops sending the carrier (forces the envelope low)
+    pub fn space(&self) {
+        // OC1M: force inactive level
+        self.0.ccmr1_output.modify(|_, w| unsafe { w.oc1m().bits(0b100) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, dead_time};
+
+    #[test]
+    fn dead_time_ranges() {
+        // DTG[7] = 0: 1 tDTS steps
+        assert_eq!(dead_time(0).ok(), Some((0, 0x00)));
+        assert_eq!(dead_time(127).ok(), Some((0, 0x7f)));
+        // DTG[7:6] = 10: 2 tDTS steps from 128
+        assert_eq!(dead_time(128).ok(), Some((0, 0x80)));
+        assert_eq!(dead_time(129).ok(), Some((0, 0x81)));
+        assert_eq!(dead_time(254).ok(), Some((0, 0xbf)));
+        // DTG[7:5] = 110: 8 tDTS steps from 256
+        assert_eq!(dead_time(255).ok(), Some((0, 0xc0)));
+        assert_eq!(dead_time(504).ok(), Some((0, 0xdf)));
+        // DTG[7:5] = 111: 16 tDTS steps from 512
+        assert_eq!(dead_time(505).ok(), Some((0, 0xe0)));
+        assert_eq!(dead_time(1008).ok(), Some((0, 0xff)));
+    }
+
+    #[test]
+    fn dead_time_clock_division() {
+        // tDTS = 2 tCK_INT
+        assert_eq!(dead_time(1009).ok(), Some((0b01, 0xe0)));
+        assert_eq!(dead_time(2016).ok(), Some((0b01, 0xff)));
+        // tDTS = 4 tCK_INT
+        assert_eq!(dead_time(2017).ok(), Some((0b10, 0xe0)));
+        assert_eq!(dead_time(4032).ok(), Some((0b10, 0xff)));
+    }
+
+    #[test]
+    fn dead_time_too_long() {
+        match dead_time(4033) {
+            Err(Error::DeadTimeTooLong) => {}
+            _ => panic!("dead time of 4033 ticks accepted"),
+        }
+
+        assert!(dead_time(u32::max_value()).is_err());
+    }
+}
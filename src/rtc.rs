@@ -0,0 +1,822 @@
+//! Real-time clock (RTC)
+//!
+//! The RTC lives in the backup domain: if it's clocked by the LSE and the
+//! board has a backup battery (VBAT), it keeps time across resets and power
+//! cycles. `init` doesn't touch a running RTC that was configured with the
+//! same clock source so the calendar survives resets.
+//!
+//! The calendar uses the 24-hour format and covers the years 2000 - 2099.
+//!
+//! Alarms and the wakeup timer are routed to the NVIC through the EXTI: line
+//! 17 (`RTC_ALARM` interrupt) and line 20 (`RTC_WKUP` interrupt)
+//! respectively. `listen` configures both the RTC and the EXTI.
+
+use core::ptr;
+
+use stm32f30x::{Exti, Pwr, Rcc, Rtc};
+
+/// Number of backup registers
+pub const BACKUP_REGISTERS: usize = 16;
+
+/// Number of iterations to wait for the LSE to start up
+const LSE_TIMEOUT: u32 = 0x20_0000;
+
+/// Number of iterations to wait for the LSI to start up
+///
+/// NOTE the LSI starts up in at most 85 us; this is a generous bound
+const LSI_TIMEOUT: u32 = 0x1_0000;
+
+/// Number of iterations to wait for the RTC to acknowledge a register update
+///
+/// NOTE the INITF, ALRAWF, ALRBWF and WUTWF flags are set within a few RTCCLK
+/// cycles; they only time out if the RTC clock isn't running
+const SYNC_TIMEOUT: u32 = 0x1_0000;
+
+/// RTC error
+#[derive(Debug)]
+pub enum Error {
+    /// The date or time is out of range
+    InvalidDateTime,
+    /// The calibration value is out of range
+    InvalidCalibration,
+    /// The backup register index is out of range
+    InvalidBackupRegister,
+    /// The LSE oscillator didn't start
+    LseNotReady,
+    /// The LSI oscillator didn't start
+    LsiNotReady,
+    /// The RTC didn't acknowledge a register update; its clock isn't running
+    Timeout,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Clock source of the RTC
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ClockSource {
+    /// 32.768 KHz crystal connected to OSC32_IN / OSC32_OUT
+    Lse,
+    /// External 32.768 KHz clock fed into OSC32_IN
+    LseBypass,
+    /// Internal RC oscillator; doesn't keep running without VDD
+    Lsi,
+}
+
+/// Time of the day (24-hour format)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Time {
+    /// 0 - 23
+    pub hours: u8,
+    /// 0 - 59
+    pub minutes: u8,
+    /// 0 - 59
+    pub seconds: u8,
+}
+
+impl Time {
+    fn is_valid(&self) -> bool {
+        self.hours < 24 && self.minutes < 60 && self.seconds < 60
+    }
+}
+
+/// Calendar date
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Date {
+    /// 2000 - 2099
+    pub year: u16,
+    /// 1 - 12
+    pub month: u8,
+    /// 1 - 31
+    pub day: u8,
+}
+
+impl Date {
+    /// Returns the day of the week: 1 = Monday, ..., 7 = Sunday
+    ///
+    /// Returns `None` if the date is not a valid calendar date in the years
+    /// 2000 - 2099
+    pub fn weekday(&self) -> Option<u8> {
+        if !self.is_valid() {
+            return None;
+        }
+
+        // Sakamoto's algorithm
+        const T: [u16; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+
+        let y = if self.month < 3 {
+            self.year - 1
+        } else {
+            self.year
+        };
+        let d = (y + y / 4 - y / 100 + y / 400 + T[self.month as usize - 1] +
+                     u16::from(self.day)) % 7;
+
+        // 0 = Sunday
+        Some(if d == 0 { 7 } else { d as u8 })
+    }
+
+    fn is_valid(&self) -> bool {
+        if self.year < 2000 || self.year > 2099 || self.month == 0 ||
+            self.month > 12 || self.day == 0
+        {
+            return false;
+        }
+
+        // NOTE every 4th year in 2000 - 2099 is a leap year
+        let days = match self.month {
+            2 => if self.year % 4 == 0 { 29 } else { 28 },
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        };
+
+        self.day <= days
+    }
+}
+
+/// Alarm
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Alarm {
+    /// Alarm A
+    A,
+    /// Alarm B
+    B,
+}
+
+/// Day match of an alarm
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AlarmDay {
+    /// Day of the month, 1 - 31
+    Date(u8),
+    /// Day of the week, 1 = Monday, ..., 7 = Sunday
+    Weekday(u8),
+}
+
+/// Alarm match
+///
+/// `None` fields are masked out ("don't care"). For example, an alarm with
+/// only `seconds: Some(0)` fires once per minute.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AlarmSpec {
+    /// Day match
+    pub day: Option<AlarmDay>,
+    /// Hours match
+    pub hours: Option<u8>,
+    /// Minutes match
+    pub minutes: Option<u8>,
+    /// Seconds match
+    pub seconds: Option<u8>,
+}
+
+/// Clock of the wakeup timer
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WakeupClock {
+    /// RTCCLK / 16
+    RtcDiv16,
+    /// RTCCLK / 8
+    RtcDiv8,
+    /// RTCCLK / 4
+    RtcDiv4,
+    /// RTCCLK / 2
+    RtcDiv2,
+    /// The 1 Hz calendar clock
+    Spre,
+    /// The 1 Hz calendar clock, with 2^16 added to the counter value
+    SpreExtended,
+}
+
+/// RTC event
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// Alarm A matched
+    AlarmA,
+    /// Alarm B matched
+    AlarmB,
+    /// The wakeup timer expired
+    Wakeup,
+}
+
+/// Real-time clock
+pub struct RealTimeClock<'a>(pub &'a Rtc);
+
+impl<'a> Clone for RealTimeClock<'a> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a> Copy for RealTimeClock<'a> {}
+
+impl<'a> RealTimeClock<'a> {
+    /// Initializes the RTC
+    ///
+    /// If the RTC is already running from `source` (e.g. after a system
+    /// reset) the calendar is preserved; otherwise the backup domain is reset
+    /// and the calendar starts at 2000-01-01 00:00:00.
+    ///
+    /// NOTE this enables write access to the backup domain and leaves it
+    /// enabled
+    pub fn init(
+        &self,
+        source: ClockSource,
+        pwr: &Pwr,
+        rcc: &Rcc,
+    ) -> Result<(), Error> {
+        let rtc = self.0;
+
+        // enable write access to the backup domain
+        rcc.apb1enr.modify(|_, w| w.pwren().enabled());
+        pwr.cr.modify(|_, w| unsafe { w.dbp().bits(1) });
+
+        let rtcsel = match source {
+            ClockSource::Lse | ClockSource::LseBypass => 0b01,
+            ClockSource::Lsi => 0b10,
+        };
+
+        let bdcr = rcc.bdcr.read();
+        let running = bdcr.rtcen().bits() == 1 &&
+            bdcr.rtcsel().bits() == rtcsel;
+
+        if !running {
+            // reset the backup domain; RTCSEL can only be changed this way
+            rcc.bdcr.modify(|_, w| unsafe { w.bdrst().bits(1) });
+            rcc.bdcr.modify(|_, w| unsafe { w.bdrst().bits(0) });
+        }
+
+        // NOTE the LSI is turned off by a system reset and must be restarted
+        // even if the RTC was running
+        match source {
+            ClockSource::Lse | ClockSource::LseBypass => {
+                let bypass = (source == ClockSource::LseBypass) as u8;
+                rcc.bdcr.modify(|_, w| unsafe {
+                    w.lsebyp().bits(bypass).lseon().bits(1)
+                });
+
+                let mut timeout = LSE_TIMEOUT;
+                while rcc.bdcr.read().lserdy().bits() == 0 {
+                    timeout -= 1;
+                    if timeout == 0 {
+                        return Err(Error::LseNotReady);
+                    }
+                }
+            }
+            ClockSource::Lsi => {
+                rcc.csr.modify(|_, w| unsafe { w.lsion().bits(1) });
+
+                let mut timeout = LSI_TIMEOUT;
+                while rcc.csr.read().lsirdy().bits() == 0 {
+                    timeout -= 1;
+                    if timeout == 0 {
+                        return Err(Error::LsiNotReady);
+                    }
+                }
+            }
+        }
+
+        if running {
+            self.wait_for_sync();
+            return Ok(());
+        }
+
+        rcc.bdcr.modify(|_, w| unsafe {
+            w.rtcsel().bits(rtcsel).rtcen().bits(1)
+        });
+
+        // ck_spre = RTCCLK / (PREDIV_A + 1) / (PREDIV_S + 1) = 1 Hz
+        //
+        // The asynchronous prescaler divides the nominal frequency exactly:
+        // 32_768 = 128 * 256 and 40_000 = 125 * 320. If the frequency
+        // constants are changed to values that don't factor like this the
+        // synchronous prescaler is truncated and the calendar runs fast by
+        // up to `1 / prediv_s` (0.4% with the LSE). NOTE the LSI itself is
+        // only accurate to within +-25% (see `lsi`), which dominates the
+        // error of an LSI clocked calendar.
+        let (rtcclk, prediv_a) = match source {
+            ClockSource::Lse | ClockSource::LseBypass => {
+                (::lse::FREQUENCY, 128)
+            }
+            ClockSource::Lsi => (::lsi::FREQUENCY, 125),
+        };
+        let prediv_s = rtcclk / prediv_a;
+
+        self.unlock();
+        if let Err(e) = self.enter_init() {
+            self.lock();
+            return Err(e);
+        }
+
+        rtc.prer.write(|w| unsafe {
+            w.prediv_s()
+                .bits((prediv_s - 1) as u16)
+                .prediv_a()
+                .bits((prediv_a - 1) as u8)
+        });
+
+        // 24-hour format
+        rtc.cr.modify(|_, w| unsafe { w.fmt().bits(0) });
+
+        self.exit_init();
+        self.lock();
+
+        self.wait_for_sync();
+
+        Ok(())
+    }
+
+    /// Returns the current date and time
+    pub fn datetime(&self) -> (Date, Time) {
+        let rtc = self.0;
+
+        // NOTE reading TR locks the DR shadow register until DR is read so
+        // the date and the time are consistent
+        let tr = rtc.tr.read();
+        let dr = rtc.dr.read();
+
+        let time = Time {
+            hours: tr.ht().bits() * 10 + tr.hu().bits(),
+            minutes: tr.mnt().bits() * 10 + tr.mnu().bits(),
+            seconds: tr.st().bits() * 10 + tr.su().bits(),
+        };
+        let date = Date {
+            year: 2000 + u16::from(dr.yt().bits() * 10 + dr.yu().bits()),
+            month: dr.mt().bits() * 10 + dr.mu().bits(),
+            day: dr.dt().bits() * 10 + dr.du().bits(),
+        };
+
+        (date, time)
+    }
+
+    /// Returns the current date
+    pub fn date(&self) -> Date {
+        self.datetime().0
+    }
+
+    /// Returns the current time
+    pub fn time(&self) -> Time {
+        self.datetime().1
+    }
+
+    /// Sets the date and the time
+    pub fn set_datetime(&self, date: &Date, time: &Time) -> Result<(), Error> {
+        let weekday = match date.weekday() {
+            Some(weekday) if time.is_valid() => weekday,
+            _ => return Err(Error::InvalidDateTime),
+        };
+
+        let rtc = self.0;
+
+        let year = (date.year - 2000) as u8;
+
+        self.unlock();
+        if let Err(e) = self.enter_init() {
+            self.lock();
+            return Err(e);
+        }
+
+        rtc.tr.write(|w| unsafe {
+            w.ht()
+                .bits(time.hours / 10)
+                .hu()
+                .bits(time.hours % 10)
+                .mnt()
+                .bits(time.minutes / 10)
+                .mnu()
+                .bits(time.minutes % 10)
+                .st()
+                .bits(time.seconds / 10)
+                .su()
+                .bits(time.seconds % 10)
+        });
+        rtc.dr.write(|w| unsafe {
+            w.yt()
+                .bits(year / 10)
+                .yu()
+                .bits(year % 10)
+                .wdu()
+                .bits(weekday)
+                .mt()
+                .bits(date.month / 10)
+                .mu()
+                .bits(date.month % 10)
+                .dt()
+                .bits(date.day / 10)
+                .du()
+                .bits(date.day % 10)
+        });
+
+        self.exit_init();
+        self.lock();
+
+        self.wait_for_sync();
+
+        Ok(())
+    }
+
+    /// Sets the time, keeping the date
+    pub fn set_time(&self, time: &Time) -> Result<(), Error> {
+        let date = self.date();
+        self.set_datetime(&date, time)
+    }
+
+    /// Sets the date, keeping the time
+    pub fn set_date(&self, date: &Date) -> Result<(), Error> {
+        let time = self.time();
+        self.set_datetime(date, &time)
+    }
+
+    /// Configures and enables `alarm`
+    pub fn set_alarm(&self, alarm: Alarm, spec: &AlarmSpec) -> Result<(), Error> {
+        let rtc = self.0;
+
+        let valid = spec.hours.map(|h| h < 24).unwrap_or(true) &&
+            spec.minutes.map(|m| m < 60).unwrap_or(true) &&
+            spec.seconds.map(|s| s < 60).unwrap_or(true) &&
+            match spec.day {
+                Some(AlarmDay::Date(d)) => d >= 1 && d <= 31,
+                Some(AlarmDay::Weekday(d)) => d >= 1 && d <= 7,
+                None => true,
+            };
+
+        if !valid {
+            return Err(Error::InvalidDateTime);
+        }
+
+        // (WDSEL, tens, units); the weekday has no tens digit
+        let (wdsel, dt, du) = match spec.day {
+            Some(AlarmDay::Date(d)) => (0, d / 10, d % 10),
+            Some(AlarmDay::Weekday(d)) => (1, 0, d),
+            None => (0, 0, 0),
+        };
+        let hours = spec.hours.unwrap_or(0);
+        let minutes = spec.minutes.unwrap_or(0);
+        let seconds = spec.seconds.unwrap_or(0);
+
+        // MSKx: the field doesn't take part in the match
+        let msk4 = spec.day.is_none() as u8;
+        let msk3 = spec.hours.is_none() as u8;
+        let msk2 = spec.minutes.is_none() as u8;
+        let msk1 = spec.seconds.is_none() as u8;
+
+        macro_rules! alrmr {
+            ($w:ident) => {
+                $w.msk4()
+                    .bits(msk4)
+                    .wdsel()
+                    .bits(wdsel)
+                    .dt()
+                    .bits(dt)
+                    .du()
+                    .bits(du)
+                    .msk3()
+                    .bits(msk3)
+                    .ht()
+                    .bits(hours / 10)
+                    .hu()
+                    .bits(hours % 10)
+                    .msk2()
+                    .bits(msk2)
+                    .mnt()
+                    .bits(minutes / 10)
+                    .mnu()
+                    .bits(minutes % 10)
+                    .msk1()
+                    .bits(msk1)
+                    .st()
+                    .bits(seconds / 10)
+                    .su()
+                    .bits(seconds % 10)
+            }
+        }
+
+        self.unlock();
+
+        match alarm {
+            Alarm::A => {
+                rtc.cr.modify(|_, w| unsafe { w.alrae().bits(0) });
+
+                let mut timeout = SYNC_TIMEOUT;
+                while rtc.isr.read().alrawf().bits() == 0 {
+                    timeout -= 1;
+                    if timeout == 0 {
+                        self.lock();
+                        return Err(Error::Timeout);
+                    }
+                }
+
+                rtc.alrmar.write(|w| unsafe { alrmr!(w) });
+
+                rtc.cr.modify(|_, w| unsafe { w.alrae().bits(1) });
+            }
+            Alarm::B => {
+                rtc.cr.modify(|_, w| unsafe { w.alrbe().bits(0) });
+
+                let mut timeout = SYNC_TIMEOUT;
+                while rtc.isr.read().alrbwf().bits() == 0 {
+                    timeout -= 1;
+                    if timeout == 0 {
+                        self.lock();
+                        return Err(Error::Timeout);
+                    }
+                }
+
+                rtc.alrmbr.write(|w| unsafe { alrmr!(w) });
+
+                rtc.cr.modify(|_, w| unsafe { w.alrbe().bits(1) });
+            }
+        }
+
+        self.lock();
+
+        Ok(())
+    }
+
+    /// Disables `alarm`
+    pub fn disable_alarm(&self, alarm: Alarm) {
+        self.unlock();
+        self.0.cr.modify(|_, w| unsafe {
+            match alarm {
+                Alarm::A => w.alrae().bits(0),
+                Alarm::B => w.alrbe().bits(0),
+            }
+        });
+        self.lock();
+    }
+
+    /// Starts the periodic wakeup timer
+    ///
+    /// The timer expires every `count + 1` cycles of `clock`
+    pub fn set_wakeup(
+        &self,
+        clock: WakeupClock,
+        count: u16,
+    ) -> Result<(), Error> {
+        let rtc = self.0;
+
+        let wucksel = match clock {
+            WakeupClock::RtcDiv16 => 0b000,
+            WakeupClock::RtcDiv8 => 0b001,
+            WakeupClock::RtcDiv4 => 0b010,
+            WakeupClock::RtcDiv2 => 0b011,
+            WakeupClock::Spre => 0b100,
+            WakeupClock::SpreExtended => 0b110,
+        };
+
+        self.unlock();
+
+        rtc.cr.modify(|_, w| unsafe { w.wute().bits(0) });
+
+        let mut timeout = SYNC_TIMEOUT;
+        while rtc.isr.read().wutwf().bits() == 0 {
+            timeout -= 1;
+            if timeout == 0 {
+                self.lock();
+                return Err(Error::Timeout);
+            }
+        }
+
+        rtc.wutr.write(|w| unsafe { w.wut().bits(count) });
+        rtc.cr.modify(|_, w| unsafe {
+            w.wucksel().bits(wucksel).wute().bits(1)
+        });
+
+        self.lock();
+
+        Ok(())
+    }
+
+    /// Stops the wakeup timer
+    pub fn disable_wakeup(&self) {
+        self.unlock();
+        self.0.cr.modify(|_, w| unsafe { w.wute().bits(0) });
+        self.lock();
+    }
+
+    /// Sets the smooth calibration of the RTC clock
+    ///
+    /// `pulses` RTCCLK pulses are added (positive values) or masked (negative
+    /// values) every 2^20 RTCCLK cycles (32 s with a 32.768 KHz LSE). One
+    /// pulse is about 0.954 ppm. The valid range is -511 to 512.
+    pub fn set_calibration(&self, pulses: i16) -> Result<(), Error> {
+        if pulses < -511 || pulses > 512 {
+            return Err(Error::InvalidCalibration);
+        }
+
+        let rtc = self.0;
+
+        // CALP adds 512 pulses, CALM masks 0 - 511 pulses
+        let (calp, calm) = if pulses > 0 {
+            (1, (512 - pulses) as u16)
+        } else {
+            (0, (-pulses) as u16)
+        };
+
+        self.unlock();
+
+        while rtc.isr.read().recalpf().bits() == 1 {}
+        rtc.calr.write(|w| unsafe { w.calp().bits(calp).calm().bits(calm) });
+
+        self.lock();
+
+        Ok(())
+    }
+
+    /// Enables the interrupt for `event`, in the RTC and in the EXTI
+    pub fn listen(&self, event: Event, exti: &Exti) {
+        // rising edge trigger
+        match event {
+            Event::AlarmA | Event::AlarmB => {
+                exti.rtsr1.modify(|_, w| unsafe { w.tr17().bits(1) });
+                exti.imr1.modify(|_, w| unsafe { w.mr17().bits(1) });
+            }
+            Event::Wakeup => {
+                exti.rtsr1.modify(|_, w| unsafe { w.tr20().bits(1) });
+                exti.imr1.modify(|_, w| unsafe { w.mr20().bits(1) });
+            }
+        }
+
+        self.unlock();
+        self.0.cr.modify(|_, w| unsafe {
+            match event {
+                Event::AlarmA => w.alraie().bits(1),
+                Event::AlarmB => w.alrbie().bits(1),
+                Event::Wakeup => w.wutie().bits(1),
+            }
+        });
+        self.lock();
+    }
+
+    /// Disables the interrupt for `event` in the RTC
+    ///
+    /// NOTE the EXTI line is left untouched as it's shared by both alarms
+    pub fn unlisten(&self, event: Event) {
+        self.unlock();
+        self.0.cr.modify(|_, w| unsafe {
+            match event {
+                Event::AlarmA => w.alraie().bits(0),
+                Event::AlarmB => w.alrbie().bits(0),
+                Event::Wakeup => w.wutie().bits(0),
+            }
+        });
+        self.lock();
+    }
+
+    /// Checks whether `event` has occurred
+    pub fn is_pending(&self, event: Event) -> bool {
+        let isr = self.0.isr.read();
+
+        match event {
+            Event::AlarmA => isr.alraf().bits() == 1,
+            Event::AlarmB => isr.alrbf().bits() == 1,
+            Event::Wakeup => isr.wutf().bits() == 1,
+        }
+    }
+
+    /// Clears the `event` flag in the RTC and in the EXTI
+    pub fn clear(&self, event: Event, exti: &Exti) {
+        // NOTE the flags are cleared by writing 0; writing 1 has no effect
+        self.0.isr.modify(|_, w| unsafe {
+            match event {
+                Event::AlarmA => w.alraf().bits(0),
+                Event::AlarmB => w.alrbf().bits(0),
+                Event::Wakeup => w.wutf().bits(0),
+            }
+        });
+
+        match event {
+            Event::AlarmA | Event::AlarmB => {
+                exti.pr1.write(|w| unsafe { w.pr17().bits(1) })
+            }
+            Event::Wakeup => exti.pr1.write(|w| unsafe { w.pr20().bits(1) }),
+        }
+    }
+
+    /// Waits until the shadow registers are synchronized with the calendar
+    ///
+    /// This is required after initialization and after waking up from the
+    /// stop or standby modes
+    pub fn wait_for_sync(&self) {
+        let rtc = self.0;
+
+        self.unlock();
+        rtc.isr.modify(|_, w| unsafe { w.rsf().bits(0) });
+        self.lock();
+
+        while rtc.isr.read().rsf().bits() == 0 {}
+    }
+
+    /// Reads the backup register `index`
+    ///
+    /// Returns `None` if `index` is out of range
+    pub fn read_backup(&self, index: usize) -> Option<u32> {
+        if index >= BACKUP_REGISTERS {
+            return None;
+        }
+
+        // NOTE BKP0R - BKP15R are contiguous
+        let bkpr = unsafe { self.backup_registers().offset(index as isize) };
+
+        Some(unsafe { ptr::read_volatile(bkpr) })
+    }
+
+    /// Writes `value` into the backup register `index`
+    pub fn write_backup(&self, index: usize, value: u32) -> Result<(), Error> {
+        if index >= BACKUP_REGISTERS {
+            return Err(Error::InvalidBackupRegister);
+        }
+
+        unsafe {
+            ptr::write_volatile(
+                self.backup_registers().offset(index as isize),
+                value,
+            )
+        }
+
+        Ok(())
+    }
+
+    fn backup_registers(&self) -> *mut u32 {
+        &self.0.bkp0r as *const _ as *mut u32
+    }
+
+    /// Disables the write protection of the RTC registers
+    fn unlock(&self) {
+        self.0.wpr.write(|w| unsafe { w.key().bits(0xca) });
+        self.0.wpr.write(|w| unsafe { w.key().bits(0x53) });
+    }
+
+    /// Enables the write protection of the RTC registers
+    fn lock(&self) {
+        self.0.wpr.write(|w| unsafe { w.key().bits(0xff) });
+    }
+
+    /// Enters the initialization mode; on timeout the mode request is
+    /// withdrawn
+    fn enter_init(&self) -> Result<(), Error> {
+        let rtc = self.0;
+
+        rtc.isr.modify(|_, w| unsafe { w.init().bits(1) });
+
+        let mut timeout = SYNC_TIMEOUT;
+        while rtc.isr.read().initf().bits() == 0 {
+            timeout -= 1;
+            if timeout == 0 {
+                self.exit_init();
+                return Err(Error::Timeout);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn exit_init(&self) {
+        self.0.isr.modify(|_, w| unsafe { w.init().bits(0) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Date;
+
+    fn date(year: u16, month: u8, day: u8) -> Date {
+        Date { year, month, day }
+    }
+
+    #[test]
+    fn weekday() {
+        // Saturday
+        assert_eq!(date(2000, 1, 1).weekday(), Some(6));
+        // Wednesday; first day after a leap day
+        assert_eq!(date(2000, 3, 1).weekday(), Some(3));
+        // Thursday; leap day
+        assert_eq!(date(2024, 2, 29).weekday(), Some(4));
+        // Sunday
+        assert_eq!(date(2017, 10, 22).weekday(), Some(7));
+        // Monday
+        assert_eq!(date(2017, 10, 23).weekday(), Some(1));
+        // Thursday; end of the range
+        assert_eq!(date(2099, 12, 31).weekday(), Some(4));
+    }
+
+    #[test]
+    fn weekday_invalid() {
+        assert_eq!(date(2017, 0, 1).weekday(), None);
+        assert_eq!(date(2017, 13, 1).weekday(), None);
+        assert_eq!(date(0, 1, 1).weekday(), None);
+    }
+
+    #[test]
+    fn is_valid() {
+        assert!(date(2000, 1, 1).is_valid());
+        assert!(date(2099, 12, 31).is_valid());
+        assert!(date(2000, 2, 29).is_valid());
+        assert!(date(2017, 4, 30).is_valid());
+
+        assert!(!date(1999, 12, 31).is_valid());
+        assert!(!date(2100, 1, 1).is_valid());
+        assert!(!date(2017, 0, 1).is_valid());
+        assert!(!date(2017, 13, 1).is_valid());
+        assert!(!date(2017, 1, 0).is_valid());
+        assert!(!date(2017, 1, 32).is_valid());
+        assert!(!date(2017, 2, 29).is_valid());
+        assert!(!date(2017, 4, 31).is_valid());
+    }
+}
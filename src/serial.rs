@@ -7,12 +7,16 @@
 //! - TX = PA9
 //! - RX = PA10
 //! - Interrupt = USART1
+//! - DMA RX = DMA1 channel 5
+//! - DMA TX = DMA1 channel 4
 //!
 //! # USART2
 //!
 //! - TX = PA14
 //! - RX = PA15
 //! - Interrupt = USART2
+//! - DMA RX = DMA1 channel 6
+//! - DMA TX = DMA1 channel 7
 //!
 //! # USART3
 //!
@@ -20,19 +24,18 @@
 //! - RX = PC11
 //! - Interrupt = USART3
 
-use core::any::{Any, TypeId};
 use core::marker::Unsize;
 use core::ops::Deref;
 use core::ptr;
 
-use cast::u16;
 use hal;
 use nb;
 use static_ref::Ref;
-use stm32f30x::{Dma1, Gpioa, Rcc, Usart1, Usart2,
-                  gpioa, usart1};
+use stm32f30x::{Dma1, Gpioa, Rcc, Usart1, Usart2, gpioa, usart1};
 
-use dma::{self, Buffer, Dma1Channel4, Dma1Channel5};
+use dma::{self, Buffer, DMA, Direction, Dma1Channel4, Dma1Channel5,
+          Dma1Channel6, Dma1Channel7, DmaChannel, OwnedTransfer, Size,
+          TransferError};
 
 /// Specialized `Result` type
 pub type Result<T> = ::core::result::Result<T, nb::Error<Error>>;
@@ -43,16 +46,77 @@ pub unsafe trait Usart: Deref<Target = usart1::RegisterBlock> {
     type GPIO: Deref<Target = gpioa::RegisterBlock>;
     /// IMPLEMENTATION DETAIL
     type Ticks: Into<u32>;
+    /// IMPLEMENTATION DETAIL
+    type DMA: DMA;
+    /// IMPLEMENTATION DETAIL
+    type RxChannel: DmaChannel<Dma = Self::DMA>;
+    /// IMPLEMENTATION DETAIL
+    type TxChannel: DmaChannel<Dma = Self::DMA>;
+
+    /// IMPLEMENTATION DETAIL
+    fn enable(rcc: &Rcc);
+    /// IMPLEMENTATION DETAIL
+    fn reset(rcc: &Rcc);
+    /// IMPLEMENTATION DETAIL
+    fn configure_pins(gpio: &Self::GPIO, rcc: &Rcc);
 }
 
 unsafe impl Usart for Usart1 {
     type GPIO = Gpioa;
     type Ticks = ::apb2::Ticks;
+    type DMA = Dma1;
+    type RxChannel = Dma1Channel5;
+    type TxChannel = Dma1Channel4;
+
+    fn enable(rcc: &Rcc) {
+        rcc.apb2enr.modify(|_, w| w.usart1en().enabled());
+    }
+
+    fn reset(rcc: &Rcc) {
+        rcc.apb2rstr.modify(|_, w| unsafe { w.usart1rst().bits(1) });
+        rcc.apb2rstr.modify(|_, w| unsafe { w.usart1rst().bits(0) });
+    }
+
+    fn configure_pins(gpio: &Gpioa, rcc: &Rcc) {
+        rcc.ahbenr.modify(|_, w| w.iopaen().enabled());
+
+        // PA9 = TX, PA10 = RX
+        gpio
+            .afrh
+            .modify(|_, w| unsafe { w.afrh9().bits(7).afrh10().bits(7) });
+        gpio
+            .moder
+            .modify(|_, w| w.moder9().alternate().moder10().alternate());
+    }
 }
 
 unsafe impl Usart for Usart2 {
     type GPIO = Gpioa;
     type Ticks = ::apb1::Ticks;
+    type DMA = Dma1;
+    type RxChannel = Dma1Channel6;
+    type TxChannel = Dma1Channel7;
+
+    fn enable(rcc: &Rcc) {
+        rcc.apb1enr.modify(|_, w| w.usart2en().enabled());
+    }
+
+    fn reset(rcc: &Rcc) {
+        rcc.apb1rstr.modify(|_, w| unsafe { w.usart2rst().bits(1) });
+        rcc.apb1rstr.modify(|_, w| unsafe { w.usart2rst().bits(0) });
+    }
+
+    fn configure_pins(gpio: &Gpioa, rcc: &Rcc) {
+        rcc.ahbenr.modify(|_, w| w.iopaen().enabled());
+
+        // PA14 = TX, PA15 = RX
+        gpio
+            .afrh
+            .modify(|_, w| unsafe { w.afrh14().bits(7).afrh15().bits(7) });
+        gpio
+            .moder
+            .modify(|_, w| w.moder14().alternate().moder15().alternate());
+    }
 }
 
 //unsafe impl Usart for Usart3 {
@@ -90,11 +154,11 @@ pub enum Event {
 /// - RXNE
 pub struct Serial<'a, U>(pub &'a U)
 where
-    U: Any + Usart;
+    U: Usart;
 
 impl<'a, U> Clone for Serial<'a, U>
 where
-    U: Any + Usart,
+    U: Usart,
 {
     fn clone(&self) -> Self {
         *self
@@ -103,13 +167,13 @@ where
 
 impl<'a, U> Copy for Serial<'a, U>
 where
-    U: Any + Usart,
+    U: Usart,
 {
 }
 
 impl<'a, U> Serial<'a, U>
 where
-    U: Any + Usart,
+    U: Usart,
 {
     /// Initializes the serial interface with a baud rate of `baut_rate` bits
     /// per second
@@ -119,147 +183,48 @@ where
     pub fn init<B>(
         &self,
         baud_rate: B,
-        dma1: Option<&Dma1>,
+        dma: Option<&U::DMA>,
         gpio: &U::GPIO,
         rcc: &Rcc,
     ) where
         B: Into<U::Ticks>,
     {
-        self._init(baud_rate.into(), dma1, gpio, rcc)
+        self._init(baud_rate.into(), dma, gpio, rcc)
     }
 
     fn _init(
         &self,
         baud_rate: U::Ticks,
-        dma1: Option<&Dma1>,
+        dma: Option<&U::DMA>,
         gpio: &U::GPIO,
         rcc: &Rcc,
     ) {
         let usart = self.0;
 
         // power up peripherals
-        if dma1.is_some() {
-            rcc.ahbenr.modify(|_, w| w.dmaen().enabled());
+        if dma.is_some() {
+            U::DMA::enable(rcc);
         }
-        if usart.get_type_id() == TypeId::of::<Usart1>() {
-            rcc.apb2enr.modify(|_, w| w.usart1en().enabled());
-            rcc.ahbenr.modify(|_, w| w.iopaen().enabled());
-        } else if usart.get_type_id() == TypeId::of::<Usart2>() {
-            rcc.apb1enr.modify(|_, w| w.usart2en().enabled());
-            rcc.ahbenr.modify(|_, w| w.iopaen().enabled());
-  /*      } else if usart.get_type_id() == TypeId::of::<Usart3>() {
-            rcc.apb1enr.modify(|_, w| w.usart3en().enabled());
-            rcc.apb2enr.modify(
-                |_, w| w.afioen().enabled().iopben().enabled(),
-            );*/
-        }
-
-        if usart.get_type_id() == TypeId::of::<Usart1>() {
-            // PA9 = TX, PA10 = RX
-            gpio
-                .afrh
-                .modify(|_, w| unsafe { w.afrh9().bits(7).afrh10().bits(7) });
-            gpio
-                .moder
-                .modify(|_, w| w.moder9().alternate().moder10().alternate());
-        } else if usart.get_type_id() == TypeId::of::<Usart2>() {
-            // PA2 = TX, PA3 = RX
-            gpio
-                .afrh
-                .modify(|_, w| unsafe { w.afrh14().bits(7).afrh15().bits(7) });
-            gpio
-                .moder
-                .modify(|_, w| w.moder14().alternate().moder15().alternate());
-        } /*else if usart.get_type_id() == TypeId::of::<Usart3>() {
-            // PB10 = TX, PB11 = RX
-            afio.mapr.modify(
-                |_, w| unsafe { w.usart3_remap().bits(0b00) },
+        U::enable(rcc);
+        U::reset(rcc);
+
+        U::configure_pins(gpio, rcc);
+
+        if let Some(dma) = dma {
+            U::TxChannel::configure(
+                dma,
+                &dma::Config::peripheral(
+                    Direction::MemoryToPeripheral,
+                    Size::Byte,
+                ),
+            );
+            U::RxChannel::configure(
+                dma,
+                &dma::Config::peripheral(
+                    Direction::PeripheralToMemory,
+                    Size::Byte,
+                ),
             );
-            gpio.crh.modify(|_, w| {
-                w.mode10()
-                    .output()
-                    .cnf10()
-                    .alt_push()
-                    .mode11()
-                    .input()
-                    .cnf11()
-                    .bits(0b01)
-            });
-        } */
-
-        if let Some(dma1) = dma1 {
-            if usart.get_type_id() == TypeId::of::<Usart1>() {
-                // TX DMA transfer
-                // mem2mem: Memory to memory mode disabled
-                // pl: Medium priority
-                // msize: Memory size = 8 bits
-                // psize: Peripheral size = 8 bits
-                // minc: Memory increment mode enabled
-                // pinc: Peripheral increment mode disabled
-                // circ: Circular mode disabled
-                // dir: Transfer from memory to peripheral
-                // tceie: Transfer complete interrupt enabled
-                // en: Disabled
-                dma1.ccr4.write(|w| unsafe {
-                    w.mem2mem()
-                        .bits(0)
-                        .pl()
-                        .bits(0b01)
-                        .msize()
-                        .bits(0b00)
-                        .psize()
-                        .bits(0b00)
-                        .minc()
-                        .bits(1)
-                        .circ()
-                        .bits(0)
-                        .pinc()
-                        .bits(0)
-                        .dir()
-                        .bits(1)
-                        .tcie()
-                        .bits(1)
-                        .en()
-                        .bits(0)
-                });
-
-                // RX DMA transfer
-                // mem2mem: Memory to memory mode disabled
-                // pl: Medium priority
-                // msize: Memory size = 8 bits
-                // psize: Peripheral size = 8 bits
-                // minc: Memory increment mode enabled
-                // pinc: Peripheral increment mode disabled
-                // circ: Circular mode disabled
-                // dir: Transfer from peripheral to memory
-                // tceie: Transfer complete interrupt enabled
-                // en: Disabled
-                dma1.ccr5.write(|w| unsafe {
-                    w.mem2mem()
-                        .bits(0)
-                        .pl()
-                        .bits(0b01)
-                        .msize()
-                        .bits(0b00)
-                        .psize()
-                        .bits(0b00)
-                        .minc()
-                        .bits(1)
-                        .circ()
-                        .bits(0)
-                        .pinc()
-                        .bits(0)
-                        .dir()
-                        .bits(0)
-                        .tcie()
-                        .bits(1)
-                        .en()
-                        .bits(0)
-                });
-            } else {
-                // TODO enable DMA for USART{2,3}
-                unimplemented!()
-            }
         }
 
         // 8N1
@@ -299,6 +264,140 @@ where
         });
     }
 
+    /// Starts a DMA transfer to receive serial data into a `buffer`
+    ///
+    /// This will mutably lock the `buffer` preventing borrowing its contents
+    /// The `buffer` can be `release`d after the DMA transfer finishes
+    // TODO support circular mode + half transfer interrupt as a double
+    // buffering mode
+    pub fn read_exact<B>(
+        &self,
+        dma: &U::DMA,
+        _channel: &U::RxChannel,
+        buffer: Ref<Buffer<B, U::RxChannel>>,
+    ) -> ::core::result::Result<(), dma::Error>
+    where
+        B: Unsize<[u8]>,
+    {
+        let usart = self.0;
+
+        if U::RxChannel::is_enabled(dma) {
+            return Err(dma::Error::InUse);
+        }
+
+        let len = dma::transfer_len(buffer.len::<u8>())?;
+        let buffer: &mut [u8] = buffer.lock_mut()?;
+
+        U::RxChannel::start(
+            dma,
+            &usart.rdr as *const _ as u32,
+            buffer.as_ptr() as u32,
+            len,
+        );
+
+        Ok(())
+    }
+
+    /// Starts a DMA transfer to send `buffer` through this serial port
+    ///
+    /// This will immutably lock the `buffer` preventing mutably borrowing its
+    /// contents. The `buffer` can be `release`d after the DMA transfer finishes
+    pub fn write_all<B>(
+        &self,
+        dma: &U::DMA,
+        _channel: &U::TxChannel,
+        buffer: Ref<Buffer<B, U::TxChannel>>,
+    ) -> ::core::result::Result<(), dma::Error>
+    where
+        B: Unsize<[u8]>,
+    {
+        let usart = self.0;
+
+        if U::TxChannel::is_enabled(dma) {
+            return Err(dma::Error::InUse);
+        }
+
+        let len = dma::transfer_len(buffer.len::<u8>())?;
+        let buffer: &[u8] = buffer.lock()?;
+
+        U::TxChannel::start(
+            dma,
+            &usart.tdr as *const _ as u32,
+            buffer.as_ptr() as u32,
+            len,
+        );
+
+        Ok(())
+    }
+
+    /// Starts receiving serial data into `buffer` using the DMA
+    ///
+    /// `buffer` and `channel` are handed back by `OwnedTransfer::wait` once
+    /// `buffer` has been filled, or right away if the transfer can't be
+    /// started.
+    pub fn receive<B>(
+        &self,
+        dma: &U::DMA,
+        channel: U::RxChannel,
+        buffer: &'static mut B,
+    ) -> ::core::result::Result<
+        OwnedTransfer<B, U::RxChannel>,
+        TransferError<B, U::RxChannel>,
+    >
+    where
+        B: Unsize<[u8]>,
+    {
+        let usart = self.0;
+
+        // NOTE(unsafe) RDR is the source of the RX channel requests
+        unsafe {
+            OwnedTransfer::start::<u8>(
+                channel,
+                dma,
+                &dma::Config::peripheral(
+                    Direction::PeripheralToMemory,
+                    Size::Byte,
+                ),
+                &usart.rdr as *const _ as u32,
+                buffer,
+            )
+        }
+    }
+
+    /// Starts sending `buffer` through this serial port using the DMA
+    ///
+    /// `buffer` and `channel` are handed back by `OwnedTransfer::wait` once
+    /// `buffer` has been sent, or right away if the transfer can't be
+    /// started.
+    pub fn send<B>(
+        &self,
+        dma: &U::DMA,
+        channel: U::TxChannel,
+        buffer: &'static mut B,
+    ) -> ::core::result::Result<
+        OwnedTransfer<B, U::TxChannel>,
+        TransferError<B, U::TxChannel>,
+    >
+    where
+        B: Unsize<[u8]>,
+    {
+        let usart = self.0;
+
+        // NOTE(unsafe) TDR is the destination of the TX channel requests
+        unsafe {
+            OwnedTransfer::start::<u8>(
+                channel,
+                dma,
+                &dma::Config::peripheral(
+                    Direction::MemoryToPeripheral,
+                    Size::Byte,
+                ),
+                &usart.tdr as *const _ as u32,
+                buffer,
+            )
+        }
+    }
+
     /// Starts listening for an interrupt `event`
     pub fn listen(&self, event: Event) {
         let usart = self.0;
@@ -324,7 +423,7 @@ where
 
 impl<'a, U> hal::Serial for Serial<'a, U>
 where
-    U: Any + Usart,
+    U: Usart,
 {
     type Error = Error;
 
@@ -370,75 +469,3 @@ where
         }
     }
 }
-
-impl<'a> Serial<'a, Usart1> {
-    /// Starts a DMA transfer to receive serial data into a `buffer`
-    ///
-    /// This will mutably lock the `buffer` preventing borrowing its contents
-    /// The `buffer` can be `release`d after the DMA transfer finishes
-    // TODO support circular mode + half transfer interrupt as a double
-    // buffering mode
-    pub fn read_exact<B>(
-        &self,
-        dma1: &Dma1,
-        buffer: Ref<Buffer<B, Dma1Channel5>>,
-    ) -> ::core::result::Result<(), dma::Error>
-    where
-        B: Unsize<[u8]>,
-    {
-        let usart1 = self.0;
-
-        if dma1.ccr5.read().en().bits() == 1 {
-            return Err(dma::Error::InUse);
-        }
-
-        let buffer: &mut [u8] = buffer.lock_mut();
-
-        dma1.cndtr5.write(|w| unsafe {
-            w.ndt().bits(u16(buffer.len()).unwrap())
-        });
-        dma1.cpar5.write(|w| unsafe {
-            w.bits(&usart1.rdr as *const _ as u32)
-        });
-        dma1.cmar5.write(
-            |w| unsafe { w.bits(buffer.as_ptr() as u32) },
-        );
-        dma1.ccr5.modify(|_, w| unsafe { w.en().bits(1) });
-
-        Ok(())
-    }
-
-    /// Starts a DMA transfer to send `buffer` through this serial port
-    ///
-    /// This will immutably lock the `buffer` preventing mutably borrowing its
-    /// contents. The `buffer` can be `release`d after the DMA transfer finishes
-    pub fn write_all<B>(
-        &self,
-        dma1: &Dma1,
-        buffer: Ref<Buffer<B, Dma1Channel4>>,
-    ) -> ::core::result::Result<(), dma::Error>
-    where
-        B: Unsize<[u8]>,
-    {
-        let usart1 = self.0;
-
-        if dma1.ccr4.read().en().bits() == 1 {
-            return Err(dma::Error::InUse);
-        }
-
-        let buffer: &[u8] = buffer.lock();
-
-        dma1.cndtr4.write(|w| unsafe {
-            w.ndt().bits(u16(buffer.len()).unwrap())
-        });
-        dma1.cpar4.write(|w| unsafe {
-            w.bits(&usart1.tdr as *const _ as u32)
-        });
-        dma1.cmar4.write(
-            |w| unsafe { w.bits(buffer.as_ptr() as u32) },
-        );
-        dma1.ccr4.modify(|_, w| unsafe { w.en().bits(1) });
-
-        Ok(())
-    }
-}
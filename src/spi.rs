@@ -8,23 +8,174 @@
 //! - SCK = PA5
 //! - MISO = PA6
 //! - MOSI = PA7
+//! - DMA RX = DMA1 channel 2
+//! - DMA TX = DMA1 channel 3
+//!
+//! # SPI2
+//!
+//! - NSS = PB12
+//! - SCK = PB13
+//! - MISO = PB14
+//! - MOSI = PB15
+//! - DMA RX = DMA1 channel 4
+//! - DMA TX = DMA1 channel 5
+//!
+//! # SPI3
+//!
+//! - NSS = PA15 (not configured by this driver, set it to AF6 yourself when
+//!   using hardware slave management)
+//! - SCK = PC10
+//! - MISO = PC11
+//! - MOSI = PC12
+//! - DMA RX = DMA2 channel 1
+//! - DMA TX = DMA2 channel 2
 
-use core::any::{Any, TypeId};
+use core::marker::Unsize;
 use core::ops::Deref;
 use core::ptr;
 
 use hal;
 use nb;
-use stm32f30x::{Gpioa, Rcc, Spi1, gpioa, spi1};
+use static_ref::Ref;
+use stm32f30x::{Dma1, Dma2, Gpioa, Gpiob, Gpioc, Rcc, Spi1, Spi2, Spi3,
+                gpioa, spi1};
+
+use dma::{self, Buffer, DMA, Direction, Dma1Channel2, Dma1Channel3,
+          Dma1Channel4, Dma1Channel5, Dma2Channel1, Dma2Channel2, DmaChannel,
+          Priority, Size};
 
 /// SPI instance that can be used with the `Spi` abstraction
 pub unsafe trait SPI: Deref<Target = spi1::RegisterBlock> {
     /// GPIO block associated to this SPI instance
     type GPIO: Deref<Target = gpioa::RegisterBlock>;
+    /// DMA controller that serves the requests of this SPI instance
+    type DMA: DMA;
+    /// DMA channel that serves the RX requests of this SPI instance
+    type RxChannel: DmaChannel<Dma = Self::DMA>;
+    /// DMA channel that serves the TX requests of this SPI instance
+    type TxChannel: DmaChannel<Dma = Self::DMA>;
+
+    /// Powers up this SPI instance
+    fn enable(rcc: &Rcc);
+    /// Resets this SPI instance
+    fn reset(rcc: &Rcc);
+    /// Configures the pins of this SPI instance as alternate functions
+    fn configure_pins(gpio: &Self::GPIO, rcc: &Rcc);
 }
 
 unsafe impl SPI for Spi1 {
     type GPIO = Gpioa;
+    type DMA = Dma1;
+    type RxChannel = Dma1Channel2;
+    type TxChannel = Dma1Channel3;
+
+    fn enable(rcc: &Rcc) {
+        rcc.apb2enr.modify(|_, w| w.spi1en().enabled());
+    }
+
+    fn reset(rcc: &Rcc) {
+        rcc.apb2rstr.modify(|_, w| unsafe { w.spi1rst().bits(1) });
+        rcc.apb2rstr.modify(|_, w| unsafe { w.spi1rst().bits(0) });
+    }
+
+    fn configure_pins(gpio: &Gpioa, rcc: &Rcc) {
+        rcc.ahbenr.modify(|_, w| w.iopaen().enabled());
+
+        // NSS = PA4 = Alternate function push pull
+        // SCK = PA5 = Alternate function push pull
+        // MISO = PA6 = Floating input
+        // MOSI = PA7 = Alternate function push pull
+        gpio
+            .afrl.modify(|_, w| unsafe {
+                w
+                    .afrl4().bits(5)
+                    .afrl5().bits(5)
+                    .afrl6().bits(5)
+                    .afrl7().bits(5)
+            });
+        gpio
+            .moder
+            .modify(|_, w| w
+                    .moder4().alternate()
+                    .moder5().alternate()
+                    .moder6().alternate()
+                    .moder7().alternate()
+            );
+    }
+}
+
+unsafe impl SPI for Spi2 {
+    type GPIO = Gpiob;
+    type DMA = Dma1;
+    type RxChannel = Dma1Channel4;
+    type TxChannel = Dma1Channel5;
+
+    fn enable(rcc: &Rcc) {
+        rcc.apb1enr.modify(|_, w| w.spi2en().enabled());
+    }
+
+    fn reset(rcc: &Rcc) {
+        rcc.apb1rstr.modify(|_, w| unsafe { w.spi2rst().bits(1) });
+        rcc.apb1rstr.modify(|_, w| unsafe { w.spi2rst().bits(0) });
+    }
+
+    fn configure_pins(gpio: &Gpiob, rcc: &Rcc) {
+        rcc.ahbenr.modify(|_, w| w.iopben().enabled());
+
+        // NSS = PB12, SCK = PB13, MISO = PB14, MOSI = PB15
+        gpio
+            .afrh.modify(|_, w| unsafe {
+                w
+                    .afrh12().bits(5)
+                    .afrh13().bits(5)
+                    .afrh14().bits(5)
+                    .afrh15().bits(5)
+            });
+        gpio
+            .moder
+            .modify(|_, w| w
+                    .moder12().alternate()
+                    .moder13().alternate()
+                    .moder14().alternate()
+                    .moder15().alternate()
+            );
+    }
+}
+
+unsafe impl SPI for Spi3 {
+    type GPIO = Gpioc;
+    type DMA = Dma2;
+    type RxChannel = Dma2Channel1;
+    type TxChannel = Dma2Channel2;
+
+    fn enable(rcc: &Rcc) {
+        rcc.apb1enr.modify(|_, w| w.spi3en().enabled());
+    }
+
+    fn reset(rcc: &Rcc) {
+        rcc.apb1rstr.modify(|_, w| unsafe { w.spi3rst().bits(1) });
+        rcc.apb1rstr.modify(|_, w| unsafe { w.spi3rst().bits(0) });
+    }
+
+    fn configure_pins(gpio: &Gpioc, rcc: &Rcc) {
+        rcc.ahbenr.modify(|_, w| w.iopcen().enabled());
+
+        // SCK = PC10, MISO = PC11, MOSI = PC12
+        gpio
+            .afrh.modify(|_, w| unsafe {
+                w
+                    .afrh10().bits(6)
+                    .afrh11().bits(6)
+                    .afrh12().bits(6)
+            });
+        gpio
+            .moder
+            .modify(|_, w| w
+                    .moder10().alternate()
+                    .moder11().alternate()
+                    .moder12().alternate()
+            );
+    }
 }
 
 /// SPI result
@@ -39,68 +190,145 @@ pub enum Error {
     ModeFault,
     /// CRC error
     Crc,
+    /// Underrun occurred (the master clocked out data before the slave
+    /// provided it)
+    ///
+    /// NOTE the STM32F3 only raises the underrun flag (UDR) in I2S mode. In
+    /// SPI mode a slave that runs out of data goes on shifting out stale
+    /// data without any error, so the TX data must be loaded (e.g. with a
+    /// DMA `transfer`) before the master starts clocking.
+    Underrun,
+    /// DMA transfer error
+    Dma(dma::Error),
+    /// The operation requires the SPI to be disabled
+    Enabled,
     #[doc(hidden)]
     _Extensible,
 }
 
+/// Interrupt event
+pub enum Event {
+    /// RX buffer Not Empty (new data available)
+    Rxne,
+    /// TX buffer Empty (more data can be send)
+    Txe,
+    /// Overrun, underrun, mode fault or CRC error
+    Error,
+}
+
+/// Role of the SPI on the bus
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Role {
+    /// Drives SCK and NSS
+    Master,
+    /// Follows the SCK and NSS driven by an external master
+    Slave,
+}
+
 /// Serial Peripheral Interface
 pub struct Spi<'a, S>(pub &'a S)
 where
-    S: Any + SPI;
+    S: SPI;
 
 impl<'a, S> Spi<'a, S>
 where
-    S: Any + SPI,
+    S: SPI,
 {
-    /// Initializes the SPI
-    pub fn init(&self, gpio: &S::GPIO, rcc: &Rcc) {
+    /// Initializes the SPI as a bus master
+    ///
+    /// If `dma` is provided the DMA channels associated to this SPI instance
+    /// are configured for the `transfer` method.
+    pub fn init(&self, dma: Option<&S::DMA>, gpio: &S::GPIO, rcc: &Rcc) {
+        self._init(Role::Master, dma, gpio, rcc)
+    }
+
+    /// Initializes the SPI as a bus slave
+    ///
+    /// The slave is selected through the hardware NSS input. If `dma` is
+    /// provided the DMA channels associated to this SPI instance are
+    /// configured for the `transfer` method.
+    pub fn init_slave(&self, dma: Option<&S::DMA>, gpio: &S::GPIO, rcc: &Rcc) {
+        self._init(Role::Slave, dma, gpio, rcc)
+    }
+
+    fn _init(
+        &self,
+        role: Role,
+        dma: Option<&S::DMA>,
+        gpio: &S::GPIO,
+        rcc: &Rcc,
+    ) {
         let spi = self.0;
 
-        if spi.get_type_id() == TypeId::of::<Spi1>() {
-            // enable AFIO, SPI1, GPIOA
-            rcc.apb2enr.modify(|_, w| w.spi1en().enabled());
-            rcc.ahbenr.modify(|_, w| w.iopaen().enabled());
-
-            // NSS = PA4 = Alternate function push pull
-            // SCK = PA5 = Alternate function push pull
-            // MISO = PA6 = Floating input
-            // MOSI = PA7 = Alternate function push pull
-            gpio
-                .afrl.modify(|_, w| unsafe {
-                    w
-                        .afrl4().bits(5)
-                        .afrl5().bits(5)
-                        .afrl6().bits(5)
-                        .afrl7().bits(5)
-                });
-            gpio
-                .moder
-                .modify(|_, w| w
-                        .moder4().alternate()
-                        .moder5().alternate()
-                        .moder6().alternate()
-                        .moder7().alternate()
-                );
-        }
-
-        // enable SS output
-        spi.cr2.write(|w| unsafe { w.ssoe().bits(1) });
+        // power up peripherals
+        if dma.is_some() {
+            S::DMA::enable(rcc);
+        }
+        S::enable(rcc);
+        S::reset(rcc);
+
+        S::configure_pins(gpio, rcc);
+
+        if let Some(dma) = dma {
+            // full duplex transfers of 8-bit frames; the RX channel has the
+            // highest priority so that no incoming frame gets lost
+            S::RxChannel::configure(
+                dma,
+                &dma::Config {
+                    priority: Priority::VeryHigh,
+                    ..dma::Config::peripheral(
+                        Direction::PeripheralToMemory,
+                        Size::Byte,
+                    )
+                },
+            );
+            S::TxChannel::configure(
+                dma,
+                &dma::Config {
+                    priority: Priority::High,
+                    transfer_complete_interrupt: false,
+                    ..dma::Config::peripheral(
+                        Direction::MemoryToPeripheral,
+                        Size::Byte,
+                    )
+                },
+            );
+        }
+
+        // ssoe: SS output in master mode; NSS is an input in slave mode
+        // frxth: RXNE (and the RX DMA request) is set when 8 bits have been
+        // received
+        // rxdmaen / txdmaen: the DMA requests do nothing until a DMA
+        // channel gets enabled
+        let ssoe = if role == Role::Master { 1 } else { 0 };
+        let dmaen = if dma.is_some() { 1 } else { 0 };
+        spi.cr2.write(|w| unsafe {
+            w.ssoe()
+                .bits(ssoe)
+                .frxth()
+                .bits(1)
+                .rxdmaen()
+                .bits(dmaen)
+                .txdmaen()
+                .bits(dmaen)
+        });
 
         // cpha: second clock transition is the first data capture
         // cpol: CK to 1 when idle
-        // mstr: master configuration
-        // br: 1 MHz frequency
+        // mstr: master or slave configuration
+        // br: 1 MHz frequency (ignored in slave mode)
         // lsbfirst: MSB first
         // ssm: disable software slave management
         // dff: 8 bit frames
         // bidimode: 2-line unidirectional
+        let mstr = if role == Role::Master { 1 } else { 0 };
         spi.cr1.write(|w| unsafe {
             w.cpha()
                 .bits(1)
                 .cpol()
                 .bits(1)
                 .mstr()
-                .bits(1)
+                .bits(mstr)
                 .br()
                 .bits(0b10)
                 .lsbfirst()
@@ -116,38 +344,308 @@ where
         });
     }
 
+    /// Starts a full duplex DMA transfer
+    ///
+    /// The contents of `tx` are shifted out while `rx` gets filled with the
+    /// incoming data. In slave mode `tx` is the response that gets preloaded
+    /// before the master starts clocking; the transfer completes once the
+    /// master has clocked `rx.len()` bytes.
+    ///
+    /// This will mutably lock `rx` and immutably lock `tx`. Both buffers are
+    /// released by `Spi::release` once the DMA transfer finishes. Returns
+    /// `dma::Error::LengthMismatch` if `rx` and `tx` don't have the same
+    /// length.
+    ///
+    /// With the CRC enabled `tx` and `rx` only hold the data of the frame:
+    /// the hardware shifts out the TX CRC right after the last byte of `tx`
+    /// (no CRC-next handling is required) and the received CRC is left in
+    /// the RX FIFO for `release` to check.
+    pub fn transfer<R, T>(
+        &self,
+        dma: &S::DMA,
+        _tx_channel: &S::TxChannel,
+        _rx_channel: &S::RxChannel,
+        tx: Ref<Buffer<T, S::TxChannel>>,
+        rx: Ref<Buffer<R, S::RxChannel>>,
+    ) -> ::core::result::Result<(), dma::Error>
+    where
+        R: Unsize<[u8]>,
+        T: Unsize<[u8]>,
+    {
+        let spi = self.0;
+
+        if S::RxChannel::is_enabled(dma) || S::TxChannel::is_enabled(dma) {
+            return Err(dma::Error::InUse);
+        }
+
+        if rx.len::<u8>() != tx.len::<u8>() {
+            return Err(dma::Error::LengthMismatch);
+        }
+
+        let len = dma::transfer_len(rx.len::<u8>())?;
+
+        let rx_data: &mut [u8] = rx.lock_mut()?;
+        let tx_data: &[u8] = match tx.lock() {
+            Ok(tx_data) => tx_data,
+            Err(e) => {
+                // NOTE the RX channel hasn't been started yet
+                rx.abort(dma);
+                return Err(e);
+            }
+        };
+
+        let dr = &spi.dr as *const _ as u32;
+
+        // NOTE the RX channel must be enabled before the TX channel
+        S::RxChannel::start(dma, dr, rx_data.as_ptr() as u32, len);
+        S::TxChannel::start(dma, dr, tx_data.as_ptr() as u32, len);
+
+        Ok(())
+    }
+
+    /// Waits until a DMA `transfer` finishes and releases its buffers
+    ///
+    /// With the CRC enabled this also waits for the CRC that terminates the
+    /// frame and checks it against the CRC computed over `rx`; see
+    /// `check_crc`.
+    pub fn release<R, T>(
+        &self,
+        dma: &S::DMA,
+        tx: Ref<Buffer<T, S::TxChannel>>,
+        rx: Ref<Buffer<R, S::RxChannel>>,
+    ) -> Result<()> {
+        tx.release(dma).map_err(dma_error)?;
+        rx.release(dma).map_err(dma_error)?;
+
+        if self.is_crc_enabled() {
+            self.check_crc()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the role this SPI was initialized with
+    pub fn role(&self) -> Role {
+        if self.0.cr1.read().mstr().bits() == 1 {
+            Role::Master
+        } else {
+            Role::Slave
+        }
+    }
+
     /// Disables the SPI bus
     ///
-    /// **NOTE** This drives the NSS pin high
+    /// **NOTE** In master mode this drives the NSS pin high
     pub fn disable(&self) {
         self.0.cr1.modify(|_, w| unsafe { w.spe().bits(0) })
     }
 
     /// Enables the SPI bus
     ///
-    /// **NOTE** This drives the NSS pin low
+    /// **NOTE** In master mode this drives the NSS pin low
     pub fn enable(&self) {
         self.0.cr1.modify(|_, w| unsafe { w.spe().bits(1) })
     }
+
+    /// Starts listening for an interrupt `event`
+    pub fn listen(&self, event: Event) {
+        let spi = self.0;
+
+        match event {
+            Event::Rxne => spi.cr2.modify(|_, w| unsafe { w.rxneie().bits(1) }),
+            Event::Txe => spi.cr2.modify(|_, w| unsafe { w.txeie().bits(1) }),
+            Event::Error => spi.cr2.modify(|_, w| unsafe { w.errie().bits(1) }),
+        }
+    }
+
+    /// Stops listening for an interrupt `event`
+    pub fn unlisten(&self, event: Event) {
+        let spi = self.0;
+
+        match event {
+            Event::Rxne => spi.cr2.modify(|_, w| unsafe { w.rxneie().bits(0) }),
+            Event::Txe => spi.cr2.modify(|_, w| unsafe { w.txeie().bits(0) }),
+            Event::Error => spi.cr2.modify(|_, w| unsafe { w.errie().bits(0) }),
+        }
+    }
+
+    /// Clears the overrun, underrun, mode fault and CRC error flags
+    ///
+    /// This discards the byte that's pending in the RX buffer, if any. In
+    /// master mode the SPI has to be re-`enable`d after a mode fault.
+    pub fn clear_errors(&self) {
+        let spi = self.0;
+
+        // OVR is cleared by a read of DR followed by a read of SR; UDR by
+        // the read of SR
+        unsafe {
+            ptr::read_volatile(&spi.dr as *const _ as *const u8);
+        }
+        let sr = spi.sr.read();
+
+        if sr.modf().bits() == 1 {
+            // MODF is cleared by a read of SR followed by a write of CR1
+            spi.cr1.modify(|_, w| w);
+        }
+
+        // CRCERR is cleared by writing 0 to it
+        spi.sr.modify(|_, w| unsafe { w.crcerr().bits(0) });
+    }
+
+    /// Enables the hardware CRC calculation using the given `polynomial`
+    ///
+    /// The CRC is 8 bits long for 8-bit frames and 16 bits long for 16-bit
+    /// frames. This also resets the RX and TX CRC registers.
+    ///
+    /// Returns `Error::Enabled` if the SPI is enabled
+    pub fn enable_crc(
+        &self,
+        polynomial: u16,
+    ) -> ::core::result::Result<(), Error> {
+        let spi = self.0;
+
+        if spi.cr1.read().spe().bits() == 1 {
+            return Err(Error::Enabled);
+        }
+
+        spi.crcpr.write(|w| unsafe { w.crcpoly().bits(polynomial) });
+
+        // toggling CRCEN clears the CRC registers
+        spi.cr1.modify(|_, w| unsafe { w.crcen().bits(0) });
+        spi.cr1.modify(|_, w| unsafe { w.crcen().bits(1) });
+
+        Ok(())
+    }
+
+    /// Disables the hardware CRC calculation
+    ///
+    /// Returns `Error::Enabled` if the SPI is enabled
+    pub fn disable_crc(&self) -> ::core::result::Result<(), Error> {
+        let spi = self.0;
+
+        if spi.cr1.read().spe().bits() == 1 {
+            return Err(Error::Enabled);
+        }
+
+        spi.cr1.modify(|_, w| unsafe { w.crcen().bits(0) });
+
+        Ok(())
+    }
+
+    /// Resets the RX and TX CRC registers to start a new CRC frame
+    ///
+    /// Returns `Error::Enabled` if the SPI is enabled
+    pub fn reset_crc(&self) -> ::core::result::Result<(), Error> {
+        self.enable_crc(self.crc_polynomial())
+    }
+
+    /// Checks whether the hardware CRC calculation is enabled
+    pub fn is_crc_enabled(&self) -> bool {
+        self.0.cr1.read().crcen().bits() == 1
+    }
+
+    /// Returns the CRC polynomial
+    pub fn crc_polynomial(&self) -> u16 {
+        self.0.crcpr.read().crcpoly().bits()
+    }
+
+    /// Returns the CRC computed over the received data
+    pub fn rx_crc(&self) -> u16 {
+        self.0.rxcrcr.read().rxcrc().bits()
+    }
+
+    /// Returns the CRC computed over the transmitted data
+    pub fn tx_crc(&self) -> u16 {
+        self.0.txcrcr.read().txcrc().bits()
+    }
+
+    /// Sends the last `byte` of a CRC frame
+    ///
+    /// The TX CRC is shifted out right after `byte`. Once the received CRC
+    /// has arrived use `check_crc` to verify it.
+    pub fn send_last(&self, byte: u8) -> Result<()> {
+        hal::Spi::send(self, byte)?;
+
+        // NOTE must be set right after the last data has been written into
+        // the TX buffer
+        self.0.cr1.modify(|_, w| unsafe { w.crcnext().bits(1) });
+
+        Ok(())
+    }
+
+    /// Reads the CRC that terminates a frame and checks it against the CRC
+    /// computed over the received data
+    ///
+    /// This must be called once all the data of the frame has been `read`.
+    /// DMA transfers are checked by `release`.
+    ///
+    /// The CRC error flag is cleared by this method.
+    pub fn check_crc(&self) -> Result<()> {
+        let spi = self.0;
+        let sr = spi.sr.read();
+
+        if sr.ovr().bits() == 1 {
+            return Err(nb::Error::Other(Error::Overrun));
+        }
+
+        if sr.rxne().bits() == 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        // drain the received CRC (1 or 2 bytes)
+        // NOTE(read_volatile) see note in the `read` method
+        unsafe {
+            if spi.cr1.read().dff().bits() == 1 {
+                ptr::read_volatile(&spi.dr as *const _ as *const u16);
+            } else {
+                ptr::read_volatile(&spi.dr as *const _ as *const u8);
+            }
+        }
+
+        if spi.sr.read().crcerr().bits() == 1 {
+            spi.sr.modify(|_, w| unsafe { w.crcerr().bits(0) });
+            Err(nb::Error::Other(Error::Crc))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn check_errors(&self) -> Result<()> {
+        let sr = self.0.sr.read();
+
+        if sr.ovr().bits() == 1 {
+            Err(nb::Error::Other(Error::Overrun))
+        } else if sr.udr().bits() == 1 {
+            Err(nb::Error::Other(Error::Underrun))
+        } else if sr.modf().bits() == 1 {
+            Err(nb::Error::Other(Error::ModeFault))
+        } else if sr.crcerr().bits() == 1 {
+            Err(nb::Error::Other(Error::Crc))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn dma_error(error: nb::Error<dma::Error>) -> nb::Error<Error> {
+    match error {
+        nb::Error::Other(e) => nb::Error::Other(Error::Dma(e)),
+        nb::Error::WouldBlock => nb::Error::WouldBlock,
+    }
 }
 
 impl<'a, S> hal::Spi<u8> for Spi<'a, S>
 where
-    S: Any + SPI,
+    S: SPI,
 {
     type Error = Error;
 
     fn read(&self) -> Result<u8> {
         let spi1 = self.0;
-        let sr = spi1.sr.read();
 
-        if sr.ovr().bits() == 1 {
-            Err(nb::Error::Other(Error::Overrun))
-        } else if sr.modf().bits() == 1 {
-            Err(nb::Error::Other(Error::ModeFault))
-        } else if sr.crcerr().bits() == 1 {
-            Err(nb::Error::Other(Error::Crc))
-        } else if sr.rxne().bits() == 1 {
+        self.check_errors()?;
+
+        if spi1.sr.read().rxne().bits() == 1 {
             Ok(unsafe {
                 ptr::read_volatile(&spi1.dr as *const _ as *const u8)
             })
@@ -158,15 +656,10 @@ where
 
     fn send(&self, byte: u8) -> Result<()> {
         let spi1 = self.0;
-        let sr = spi1.sr.read();
 
-        if sr.ovr().bits() == 1 {
-            Err(nb::Error::Other(Error::Overrun))
-        } else if sr.modf().bits() == 1 {
-            Err(nb::Error::Other(Error::ModeFault))
-        } else if sr.crcerr().bits() == 1 {
-            Err(nb::Error::Other(Error::Crc))
-        } else if sr.txe().bits() == 1 {
+        self.check_errors()?;
+
+        if spi1.sr.read().txe().bits() == 1 {
             // NOTE(write_volatile) see note above
             unsafe {
                 ptr::write_volatile(&spi1.dr as *const _ as *mut u8, byte)
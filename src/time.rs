@@ -1,4 +1,11 @@
 //! Units of time
+//!
+//! Arithmetic on these units saturates instead of overflowing; use the
+//! `checked_*` methods to detect overflows. Conversions to finer units (e.g.
+//! `Seconds` -> `Milliseconds`) also saturate; `CheckedFrom` provides the
+//! fallible version of these conversions.
+
+use core::ops;
 
 macro_rules! map {
     ($Self:ident) => {
@@ -14,8 +21,162 @@ macro_rules! map {
     }
 }
 
+macro_rules! arithmetic {
+    ($Self:ident) => {
+        impl $Self {
+            /// Checked addition. Returns `None` on overflow
+            pub fn checked_add(self, rhs: $Self) -> Option<$Self> {
+                self.0.checked_add(rhs.0).map($Self)
+            }
+
+            /// Checked subtraction. Returns `None` on underflow
+            pub fn checked_sub(self, rhs: $Self) -> Option<$Self> {
+                self.0.checked_sub(rhs.0).map($Self)
+            }
+
+            /// Checked multiplication. Returns `None` on overflow
+            pub fn checked_mul(self, rhs: u32) -> Option<$Self> {
+                self.0.checked_mul(rhs).map($Self)
+            }
+        }
+
+        impl ops::Add for $Self {
+            type Output = $Self;
+
+            fn add(self, rhs: $Self) -> $Self {
+                $Self(self.0.saturating_add(rhs.0))
+            }
+        }
+
+        impl ops::Sub for $Self {
+            type Output = $Self;
+
+            fn sub(self, rhs: $Self) -> $Self {
+                $Self(self.0.saturating_sub(rhs.0))
+            }
+        }
+
+        impl ops::Mul<u32> for $Self {
+            type Output = $Self;
+
+            fn mul(self, rhs: u32) -> $Self {
+                $Self(self.0.saturating_mul(rhs))
+            }
+        }
+    }
+}
+
+/// Converts `$From` into the finer unit `$To`, `$From(1) == $To($factor)`
+macro_rules! scale {
+    ($From:ident, $To:ident, $factor:expr) => {
+        impl From<$From> for $To {
+            fn from(x: $From) -> $To {
+                $To(x.0.saturating_mul($factor))
+            }
+        }
+
+        impl CheckedFrom<$From> for $To {
+            fn checked_from(x: $From) -> Option<$To> {
+                x.0.checked_mul($factor).map($To)
+            }
+        }
+    }
+}
+
+/// Conversions between a period and a frequency
+macro_rules! period {
+    ($Self:ident, $period:ident, $per_second:expr) => {
+        impl $Self {
+            /// Returns the frequency of a signal with this period
+            ///
+            /// Returns `None` if the period is zero
+            pub fn frequency(self, rounding: Rounding) -> Option<Hertz> {
+                divide($per_second, self.0 as u64, rounding).map(Hertz)
+            }
+        }
+
+        impl Hertz {
+            /// Returns the period of a signal with this frequency
+            ///
+            /// Returns `None` if the frequency is zero or the period doesn't
+            /// fit in the unit
+            pub fn $period(self, rounding: Rounding) -> Option<$Self> {
+                divide($per_second, self.0 as u64, rounding).map($Self)
+            }
+        }
+    }
+}
+
+/// Fallible conversion; the `TryFrom` counterpart of the saturating `From`
+/// conversions
+pub trait CheckedFrom<T>: Sized {
+    /// Performs the conversion. Returns `None` if the result doesn't fit
+    fn checked_from(x: T) -> Option<Self>;
+}
+
+/// Fallible conversion; the `TryInto` counterpart of the saturating `Into`
+/// conversions
+pub trait CheckedInto<T> {
+    /// Performs the conversion. Returns `None` if the result doesn't fit
+    fn checked_into(self) -> Option<T>;
+}
+
+impl<T, U> CheckedInto<U> for T
+where
+    U: CheckedFrom<T>,
+{
+    fn checked_into(self) -> Option<U> {
+        U::checked_from(self)
+    }
+}
+
+/// Rounding mode of lossy conversions
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Rounding {
+    /// Round towards zero
+    Down,
+    /// Round to the nearest integer
+    Nearest,
+    /// Round away from zero
+    Up,
+}
+
+/// Divides `n` by `d`, returning `None` if `d` is zero or the quotient
+/// doesn't fit in an `u32`
+fn divide(n: u64, d: u64, rounding: Rounding) -> Option<u32> {
+    if d == 0 {
+        return None;
+    }
+
+    let q = match rounding {
+        Rounding::Down => n / d,
+        Rounding::Nearest => (n + d / 2) / d,
+        Rounding::Up => (n + d - 1) / d,
+    };
+
+    checked(q)
+}
+
+/// Saturates `x` to the range of `u32`
+pub(crate) fn saturate(x: u64) -> u32 {
+    if x > u32::max_value() as u64 {
+        u32::max_value()
+    } else {
+        x as u32
+    }
+}
+
+/// Converts `x` to an `u32`, returning `None` if it doesn't fit
+pub(crate) fn checked(x: u64) -> Option<u32> {
+    if x > u32::max_value() as u64 {
+        None
+    } else {
+        Some(x as u32)
+    }
+}
+
 /// `Hz^-1`
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub struct IHertz(pub u32);
 
 impl IHertz {
@@ -28,7 +189,7 @@ impl IHertz {
 map!(IHertz);
 
 /// `Hz`
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub struct Hertz(pub u32);
 
 impl Hertz {
@@ -38,34 +199,83 @@ impl Hertz {
     }
 }
 
+arithmetic!(Hertz);
 map!(Hertz);
 
+/// `KHz`
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct KiloHertz(pub u32);
+
+arithmetic!(KiloHertz);
+map!(KiloHertz);
+
+/// `MHz`
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct MegaHertz(pub u32);
+
+arithmetic!(MegaHertz);
+map!(MegaHertz);
+
+/// `ns`
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Nanoseconds(pub u32);
+
+arithmetic!(Nanoseconds);
+map!(Nanoseconds);
+
 /// `us`
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub struct Microseconds(pub u32);
 
+arithmetic!(Microseconds);
 map!(Microseconds);
 
 /// `ms`
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub struct Milliseconds(pub u32);
 
+arithmetic!(Milliseconds);
 map!(Milliseconds);
 
 /// `s`
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub struct Seconds(pub u32);
 
+arithmetic!(Seconds);
 map!(Seconds);
 
+scale!(MegaHertz, KiloHertz, 1_000);
+scale!(MegaHertz, Hertz, 1_000_000);
+scale!(KiloHertz, Hertz, 1_000);
+
+scale!(Seconds, Milliseconds, 1_000);
+scale!(Seconds, Microseconds, 1_000_000);
+scale!(Seconds, Nanoseconds, 1_000_000_000);
+scale!(Milliseconds, Microseconds, 1_000);
+scale!(Milliseconds, Nanoseconds, 1_000_000);
+scale!(Microseconds, Nanoseconds, 1_000);
+
+period!(Nanoseconds, period_ns, 1_000_000_000);
+period!(Microseconds, period_us, 1_000_000);
+period!(Milliseconds, period_ms, 1_000);
+
 /// `u32` extension trait
 pub trait U32Ext {
     /// Wrap in `Hz`
     fn hz(self) -> Hertz;
 
+    /// Wrap in `KHz`
+    fn khz(self) -> KiloHertz;
+
+    /// Wrap in `MHz`
+    fn mhz(self) -> MegaHertz;
+
     /// Wrap in `Milliseconds`
     fn ms(self) -> Milliseconds;
 
+    /// Wrap in `Nanoseconds`
+    fn ns(self) -> Nanoseconds;
+
     /// Wrap in `Seconds`
     fn s(self) -> Seconds;
 
@@ -78,10 +288,22 @@ impl U32Ext for u32 {
         Hertz(self)
     }
 
+    fn khz(self) -> KiloHertz {
+        KiloHertz(self)
+    }
+
+    fn mhz(self) -> MegaHertz {
+        MegaHertz(self)
+    }
+
     fn ms(self) -> Milliseconds {
         Milliseconds(self)
     }
 
+    fn ns(self) -> Nanoseconds {
+        Nanoseconds(self)
+    }
+
     fn s(self) -> Seconds {
         Seconds(self)
     }
@@ -90,3 +312,95 @@ impl U32Ext for u32 {
         Microseconds(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::Rounding::*;
+
+    #[test]
+    fn arithmetic() {
+        let max = u32::max_value();
+
+        assert_eq!(Hertz(max) + Hertz(1), Hertz(max));
+        assert_eq!(Hertz(0) - Hertz(1), Hertz(0));
+        assert_eq!(Seconds(max) * 2, Seconds(max));
+        assert_eq!(Seconds(0) * max, Seconds(0));
+
+        assert_eq!(Hertz(max).checked_add(Hertz(1)), None);
+        assert_eq!(Hertz(max - 1).checked_add(Hertz(1)), Some(Hertz(max)));
+        assert_eq!(Hertz(0).checked_sub(Hertz(1)), None);
+        assert_eq!(Hertz(1).checked_sub(Hertz(1)), Some(Hertz(0)));
+        assert_eq!(Seconds(max).checked_mul(2), None);
+        assert_eq!(Seconds(max).checked_mul(1), Some(Seconds(max)));
+    }
+
+    #[test]
+    fn scale() {
+        let max = u32::max_value();
+
+        assert_eq!(Milliseconds::from(Seconds(0)), Milliseconds(0));
+        assert_eq!(Milliseconds::from(Seconds(1)), Milliseconds(1_000));
+        assert_eq!(Milliseconds::from(Seconds(max)), Milliseconds(max));
+        assert_eq!(Hertz::from(MegaHertz(max)), Hertz(max));
+
+        assert_eq!(
+            Milliseconds::checked_from(Seconds(4_294_967)),
+            Some(Milliseconds(4_294_967_000))
+        );
+        assert_eq!(Milliseconds::checked_from(Seconds(4_294_968)), None);
+        assert_eq!(
+            Nanoseconds::checked_from(Seconds(4)),
+            Some(Nanoseconds(4_000_000_000))
+        );
+        assert_eq!(Nanoseconds::checked_from(Seconds(5)), None);
+
+        let khz: Option<KiloHertz> = MegaHertz(1).checked_into();
+        assert_eq!(khz, Some(KiloHertz(1_000)));
+    }
+
+    #[test]
+    fn period() {
+        assert_eq!(Hertz(0).period_ns(Nearest), None);
+        assert_eq!(Nanoseconds(0).frequency(Nearest), None);
+
+        assert_eq!(Hertz(1).period_ns(Down), Some(Nanoseconds(1_000_000_000)));
+        assert_eq!(Hertz(1).period_us(Down), Some(Microseconds(1_000_000)));
+        assert_eq!(Nanoseconds(1).frequency(Down), Some(Hertz(1_000_000_000)));
+
+        assert_eq!(Hertz(3).period_ms(Down), Some(Milliseconds(333)));
+        assert_eq!(Hertz(3).period_ms(Nearest), Some(Milliseconds(333)));
+        assert_eq!(Hertz(3).period_ms(Up), Some(Milliseconds(334)));
+        assert_eq!(Hertz(2_000).period_ms(Nearest), Some(Milliseconds(1)));
+        assert_eq!(Hertz(2_001).period_ms(Nearest), Some(Milliseconds(0)));
+
+        let max = u32::max_value();
+        assert_eq!(Hertz(max).period_ns(Down), Some(Nanoseconds(0)));
+        assert_eq!(Hertz(max).period_ns(Up), Some(Nanoseconds(1)));
+        assert_eq!(Milliseconds(max).frequency(Down), Some(Hertz(0)));
+    }
+
+    #[test]
+    fn round_trip() {
+        for &hz in &[1, 7, 1_000, 50_000, 1_000_000] {
+            let period = Hertz(hz).period_ns(Nearest).unwrap();
+
+            assert_eq!(period.frequency(Nearest), Some(Hertz(hz)));
+        }
+
+        let us: Microseconds = Seconds(3).into();
+        assert_eq!(us, Microseconds(3_000_000));
+        assert_eq!(Hertz(440).invert().invert(), Hertz(440));
+    }
+
+    #[test]
+    fn saturate_checked() {
+        let max = u32::max_value();
+
+        assert_eq!(saturate(0), 0);
+        assert_eq!(saturate(u64::from(max)), max);
+        assert_eq!(saturate(u64::from(max) + 1), max);
+        assert_eq!(checked(u64::from(max)), Some(max));
+        assert_eq!(checked(u64::from(max) + 1), None);
+    }
+}
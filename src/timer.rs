@@ -1,12 +1,13 @@
 //! Timer
 
-use core::any::{Any, TypeId};
+use core::cmp;
 use core::ops::Deref;
 
-use cast::{u16, u32};
+use cast::u32;
 use hal;
 use nb::{self, Error};
-use stm32f30x::{Gpioa, Rcc, Tim7, Tim2, Tim3, Tim4, gpioa, tim2};
+use stm32f30x::{Gpioa, Gpiob, Gpioc, Gpiod, Gpioe, Rcc, Tim1, Tim15, Tim16,
+                Tim17, Tim2, Tim3, Tim4, Tim6, Tim7, Tim8, gpioa, tim1, tim2};
 
 /// Channel associated to a timer
 #[derive(Clone, Copy, Debug)]
@@ -21,22 +22,493 @@ pub enum Channel {
     _4,
 }
 
+/// Channel of TIM15
+#[derive(Clone, Copy, Debug)]
+pub enum Tim15Channel {
+    /// TIM15_CH1
+    _1,
+    /// TIM15_CH2
+    _2,
+}
+
+/// Channel of TIM16
+#[derive(Clone, Copy, Debug)]
+pub enum Tim16Channel {
+    /// TIM16_CH1
+    _1,
+}
+
+/// Channel of TIM17
+#[derive(Clone, Copy, Debug)]
+pub enum Tim17Channel {
+    /// TIM17_CH1
+    _1,
+}
+
+/// Source of the trigger output (TRGO) in master mode
+#[derive(Clone, Copy, Debug)]
+pub enum TriggerOutput {
+    /// The UG bit is used as TRGO
+    Reset,
+    /// The counter enable signal is used as TRGO
+    Enable,
+    /// The update event is used as TRGO
+    Update,
+}
+
+/// Timer error
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TimerError {
+    /// The period is shorter than 2 ticks (or the capture resolution is
+    /// zero)
+    PeriodTooShort,
+    /// The period (or the capture resolution) is longer than what the
+    /// prescaler and auto-reload registers can produce
+    PeriodTooLong,
+}
+
+/// Largest prescaler division factor (`PSC + 1`)
+const PSC_MAX: u64 = 1 << 16;
+
+/// Computes the prescaler (`PSC`) and auto-reload (`ARR`) values that
+/// produce an update event every `period` ticks
+///
+/// `arr_max` is the largest value the auto-reload register can hold. The
+/// prescalers that can reach `period` are searched for the one that gives
+/// the smallest error, stopping at the first one that divides `period`
+/// exactly; on a tie the smaller prescaler, which has the finer counter
+/// resolution, wins. The error is thus at most half a step of the smallest
+/// usable prescaler.
+pub fn psc_arr(period: u32, arr_max: u32) -> Result<(u16, u32), TimerError> {
+    if period < 2 {
+        Err(TimerError::PeriodTooShort)
+    } else if u64::from(period) > PSC_MAX * (u64::from(arr_max) + 1) {
+        Err(TimerError::PeriodTooLong)
+    } else {
+        Ok(compute(period, arr_max))
+    }
+}
+
+/// Like `psc_arr` but clamps `period` to the range the timer can produce
+pub fn psc_arr_saturating(period: u32, arr_max: u32) -> (u16, u32) {
+    let max = PSC_MAX * (u64::from(arr_max) + 1);
+    let period = if u64::from(period) > max {
+        max as u32
+    } else {
+        cmp::max(period, 2)
+    };
+
+    compute(period, arr_max)
+}
+
+/// Returns the period, in ticks, of a timer configured with `psc` and `arr`
+///
+/// Saturates at `u32::MAX`
+pub fn period(psc: u16, arr: u32) -> u32 {
+    let period = (u64::from(psc) + 1) * (u64::from(arr) + 1);
+
+    if period > u64::from(u32::max_value()) {
+        u32::max_value()
+    } else {
+        period as u32
+    }
+}
+
+// NOTE `period` must be in the range `2..=PSC_MAX * (arr_max + 1)`
+fn compute(period: u32, arr_max: u32) -> (u16, u32) {
+    let reload_max = u64::from(arr_max) + 1;
+
+    // smallest division factor (`PSC + 1`) that can reach `period`
+    let min = ((u64::from(period) + reload_max - 1) / reload_max) as u32;
+
+    let mut best = (min, reload(period, min, reload_max));
+    let mut best_error = error(period, best.0, best.1);
+
+    // NOTE this can take up to `PSC_MAX` iterations (prime periods) so
+    // `reload` and `error` stick to 32-bit divisions
+    let mut div = min + 1;
+    while best_error != 0 && u64::from(div) <= PSC_MAX {
+        let reload = reload(period, div, reload_max);
+        let error = error(period, div, reload);
+
+        if error < best_error {
+            best = (div, reload);
+            best_error = error;
+        }
+
+        div += 1;
+    }
+
+    ((best.0 - 1) as u16, (best.1 - 1) as u32)
+}
+
+// `period / div` rounded to the nearest integer and clamped to the reload
+// values (`ARR + 1`) the timer supports
+fn reload(period: u32, div: u32, reload_max: u64) -> u64 {
+    let (quotient, remainder) = (period / div, period % div);
+    let reload = if 2 * remainder >= div {
+        u64::from(quotient) + 1
+    } else {
+        u64::from(quotient)
+    };
+
+    cmp::min(cmp::max(reload, 2), reload_max)
+}
+
+// Distance between `period` and the period that `div` and `reload` produce
+fn error(period: u32, div: u32, reload: u64) -> u64 {
+    let (period, actual) = (u64::from(period), u64::from(div) * reload);
+
+    if actual > period {
+        actual - period
+    } else {
+        period - actual
+    }
+}
+
 /// IMPLEMENTATION DETAIL
 pub unsafe trait TIM: Deref<Target = tim2::RegisterBlock> {
     /// IMPLEMENTATION DETAIL
     type GPIO: Deref<Target = gpioa::RegisterBlock>;
+
+    /// Largest value the auto-reload register can hold
+    const ARR_MAX: u32;
+
+    /// IMPLEMENTATION DETAIL
+    fn enable(rcc: &Rcc);
+    /// IMPLEMENTATION DETAIL
+    fn reset(rcc: &Rcc);
+    /// IMPLEMENTATION DETAIL
+    fn configure_pins(gpio: &Self::GPIO, rcc: &Rcc);
 }
 
+// TIM2 has a 32-bit counter
 unsafe impl TIM for Tim2 {
     type GPIO = Gpioa;
+
+    const ARR_MAX: u32 = 0xffff_ffff;
+
+    fn enable(rcc: &Rcc) {
+        rcc.apb1enr.modify(|_, w| w.tim2en().enabled());
+    }
+
+    fn reset(rcc: &Rcc) {
+        rcc.apb1rstr.modify(|_, w| unsafe { w.tim2rst().bits(1) });
+        rcc.apb1rstr.modify(|_, w| unsafe { w.tim2rst().bits(0) });
+    }
+
+    fn configure_pins(gpio: &Gpioa, rcc: &Rcc) {
+        rcc.ahbenr.modify(|_, w| w.iopaen().enabled());
+
+        // CH1 = PA0, CH2 = PA1, CH3 = PA2, CH4 = PA3
+        gpio.afrl.modify(|_, w| unsafe {
+            w.afrl0()
+                .bits(1)
+                .afrl1()
+                .bits(1)
+                .afrl2()
+                .bits(1)
+                .afrl3()
+                .bits(1)
+        });
+        gpio.moder.modify(|_, w| {
+            w.moder0()
+                .alternate()
+                .moder1()
+                .alternate()
+                .moder2()
+                .alternate()
+                .moder3()
+                .alternate()
+        });
+    }
 }
 
 unsafe impl TIM for Tim3 {
-    type GPIO = Gpioa;
+    type GPIO = Gpioc;
+
+    const ARR_MAX: u32 = 0xffff;
+
+    fn enable(rcc: &Rcc) {
+        rcc.apb1enr.modify(|_, w| w.tim3en().enabled());
+    }
+
+    fn reset(rcc: &Rcc) {
+        rcc.apb1rstr.modify(|_, w| unsafe { w.tim3rst().bits(1) });
+        rcc.apb1rstr.modify(|_, w| unsafe { w.tim3rst().bits(0) });
+    }
+
+    fn configure_pins(gpio: &Gpioc, rcc: &Rcc) {
+        rcc.ahbenr.modify(|_, w| w.iopcen().enabled());
+
+        // CH1 = PC6, CH2 = PC7, CH3 = PC8, CH4 = PC9
+        gpio.afrl.modify(|_, w| unsafe {
+            w.afrl6().bits(2).afrl7().bits(2)
+        });
+        gpio.afrh.modify(|_, w| unsafe {
+            w.afrh8().bits(2).afrh9().bits(2)
+        });
+        gpio.moder.modify(|_, w| {
+            w.moder6()
+                .alternate()
+                .moder7()
+                .alternate()
+                .moder8()
+                .alternate()
+                .moder9()
+                .alternate()
+        });
+    }
 }
 
 unsafe impl TIM for Tim4 {
+    type GPIO = Gpiod;
+
+    const ARR_MAX: u32 = 0xffff;
+
+    fn enable(rcc: &Rcc) {
+        rcc.apb1enr.modify(|_, w| w.tim4en().enabled());
+    }
+
+    fn reset(rcc: &Rcc) {
+        rcc.apb1rstr.modify(|_, w| unsafe { w.tim4rst().bits(1) });
+        rcc.apb1rstr.modify(|_, w| unsafe { w.tim4rst().bits(0) });
+    }
+
+    fn configure_pins(gpio: &Gpiod, rcc: &Rcc) {
+        rcc.ahbenr.modify(|_, w| w.iopden().enabled());
+
+        // CH1 = PD12, CH2 = PD13, CH3 = PD14, CH4 = PD15
+        gpio.afrh.modify(|_, w| unsafe {
+            w.afrh12()
+                .bits(2)
+                .afrh13()
+                .bits(2)
+                .afrh14()
+                .bits(2)
+                .afrh15()
+                .bits(2)
+        });
+        gpio.moder.modify(|_, w| {
+            w.moder12()
+                .alternate()
+                .moder13()
+                .alternate()
+                .moder14()
+                .alternate()
+                .moder15()
+                .alternate()
+        });
+    }
+}
+
+/// IMPLEMENTATION DETAIL
+pub unsafe trait ATIM: Deref<Target = tim1::RegisterBlock> {
+    /// IMPLEMENTATION DETAIL
+    type GPIO: Deref<Target = gpioa::RegisterBlock>;
+
+    /// IMPLEMENTATION DETAIL
+    fn enable(rcc: &Rcc);
+    /// IMPLEMENTATION DETAIL
+    fn reset(rcc: &Rcc);
+    /// IMPLEMENTATION DETAIL
+    fn configure_pins(gpio: &Self::GPIO, rcc: &Rcc);
+}
+
+unsafe impl ATIM for Tim1 {
+    type GPIO = Gpioe;
+
+    fn enable(rcc: &Rcc) {
+        rcc.apb2enr.modify(|_, w| w.tim1en().enabled());
+    }
+
+    fn reset(rcc: &Rcc) {
+        rcc.apb2rstr.modify(|_, w| unsafe { w.tim1rst().bits(1) });
+        rcc.apb2rstr.modify(|_, w| unsafe { w.tim1rst().bits(0) });
+    }
+
+    fn configure_pins(gpio: &Gpioe, rcc: &Rcc) {
+        rcc.ahbenr.modify(|_, w| w.iopeen().enabled());
+
+        // CH1N = PE8, CH1 = PE9, CH2N = PE10, CH2 = PE11
+        // CH3N = PE12, CH3 = PE13, CH4 = PE14, BKIN = PE15
+        gpio.afrh.modify(|_, w| unsafe {
+            w.afrh8()
+                .bits(2)
+                .afrh9()
+                .bits(2)
+                .afrh10()
+                .bits(2)
+                .afrh11()
+                .bits(2)
+                .afrh12()
+                .bits(2)
+                .afrh13()
+                .bits(2)
+                .afrh14()
+                .bits(2)
+                .afrh15()
+                .bits(2)
+        });
+        gpio.moder.modify(|_, w| {
+            w.moder8()
+                .alternate()
+                .moder9()
+                .alternate()
+                .moder10()
+                .alternate()
+                .moder11()
+                .alternate()
+                .moder12()
+                .alternate()
+                .moder13()
+                .alternate()
+                .moder14()
+                .alternate()
+                .moder15()
+                .alternate()
+        });
+    }
+}
+
+unsafe impl ATIM for Tim8 {
+    type GPIO = Gpioc;
+
+    fn enable(rcc: &Rcc) {
+        rcc.apb2enr.modify(|_, w| w.tim8en().enabled());
+    }
+
+    fn reset(rcc: &Rcc) {
+        rcc.apb2rstr.modify(|_, w| unsafe { w.tim8rst().bits(1) });
+        rcc.apb2rstr.modify(|_, w| unsafe { w.tim8rst().bits(0) });
+    }
+
+    fn configure_pins(gpio: &Gpioc, rcc: &Rcc) {
+        rcc.ahbenr.modify(|_, w| w.iopcen().enabled());
+
+        // CH1 = PC6, CH2 = PC7, CH3 = PC8, CH4 = PC9
+        // CH1N = PC10, CH2N = PC11, CH3N = PC12
+        gpio.afrl.modify(|_, w| unsafe {
+            w.afrl6().bits(4).afrl7().bits(4)
+        });
+        gpio.afrh.modify(|_, w| unsafe {
+            w.afrh8()
+                .bits(4)
+                .afrh9()
+                .bits(4)
+                .afrh10()
+                .bits(4)
+                .afrh11()
+                .bits(4)
+                .afrh12()
+                .bits(4)
+        });
+        gpio.moder.modify(|_, w| {
+            w.moder6()
+                .alternate()
+                .moder7()
+                .alternate()
+                .moder8()
+                .alternate()
+                .moder9()
+                .alternate()
+                .moder10()
+                .alternate()
+                .moder11()
+                .alternate()
+                .moder12()
+                .alternate()
+        });
+    }
+}
+
+/// IMPLEMENTATION DETAIL
+pub unsafe trait GTIM {
+    /// IMPLEMENTATION DETAIL
+    type GPIO: Deref<Target = gpioa::RegisterBlock>;
+
+    /// IMPLEMENTATION DETAIL
+    fn enable(rcc: &Rcc);
+    /// IMPLEMENTATION DETAIL
+    fn reset(rcc: &Rcc);
+    /// IMPLEMENTATION DETAIL
+    fn configure_pins(gpio: &Self::GPIO, rcc: &Rcc);
+}
+
+unsafe impl GTIM for Tim15 {
     type GPIO = Gpioa;
+
+    fn enable(rcc: &Rcc) {
+        rcc.apb2enr.modify(|_, w| w.tim15en().enabled());
+    }
+
+    fn reset(rcc: &Rcc) {
+        rcc.apb2rstr.modify(|_, w| unsafe { w.tim15rst().bits(1) });
+        rcc.apb2rstr.modify(|_, w| unsafe { w.tim15rst().bits(0) });
+    }
+
+    fn configure_pins(gpio: &Gpioa, rcc: &Rcc) {
+        rcc.ahbenr.modify(|_, w| w.iopaen().enabled());
+
+        // CH1N = PA1, CH1 = PA2, CH2 = PA3
+        gpio.afrl.modify(|_, w| unsafe {
+            w.afrl1().bits(9).afrl2().bits(9).afrl3().bits(9)
+        });
+        gpio.moder.modify(|_, w| {
+            w.moder1()
+                .alternate()
+                .moder2()
+                .alternate()
+                .moder3()
+                .alternate()
+        });
+    }
+}
+
+unsafe impl GTIM for Tim16 {
+    type GPIO = Gpiob;
+
+    fn enable(rcc: &Rcc) {
+        rcc.apb2enr.modify(|_, w| w.tim16en().enabled());
+    }
+
+    fn reset(rcc: &Rcc) {
+        rcc.apb2rstr.modify(|_, w| unsafe { w.tim16rst().bits(1) });
+        rcc.apb2rstr.modify(|_, w| unsafe { w.tim16rst().bits(0) });
+    }
+
+    fn configure_pins(gpio: &Gpiob, rcc: &Rcc) {
+        rcc.ahbenr.modify(|_, w| w.iopben().enabled());
+
+        // CH1N = PB6, CH1 = PB8
+        gpio.afrl.modify(|_, w| unsafe { w.afrl6().bits(1) });
+        gpio.afrh.modify(|_, w| unsafe { w.afrh8().bits(1) });
+        gpio.moder
+            .modify(|_, w| w.moder6().alternate().moder8().alternate());
+    }
+}
+
+unsafe impl GTIM for Tim17 {
+    type GPIO = Gpiob;
+
+    fn enable(rcc: &Rcc) {
+        rcc.apb2enr.modify(|_, w| w.tim17en().enabled());
+    }
+
+    fn reset(rcc: &Rcc) {
+        rcc.apb2rstr.modify(|_, w| unsafe { w.tim17rst().bits(1) });
+        rcc.apb2rstr.modify(|_, w| unsafe { w.tim17rst().bits(0) });
+    }
+
+    fn configure_pins(gpio: &Gpiob, rcc: &Rcc) {
+        rcc.ahbenr.modify(|_, w| w.iopben().enabled());
+
+        // CH1 = PB5, CH1N = PB7
+        gpio.afrl
+            .modify(|_, w| unsafe { w.afrl5().bits(10).afrl7().bits(1) });
+        gpio.moder
+            .modify(|_, w| w.moder5().alternate().moder7().alternate());
+    }
 }
 
 /// `hal::Timer` implementation
@@ -52,142 +524,428 @@ impl<'a, T> Clone for Timer<'a, T> {
 
 impl<'a, T> Copy for Timer<'a, T> {}
 
-impl<'a> Timer<'a, Tim7> {
-    /// Initializes the timer with a periodic timeout of `frequency` Hz
-    ///
-    /// NOTE After initialization, the timer will be in the paused state.
-    pub fn init<P>(&self, period: P, rcc: &Rcc)
-    where
-        P: Into<::apb2::Ticks>,
-    {
-        self._init(period.into(), rcc)
-    }
+macro_rules! basic_timer {
+    ($TIM:ident, $timen:ident, $timrst:ident) => {
+        impl<'a> Timer<'a, $TIM> {
+            /// Initializes the timer with a periodic timeout of `period`
+            /// ticks
+            ///
+            /// NOTE After initialization, the timer will be in the paused
+            /// state.
+            pub fn init<P>(&self, period: P, rcc: &Rcc) -> Result<(), TimerError>
+            where
+                P: Into<::apb1::tim::Ticks>,
+            {
+                self._init(period.into(), rcc)
+            }
 
-    fn _init(&self, period: ::apb2::Ticks, rcc: &Rcc) {
-        let tim7 = self.0;
+            fn _init(
+                &self,
+                period: ::apb1::tim::Ticks,
+                rcc: &Rcc,
+            ) -> Result<(), TimerError> {
+                let tim = self.0;
 
-        // Enable TIM1
-        rcc.apb1enr.modify(|_, w| w.tim7en().enabled());
+                let (psc, arr) = psc_arr(period.0, 0xffff)?;
 
-        // Configure periodic update event
-        self._set_timeout(period);
+                // Enable and reset TIMx
+                rcc.apb1enr.modify(|_, w| w.$timen().enabled());
+                rcc.apb1rstr.modify(|_, w| unsafe { w.$timrst().bits(1) });
+                rcc.apb1rstr.modify(|_, w| unsafe { w.$timrst().bits(0) });
 
-        // Continuous mode
-        tim7.cr1.write(|w| w.opm().continuous());
+                // Configure periodic update event
+                self._set_psc_arr(psc, arr);
 
-        // Enable update event interrupt
-        tim7.dier.write(|w| unsafe { w.uie().bits(1) });
-        //tim7.dier.modify(|_, w| w.uie().set());
-    }
+                // Continuous mode
+                // urs: only counter overflows raise the update flag
+                tim.cr1.write(|w| unsafe { w.opm().continuous().urs().bits(1) });
+
+                // Load the prescaler
+                tim.egr.write(|w| unsafe { w.ug().bits(1) });
+
+                // Enable update event interrupt
+                tim.dier.write(|w| unsafe { w.uie().bits(1) });
+
+                Ok(())
+            }
+
+            /// Changes the timeout of the timer to `timeout` ticks
+            ///
+            /// Unlike `hal::Timer::set_timeout` this reports timeouts the
+            /// timer can't produce instead of clamping them.
+            pub fn set_timeout<T>(&self, timeout: T) -> Result<(), TimerError>
+            where
+                T: Into<::apb1::tim::Ticks>,
+            {
+                let (psc, arr) = psc_arr(timeout.into().0, 0xffff)?;
+                self._set_psc_arr(psc, arr);
+
+                Ok(())
+            }
+
+            /// Starts the timer in one-pulse mode
+            ///
+            /// The update flag gets raised once, `timeout` ticks from now,
+            /// and then the counter stops. `hal::Timer::resume` re-arms the
+            /// timer for another `timeout`; `init` goes back to periodic
+            /// mode.
+            pub fn start_once<T>(&self, timeout: T) -> Result<(), TimerError>
+            where
+                T: Into<::apb1::tim::Ticks>,
+            {
+                let tim = self.0;
+                let (psc, arr) = psc_arr(timeout.into().0, 0xffff)?;
+
+                tim.cr1.modify(|_, w| unsafe { w.cen().bits(0) });
+                self._set_psc_arr(psc, arr);
+
+                // opm: the counter stops at the next update event
+                tim.cr1.modify(|_, w| unsafe { w.opm().bits(1) });
+
+                // reload the prescaler and clear the counter
+                tim.egr.write(|w| unsafe { w.ug().bits(1) });
+                tim.sr.modify(|_, w| unsafe { w.uif().bits(0) });
 
-    fn _set_timeout(&self, timeout: ::apb2::Ticks) {
-        let period = timeout.0;
+                tim.cr1.modify(|_, w| unsafe { w.cen().bits(1) });
 
-        let psc = u16((period - 1) / (1 << 16)).unwrap();
-        self.0.psc.write(|w| w.psc().bits(psc));
+                Ok(())
+            }
 
-        let arr = u16(period / u32(psc + 1)).unwrap();
-        self.0.arr.write(|w| w.arr().bits(arr));
+            fn _set_psc_arr(&self, psc: u16, arr: u32) {
+                self.0.psc.write(|w| w.psc().bits(psc));
+                self.0.arr.write(|w| w.arr().bits(arr as u16));
+            }
+
+            /// Selects the event that drives the trigger output (TRGO)
+            ///
+            /// TRGO can be used to trigger DAC and ADC conversions.
+            pub fn set_trigger_output(&self, trgo: TriggerOutput) {
+                let mms = match trgo {
+                    TriggerOutput::Reset => 0b000,
+                    TriggerOutput::Enable => 0b001,
+                    TriggerOutput::Update => 0b010,
+                };
+
+                self.0.cr2.write(|w| unsafe { w.mms().bits(mms) });
+            }
+        }
+
+        impl<'a> hal::Timer for Timer<'a, $TIM> {
+            type Time = ::apb1::tim::Ticks;
+
+            fn get_timeout(&self) -> ::apb1::tim::Ticks {
+                ::apb1::tim::Ticks(period(
+                    self.0.psc.read().psc().bits(),
+                    u32(self.0.arr.read().arr().bits()),
+                ))
+            }
+
+            fn pause(&self) {
+                self.0.cr1.modify(|_, w| w.cen().disabled());
+            }
+
+            fn restart(&self) {
+                self.0.cnt.write(|w| unsafe { w.cnt().bits(0) });
+            }
+
+            fn resume(&self) {
+                self.0.cr1.modify(|_, w| w.cen().enabled());
+            }
+
+            fn set_timeout<T>(&self, timeout: T)
+            where
+                T: Into<::apb1::tim::Ticks>,
+            {
+                let (psc, arr) = psc_arr_saturating(timeout.into().0, 0xffff);
+                self._set_psc_arr(psc, arr);
+            }
+
+            fn wait(&self) -> nb::Result<(), !> {
+                if self.0.sr.read().uif().is_no_update() {
+                    Err(Error::WouldBlock)
+                } else {
+                    self.0.sr.modify(|_, w| w.uif().clear());
+                    Ok(())
+                }
+            }
+        }
     }
 }
 
-impl<'a> hal::Timer for Timer<'a, Tim7> {
-    type Time = ::apb2::Ticks;
+basic_timer!(Tim6, tim6en, tim6rst);
+basic_timer!(Tim7, tim7en, tim7rst);
 
-    fn get_timeout(&self) -> ::apb2::Ticks {
-        ::apb2::Ticks(
-            u32(self.0.psc.read().psc().bits() + 1) *
-                u32(self.0.arr.read().arr().bits()),
-        )
-    }
+macro_rules! apb2_timer {
+    ($TIM:ident, $timen:ident, $timrst:ident, $Rep:ty) => {
+        impl<'a> Timer<'a, $TIM> {
+            /// Initializes the timer with a periodic timeout of `period`
+            /// ticks
+            ///
+            /// NOTE After initialization, the timer will be in the paused
+            /// state.
+            pub fn init<P>(&self, period: P, rcc: &Rcc) -> Result<(), TimerError>
+            where
+                P: Into<::apb2::tim::Ticks>,
+            {
+                self._init(period.into(), rcc)
+            }
 
-    fn pause(&self) {
-        self.0.cr1.modify(|_, w| w.cen().disabled());
-    }
+            fn _init(
+                &self,
+                period: ::apb2::tim::Ticks,
+                rcc: &Rcc,
+            ) -> Result<(), TimerError> {
+                let tim = self.0;
 
-    fn restart(&self) {
-        self.0.cnt.write(|w| unsafe { w.cnt().bits(0) });
-    }
+                let (psc, arr) = psc_arr(period.0, 0xffff)?;
 
-    fn resume(&self) {
-        self.0.cr1.modify(|_, w| w.cen().enabled());
-    }
+                // Enable and reset TIMx
+                rcc.apb2enr.modify(|_, w| w.$timen().enabled());
+                rcc.apb2rstr.modify(|_, w| unsafe { w.$timrst().bits(1) });
+                rcc.apb2rstr.modify(|_, w| unsafe { w.$timrst().bits(0) });
 
-    fn set_timeout<T>(&self, timeout: T)
-    where
-        T: Into<::apb2::Ticks>,
-    {
-        self._set_timeout(timeout.into())
+                // Configure periodic update event
+                self._set_psc_arr(psc, arr);
+
+                // Continuous mode
+                // urs: only counter overflows raise the update flag
+                tim.cr1.write(|w| unsafe { w.opm().bits(0).urs().bits(1) });
+
+                // Load the prescaler
+                tim.egr.write(|w| unsafe { w.ug().bits(1) });
+
+                // Enable update event interrupt
+                tim.dier.write(|w| unsafe { w.uie().bits(1) });
+
+                Ok(())
+            }
+
+            /// Changes the timeout of the timer to `timeout` ticks
+            ///
+            /// Unlike `hal::Timer::set_timeout` this reports timeouts the
+            /// timer can't produce instead of clamping them.
+            pub fn set_timeout<T>(&self, timeout: T) -> Result<(), TimerError>
+            where
+                T: Into<::apb2::tim::Ticks>,
+            {
+                let (psc, arr) = psc_arr(timeout.into().0, 0xffff)?;
+                self._set_psc_arr(psc, arr);
+
+                Ok(())
+            }
+
+            /// Starts the timer in one-pulse mode
+            ///
+            /// The update flag gets raised once, `timeout` ticks from now,
+            /// and then the counter stops. `hal::Timer::resume` re-arms the
+            /// timer for another `timeout`; `init` goes back to periodic
+            /// mode.
+            pub fn start_once<T>(&self, timeout: T) -> Result<(), TimerError>
+            where
+                T: Into<::apb2::tim::Ticks>,
+            {
+                let tim = self.0;
+                let (psc, arr) = psc_arr(timeout.into().0, 0xffff)?;
+
+                tim.cr1.modify(|_, w| unsafe { w.cen().bits(0) });
+                self._set_psc_arr(psc, arr);
+
+                // opm: the counter stops at the next update event
+                tim.cr1.modify(|_, w| unsafe { w.opm().bits(1) });
+
+                // reload the prescaler and clear the counter
+                tim.egr.write(|w| unsafe { w.ug().bits(1) });
+                tim.sr.modify(|_, w| unsafe { w.uif().bits(0) });
+
+                tim.cr1.modify(|_, w| unsafe { w.cen().bits(1) });
+
+                Ok(())
+            }
+
+            fn _set_psc_arr(&self, psc: u16, arr: u32) {
+                self.0.psc.write(|w| unsafe { w.psc().bits(psc) });
+                self.0.arr.write(|w| unsafe { w.arr().bits(arr as u16) });
+            }
+
+            /// Sets the repetition counter
+            ///
+            /// The update event is only generated every `repetitions + 1`
+            /// counter overflows. Takes effect at the next update event.
+            pub fn set_repetition(&self, repetitions: $Rep) {
+                self.0.rcr.write(|w| unsafe { w.rep().bits(repetitions) });
+            }
+        }
+
+        impl<'a> hal::Timer for Timer<'a, $TIM> {
+            type Time = ::apb2::tim::Ticks;
+
+            fn get_timeout(&self) -> ::apb2::tim::Ticks {
+                ::apb2::tim::Ticks(period(
+                    self.0.psc.read().psc().bits(),
+                    u32(self.0.arr.read().arr().bits()),
+                ))
+            }
+
+            fn pause(&self) {
+                self.0.cr1.modify(|_, w| unsafe { w.cen().bits(0) });
+            }
+
+            fn restart(&self) {
+                self.0.cnt.write(|w| unsafe { w.cnt().bits(0) });
+            }
+
+            fn resume(&self) {
+                self.0.cr1.modify(|_, w| unsafe { w.cen().bits(1) });
+            }
+
+            fn set_timeout<T>(&self, timeout: T)
+            where
+                T: Into<::apb2::tim::Ticks>,
+            {
+                let (psc, arr) = psc_arr_saturating(timeout.into().0, 0xffff);
+                self._set_psc_arr(psc, arr);
+            }
+
+            fn wait(&self) -> nb::Result<(), !> {
+                if self.0.sr.read().uif().bits() == 0 {
+                    Err(Error::WouldBlock)
+                } else {
+                    self.0.sr.modify(|_, w| unsafe { w.uif().bits(0) });
+                    Ok(())
+                }
+            }
+        }
     }
+}
 
-    fn wait(&self) -> nb::Result<(), !> {
-        if self.0.sr.read().uif().is_no_update() {
-            Err(Error::WouldBlock)
-        } else {
-            self.0.sr.modify(|_, w| w.uif().clear());
-            Ok(())
+apb2_timer!(Tim1, tim1en, tim1rst, u16);
+apb2_timer!(Tim8, tim8en, tim8rst, u16);
+apb2_timer!(Tim15, tim15en, tim15rst, u8);
+apb2_timer!(Tim16, tim16en, tim16rst, u8);
+apb2_timer!(Tim17, tim17en, tim17rst, u8);
+
+macro_rules! trigger_output {
+    ($TIM:ident) => {
+        impl<'a> Timer<'a, $TIM> {
+            /// Selects the event that drives the trigger output (TRGO)
+            pub fn set_trigger_output(&self, trgo: TriggerOutput) {
+                let mms = match trgo {
+                    TriggerOutput::Reset => 0b000,
+                    TriggerOutput::Enable => 0b001,
+                    TriggerOutput::Update => 0b010,
+                };
+
+                self.0.cr2.modify(|_, w| unsafe { w.mms().bits(mms) });
+            }
         }
     }
 }
 
+trigger_output!(Tim1);
+trigger_output!(Tim8);
+trigger_output!(Tim15);
+
 impl<'a, T> Timer<'a, T>
 where
-    T: Any + TIM,
+    T: TIM,
 {
-    /// Initializes the timer with a periodic timeout of `frequency` Hz
+    /// Initializes the timer with a periodic timeout of `period` ticks
     ///
     /// NOTE After initialization, the timer will be in the paused state.
-    pub fn init<P>(&self, period: P, rcc: &Rcc)
+    pub fn init<P>(&self, period: P, rcc: &Rcc) -> Result<(), TimerError>
     where
-        P: Into<::apb1::Ticks>,
+        P: Into<::apb1::tim::Ticks>,
     {
         self.init_(period.into(), rcc)
     }
 
-    fn init_(&self, timeout: ::apb1::Ticks, rcc: &Rcc) {
+    fn init_(
+        &self,
+        timeout: ::apb1::tim::Ticks,
+        rcc: &Rcc,
+    ) -> Result<(), TimerError> {
         let tim2 = self.0;
 
-        // Enable TIMx
-        if tim2.get_type_id() == TypeId::of::<Tim2>() {
-            rcc.apb1enr.modify(|_, w| w.tim2en().enabled());
-        } else if tim2.get_type_id() == TypeId::of::<Tim3>() {
-            rcc.apb1enr.modify(|_, w| w.tim3en().enabled());
-        } else if tim2.get_type_id() == TypeId::of::<Tim4>() {
-            rcc.apb1enr.modify(|_, w| w.tim4en().enabled());
-        }
+        let (psc, arr) = psc_arr(timeout.0, T::ARR_MAX)?;
+
+        // Enable and reset TIMx
+        T::enable(rcc);
+        T::reset(rcc);
 
         // Configure periodic update event
-        self._set_timeout(timeout);
+        self._set_psc_arr(psc, arr);
 
         // Continuous mode
-        tim2.cr1.write(|w| unsafe { w.opm().bits(0) });
+        // urs: only counter overflows raise the update flag
+        tim2.cr1.write(|w| unsafe { w.opm().bits(0).urs().bits(1) });
+
+        // Load the prescaler
+        tim2.egr.write(|w| unsafe { w.ug().bits(1) });
 
         // Enable the update event interrupt
         tim2.dier.modify(|_, w| unsafe { w.uie().bits(1) });
+
+        Ok(())
     }
 
-    fn _set_timeout(&self, timeout: ::apb1::Ticks) {
-        let period = timeout.0;
+    /// Changes the timeout of the timer to `timeout` ticks
+    ///
+    /// Unlike `hal::Timer::set_timeout` this reports timeouts the timer
+    /// can't produce instead of clamping them.
+    pub fn set_timeout<TO>(&self, timeout: TO) -> Result<(), TimerError>
+    where
+        TO: Into<::apb1::tim::Ticks>,
+    {
+        let (psc, arr) = psc_arr(timeout.into().0, T::ARR_MAX)?;
+        self._set_psc_arr(psc, arr);
 
-        let psc = u16((period - 1) / (1 << 16)).unwrap();
-        self.0.psc.write(|w| unsafe { w.psc().bits(psc) });
+        Ok(())
+    }
+
+    /// Starts the timer in one-pulse mode
+    ///
+    /// The update flag gets raised once, `timeout` ticks from now, and then
+    /// the counter stops. `hal::Timer::resume` re-arms the timer for another
+    /// `timeout`; `init` goes back to periodic mode.
+    pub fn start_once<TO>(&self, timeout: TO) -> Result<(), TimerError>
+    where
+        TO: Into<::apb1::tim::Ticks>,
+    {
+        let tim = self.0;
+        let (psc, arr) = psc_arr(timeout.into().0, T::ARR_MAX)?;
+
+        tim.cr1.modify(|_, w| unsafe { w.cen().bits(0) });
+        self._set_psc_arr(psc, arr);
+
+        // opm: the counter stops at the next update event
+        tim.cr1.modify(|_, w| unsafe { w.opm().bits(1) });
+
+        // reload the prescaler and clear the counter
+        tim.egr.write(|w| unsafe { w.ug().bits(1) });
+        tim.sr.modify(|_, w| unsafe { w.uif().bits(0) });
+
+        tim.cr1.modify(|_, w| unsafe { w.cen().bits(1) });
+
+        Ok(())
+    }
 
-        let arr = period / u32(psc + 1);
+    fn _set_psc_arr(&self, psc: u16, arr: u32) {
+        self.0.psc.write(|w| unsafe { w.psc().bits(psc) });
         self.0.arr.write(|w| unsafe { w.bits(arr) });
     }
 }
 
 impl<'a, T> hal::Timer for Timer<'a, T>
 where
-    T: Any + TIM,
+    T: TIM,
 {
-    type Time = ::apb1::Ticks;
+    type Time = ::apb1::tim::Ticks;
 
-    fn get_timeout(&self) -> ::apb1::Ticks {
-        ::apb1::Ticks(
-            u32(self.0.psc.read().psc().bits() + 1) *
-                u32(self.0.arr.read().bits()),
-        )
+    fn get_timeout(&self) -> ::apb1::tim::Ticks {
+        ::apb1::tim::Ticks(period(
+            self.0.psc.read().psc().bits(),
+            self.0.arr.read().bits(),
+        ))
     }
 
     fn pause(&self) {
@@ -204,17 +962,108 @@ where
 
     fn set_timeout<TO>(&self, timeout: TO)
     where
-        TO: Into<::apb1::Ticks>,
+        TO: Into<::apb1::tim::Ticks>,
     {
-        self._set_timeout(timeout.into())
+        let (psc, arr) = psc_arr_saturating(timeout.into().0, T::ARR_MAX);
+        self._set_psc_arr(psc, arr);
     }
 
     fn wait(&self) -> nb::Result<(), !> {
         if self.0.sr.read().uif().bits() == 0 {
             Err(Error::WouldBlock)
         } else {
-            self.0.sr.modify(|_, w| unsafe { w.uif().bits(1) });
+            self.0.sr.modify(|_, w| unsafe { w.uif().bits(0) });
             Ok(())
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{period, psc_arr, psc_arr_saturating, TimerError};
+
+    const ARR16: u32 = 0xffff;
+    const ARR32: u32 = 0xffff_ffff;
+
+    #[test]
+    fn too_short() {
+        // frequencies above the timer clock round down to 0 ticks
+        assert_eq!(psc_arr(0, ARR16), Err(TimerError::PeriodTooShort));
+        assert_eq!(psc_arr(1, ARR16), Err(TimerError::PeriodTooShort));
+        assert_eq!(psc_arr(0, ARR32), Err(TimerError::PeriodTooShort));
+        assert_eq!(psc_arr_saturating(0, ARR16), (0, 1));
+        assert_eq!(psc_arr_saturating(1, ARR32), (0, 1));
+    }
+
+    #[test]
+    fn too_long() {
+        // a 16-bit prescaler and an 8-bit auto-reload reach 2^24 ticks
+        let max = 1 << 24;
+
+        assert_eq!(psc_arr(max, 0xff), Ok((0xffff, 0xff)));
+        assert_eq!(psc_arr(max + 1, 0xff), Err(TimerError::PeriodTooLong));
+        assert_eq!(
+            psc_arr_saturating(u32::max_value(), 0xff),
+            (0xffff, 0xff)
+        );
+    }
+
+    #[test]
+    fn exact() {
+        assert_eq!(psc_arr(2, ARR16), Ok((0, 1)));
+        assert_eq!(psc_arr(1_000, ARR16), Ok((0, 999)));
+        assert_eq!(psc_arr(1 << 16, ARR16), Ok((0, 0xffff)));
+        assert_eq!(psc_arr(4 << 16, ARR16), Ok((3, 0xffff)));
+        assert_eq!(psc_arr(100 << 16, ARR16), Ok((99, 0xffff)));
+        assert_eq!(psc_arr(8_000_000, ARR32), Ok((0, 7_999_999)));
+    }
+
+    #[test]
+    fn primes() {
+        // 65_537 and 1_000_003 are prime
+        assert_eq!(psc_arr(65_537, ARR16), Ok((1, 32_768)));
+        assert_eq!(psc_arr(65_537, ARR32), Ok((0, 65_536)));
+        assert_eq!(psc_arr(1_000_003, ARR16), Ok((52, 18_867)));
+        assert_eq!(psc_arr(1_000_003, ARR32), Ok((0, 1_000_002)));
+    }
+
+    #[test]
+    fn composite() {
+        // the smallest usable prescaler doesn't divide these periods but a
+        // larger one does
+        assert_eq!(psc_arr(65_541, ARR16), Ok((2, 21_846)));
+        assert_eq!(psc_arr(1_000_001, ARR16), Ok((100, 9_900)));
+        assert_eq!(psc_arr(8_000_000, ARR16), Ok((124, 63_999)));
+        assert_eq!(psc_arr(72_000_000, ARR16), Ok((1_124, 63_999)));
+    }
+
+    #[test]
+    fn error_bound() {
+        for &p in &[65_537, 1_000_003, 8_000_000, 72_000_000] {
+            let (psc, arr) = psc_arr(p, ARR16).unwrap();
+            let actual = u64::from(period(psc, arr));
+            let error = if actual > u64::from(p) {
+                actual - u64::from(p)
+            } else {
+                u64::from(p) - actual
+            };
+
+            assert!(error <= (u64::from(psc) + 1) / 2);
+        }
+    }
+
+    #[test]
+    fn max() {
+        // 0 Hz saturates to `u32::MAX` ticks
+        assert_eq!(psc_arr(u32::max_value(), ARR16), Ok((0xffff, 0xffff)));
+        assert_eq!(psc_arr(u32::max_value(), ARR32), Ok((0, 0xffff_fffe)));
+    }
+
+    #[test]
+    fn period_saturates() {
+        assert_eq!(period(0, 0), 1);
+        assert_eq!(period(124, 63_999), 8_000_000);
+        assert_eq!(period(0xffff, 0xffff), u32::max_value());
+        assert_eq!(period(1, 0xffff_ffff), u32::max_value());
+    }
+}
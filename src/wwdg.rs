@@ -0,0 +1,145 @@
+//! Window watchdog (WWDG)
+//!
+//! The WWDG is clocked by the APB1 clock and has a 7-bit down counter: the
+//! device is reset when the counter goes below `0x40` or when the watchdog
+//! is fed while the counter is still above the window value. The WWDG can
+//! raise an early wakeup interrupt one counter tick before the reset, for
+//! example to save state or to feed the watchdog from the interrupt handler.
+//!
+//! At 8 MHz, timeouts range from 512 us to 262 ms.
+
+use stm32f30x::{Rcc, Wwdg};
+
+/// Lowest value of the counter before the reset
+const T_MIN: u8 = 0x3f;
+/// Highest value of the counter
+const T_MAX: u8 = 0x7f;
+
+/// WWDG error
+#[derive(Debug)]
+pub enum Error {
+    /// The timeout is shorter than one counter tick
+    TimeoutTooShort,
+    /// The timeout is longer than the watchdog can count
+    TimeoutTooLong,
+    /// The window is shorter than one counter tick; the watchdog couldn't
+    /// be fed
+    WindowTooShort,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Computes the timer base (WDGTB), counter (T) and window (W) values for a
+/// timeout of `timeout` APB1 ticks and a window of `window` APB1 ticks
+pub fn wdgtb_t_w(timeout: u32, window: u32) -> Result<(u8, u8, u8), Error> {
+    // the counter is clocked by PCLK1 / 4096 / 2^WDGTB
+    let base = 4096;
+
+    if timeout < base {
+        return Err(Error::TimeoutTooShort);
+    }
+
+    for wdgtb in 0..4 {
+        let div = base << wdgtb;
+        let count = timeout / div;
+
+        if count <= u32::from(T_MAX - T_MIN) {
+            let wcount = window / div;
+
+            // W = T_MIN would only allow feeding the watchdog once the
+            // counter has already triggered the reset
+            if wcount == 0 {
+                return Err(Error::WindowTooShort);
+            }
+
+            let w = if wcount >= count {
+                T_MAX
+            } else {
+                T_MIN + wcount as u8
+            };
+
+            return Ok((wdgtb, T_MIN + count as u8, w));
+        }
+    }
+
+    Err(Error::TimeoutTooLong)
+}
+
+/// Window watchdog
+pub struct WindowWatchdog<'a> {
+    wwdg: &'a Wwdg,
+    t: u8,
+}
+
+impl<'a> WindowWatchdog<'a> {
+    /// Starts the watchdog with a timeout of `timeout` ticks
+    ///
+    /// The watchdog must be fed within the last `window` ticks of the
+    /// `timeout`; feeding it earlier resets the device as well. A `window`
+    /// as long as the `timeout` disables the window.
+    ///
+    /// NOTE the watchdog can't be stopped once started
+    pub fn start<T, W>(
+        wwdg: &'a Wwdg,
+        timeout: T,
+        window: W,
+        rcc: &Rcc,
+    ) -> Result<Self, Error>
+    where
+        T: Into<::apb1::Ticks>,
+        W: Into<::apb1::Ticks>,
+    {
+        let (wdgtb, t, win) = wdgtb_t_w(timeout.into().0, window.into().0)?;
+
+        rcc.apb1enr.modify(|_, w| w.wwdgen().enabled());
+
+        wwdg.cfr.modify(|_, w| unsafe { w.wdgtb().bits(wdgtb).w().bits(win) });
+
+        // wdga: start the watchdog
+        wwdg.cr.write(|w| unsafe { w.t().bits(t).wdga().bits(1) });
+
+        Ok(WindowWatchdog { wwdg: wwdg, t: t })
+    }
+
+    /// Feeds the watchdog, restarting the timeout
+    pub fn feed(&self) {
+        let t = self.t;
+
+        self.wwdg.cr.write(|w| unsafe { w.t().bits(t) });
+    }
+
+    /// Enables the early wakeup interrupt
+    ///
+    /// The interrupt fires when the counter reaches `0x40`, one counter
+    /// tick before the reset.
+    ///
+    /// NOTE the interrupt can only be disabled by a reset
+    pub fn listen(&self) {
+        self.wwdg.cfr.modify(|_, w| unsafe { w.ewi().bits(1) });
+    }
+
+    /// Checks whether the early wakeup flag is set
+    pub fn is_early_wakeup(&self) -> bool {
+        self.wwdg.sr.read().ewif().bits() == 1
+    }
+
+    /// Clears the early wakeup flag
+    pub fn clear_early_wakeup(&self) {
+        self.wwdg.sr.write(|w| unsafe { w.ewif().bits(0) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::wdgtb_t_w;
+
+    #[test]
+    fn edges() {
+        assert!(wdgtb_t_w(4095, 4095).is_err());
+        assert_eq!(wdgtb_t_w(4096, 4096).ok(), Some((0, 0x40, 0x7f)));
+        assert_eq!(wdgtb_t_w(64 * 4096, 4096).ok(), Some((0, 0x7f, 0x40)));
+        assert!(wdgtb_t_w(64 * 4096, 4095).is_err());
+        assert!(wdgtb_t_w(64 * 4096, 0).is_err());
+        assert!(wdgtb_t_w(u32::max_value(), u32::max_value()).is_err());
+    }
+}